@@ -0,0 +1,97 @@
+//! Looking up components of a batch of entities one by one against a single batched call.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use entity_data::{Archetype, EntityId, EntityStorage};
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+use std::hint::black_box;
+
+const N_ENTITIES: usize = 100_000;
+const N_LOOKUPS: usize = 1000;
+
+struct Position(f32);
+struct Velocity(#[allow(dead_code)] f32);
+
+#[derive(Archetype)]
+struct Ball {
+    pos: Position,
+    vel: Velocity,
+}
+
+#[derive(Archetype)]
+struct Wall {
+    pos: Position,
+}
+
+fn get_many(c: &mut Criterion) {
+    let mut storage = EntityStorage::new();
+    for i in 0..N_ENTITIES {
+        if i % 2 == 0 {
+            storage.add(Ball {
+                pos: Position(i as f32),
+                vel: Velocity(1.0),
+            });
+        } else {
+            storage.add(Wall {
+                pos: Position(i as f32),
+            });
+        }
+    }
+
+    // Sorted ids, as required by the mutable variants
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut entities: Vec<EntityId> = storage
+        .entities()
+        .iter()
+        .filter(|_| rng.gen_bool(N_LOOKUPS as f64 / N_ENTITIES as f64))
+        .collect();
+    entities.sort();
+    let ball_ids: Vec<_> = entities
+        .iter()
+        .filter(|e| storage.get::<Velocity>(e).is_some())
+        .map(|e| e.id)
+        .collect();
+
+    let mut group = c.benchmark_group("get_many");
+    {
+        let comps = storage
+            .get_archetype::<Ball>()
+            .unwrap()
+            .component::<Position>()
+            .unwrap();
+        group.bench_function("archetype_individual", |b| {
+            b.iter(|| {
+                let sum: f32 = ball_ids.iter().map(|&id| comps.get(id).unwrap().0).sum();
+                black_box(sum)
+            })
+        });
+        group.bench_function("archetype_batched", |b| {
+            b.iter(|| {
+                let sum: f32 = comps.get_many(&ball_ids).map(|pos| pos.unwrap().0).sum();
+                black_box(sum)
+            })
+        });
+    }
+
+    let access = storage.access();
+    let positions = access.component::<Position>();
+    group.bench_function("global_individual", |b| {
+        b.iter(|| {
+            let sum: f32 = entities.iter().map(|e| positions.get(e).unwrap().0).sum();
+            black_box(sum)
+        })
+    });
+    group.bench_function("global_batched", |b| {
+        b.iter(|| {
+            let sum: f32 = positions
+                .get_many(&entities)
+                .map(|pos| pos.unwrap().0)
+                .sum();
+            black_box(sum)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, get_many);
+criterion_main!(benches);
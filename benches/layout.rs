@@ -0,0 +1,69 @@
+//! Iteration over a single small component of archetypes with large states
+//! stored as whole states (`DataLayout::Aos`) and by components (`DataLayout::Soa`).
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use entity_data::{Archetype, EntityStorage};
+use std::hint::black_box;
+
+const N_ENTITIES: usize = 100_000;
+
+struct Position(f32);
+/// Makes the states large, it's never read.
+#[allow(dead_code)]
+struct Payload([u64; 16]);
+
+#[derive(Archetype)]
+struct AosBody {
+    pos: Position,
+    payload: Payload,
+}
+
+#[derive(Archetype)]
+#[archetype(layout = "soa")]
+struct SoaBody {
+    pos: Position,
+    payload: Payload,
+}
+
+fn single_component_iteration(c: &mut Criterion) {
+    let mut aos = EntityStorage::new();
+    let mut soa = EntityStorage::new();
+    for i in 0..N_ENTITIES {
+        aos.add(AosBody {
+            pos: Position(i as f32),
+            payload: Payload([0; 16]),
+        });
+        soa.add(SoaBody {
+            pos: Position(i as f32),
+            payload: Payload([0; 16]),
+        });
+    }
+
+    let mut group = c.benchmark_group("single_component_iteration");
+    group.bench_function("aos", |b| {
+        let comps = aos
+            .get_archetype::<AosBody>()
+            .unwrap()
+            .component::<Position>()
+            .unwrap();
+        b.iter(|| black_box(comps.iter().map(|pos| pos.0).sum::<f32>()))
+    });
+    group.bench_function("soa", |b| {
+        let comps = soa
+            .get_archetype::<SoaBody>()
+            .unwrap()
+            .component::<Position>()
+            .unwrap();
+        b.iter(|| black_box(comps.iter().map(|pos| pos.0).sum::<f32>()))
+    });
+    group.bench_function("aos_mut", |b| {
+        b.iter(|| aos.update_all::<Position, _>(|pos| pos.0 += 1.0))
+    });
+    group.bench_function("soa_mut", |b| {
+        b.iter(|| soa.update_all::<Position, _>(|pos| pos.0 += 1.0))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, single_component_iteration);
+criterion_main!(benches);
@@ -0,0 +1,58 @@
+//! Parallel [EntityStorage::par_for_each] against serial [EntityStorage::for_each_mut].
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use entity_data::{Archetype, EntityStorage};
+
+const N_ENTITIES: usize = 1_000_000;
+
+struct Position([f32; 3]);
+struct Velocity(#[allow(dead_code)] [f32; 3]);
+
+#[derive(Archetype)]
+struct Ball {
+    pos: Position,
+    vel: Velocity,
+}
+
+#[derive(Archetype)]
+struct Wall {
+    pos: Position,
+}
+
+fn integrate(pos: &mut Position) {
+    for x in &mut pos.0 {
+        *x = (*x + 0.1).sin();
+    }
+}
+
+fn par_for_each(c: &mut Criterion) {
+    let mut storage = EntityStorage::new();
+    for i in 0..N_ENTITIES {
+        let pos = Position([i as f32; 3]);
+        if i % 2 == 0 {
+            storage.add(Ball {
+                pos,
+                vel: Velocity([1.0; 3]),
+            });
+        } else {
+            storage.add(Wall { pos });
+        }
+    }
+    // Leave holes in the slots
+    let removed: Vec<_> = storage.entities().iter().step_by(7).collect();
+    for entity in &removed {
+        storage.remove(entity);
+    }
+
+    let mut group = c.benchmark_group("for_each");
+    group.bench_function("serial", |b| {
+        b.iter(|| storage.for_each_mut::<Position, _>(|_, pos| integrate(pos)))
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| storage.par_for_each::<Position, _>(|_, pos| integrate(pos)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, par_for_each);
+criterion_main!(benches);
@@ -0,0 +1,64 @@
+//! Cached queries against recomputing the matching archetypes on every call.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use entity_data::{DynamicArchetypeBuilder, EntityStorage};
+use std::any::TypeId;
+use std::hint::black_box;
+
+const N_COMPONENTS: usize = 10;
+
+struct Comp<const N: usize>(#[allow(dead_code)] u32);
+
+fn register(builder: DynamicArchetypeBuilder, index: usize) -> DynamicArchetypeBuilder {
+    match index {
+        0 => builder.register::<Comp<0>>(),
+        1 => builder.register::<Comp<1>>(),
+        2 => builder.register::<Comp<2>>(),
+        3 => builder.register::<Comp<3>>(),
+        4 => builder.register::<Comp<4>>(),
+        5 => builder.register::<Comp<5>>(),
+        6 => builder.register::<Comp<6>>(),
+        7 => builder.register::<Comp<7>>(),
+        8 => builder.register::<Comp<8>>(),
+        9 => builder.register::<Comp<9>>(),
+        _ => unreachable!(),
+    }
+}
+
+/// Creates a storage with an archetype for every non-empty subset of the components.
+fn storage_with_many_archetypes() -> EntityStorage {
+    let mut storage = EntityStorage::new();
+
+    for subset in 1..(1_u32 << N_COMPONENTS) {
+        let meta = (0..N_COMPONENTS)
+            .filter(|i| subset & (1 << i) != 0)
+            .fold(DynamicArchetypeBuilder::new(), register)
+            .build();
+        let state = vec![0_u8; meta.size];
+        // Safety: the components are plain integers, so zeroed bytes form a valid state.
+        unsafe { storage.add_raw(meta, state.as_ptr()) };
+    }
+    storage
+}
+
+fn query(c: &mut Criterion) {
+    let storage = storage_with_many_archetypes();
+    let required = [
+        TypeId::of::<Comp<0>>(),
+        TypeId::of::<Comp<3>>(),
+        TypeId::of::<Comp<5>>(),
+    ];
+    let excluded = [TypeId::of::<Comp<9>>()];
+
+    let mut group = c.benchmark_group("query");
+    group.bench_function("uncached", |b| {
+        b.iter(|| black_box(storage.archetypes_matching(&required, &excluded)))
+    });
+    group.bench_function("cached", |b| {
+        b.iter(|| black_box(storage.query_filtered(&required, &excluded)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, query);
+criterion_main!(benches);
@@ -0,0 +1,65 @@
+//! Resolving an archetype id by the archetype `TypeId` against the shortcut through
+//! the archetypes containing a component, which applies when only one archetype has it.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use entity_data::{Archetype, EntityStorage};
+use std::any::TypeId;
+use std::hint::black_box;
+
+struct Position(#[allow(dead_code)] f32);
+struct Velocity(#[allow(dead_code)] f32);
+struct Health(#[allow(dead_code)] u32);
+
+#[derive(Archetype)]
+struct Ball {
+    pos: Position,
+    vel: Velocity,
+}
+
+#[derive(Archetype)]
+struct Wall {
+    pos: Position,
+}
+
+#[derive(Archetype)]
+struct Player {
+    pos: Position,
+    health: Health,
+}
+
+fn type_id_to_archetype_id(c: &mut Criterion) {
+    let mut storage = EntityStorage::new();
+    storage.add(Ball {
+        pos: Position(0.0),
+        vel: Velocity(0.0),
+    });
+    storage.add(Wall { pos: Position(0.0) });
+    storage.add(Player {
+        pos: Position(0.0),
+        health: Health(100),
+    });
+
+    let ball = TypeId::of::<Ball>();
+    // Only `Ball` has `Velocity`
+    let velocity = TypeId::of::<Velocity>();
+
+    let mut group = c.benchmark_group("type_id_to_archetype_id");
+    group.bench_function("by_archetype_type", |b| {
+        b.iter(|| black_box(storage.type_id_to_archetype_id(black_box(&ball))))
+    });
+    group.bench_function("by_single_component", |b| {
+        b.iter(|| {
+            let mut arch_ids = storage.archetypes_with_component_id(black_box(&velocity));
+            black_box((arch_ids.len() == 1).then(|| arch_ids.next().unwrap()))
+        })
+    });
+
+    let access = storage.access();
+    group.bench_function("system_access", |b| {
+        b.iter(|| black_box(access.type_id_to_archetype_id(black_box(&ball))))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, type_id_to_archetype_id);
+criterion_main!(benches);
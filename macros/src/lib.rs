@@ -1,27 +1,144 @@
 use proc_macro2::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, DeriveInput};
+use syn::{parse_macro_input, parse_quote, DeriveInput};
 
 /// Implements archetype capabilities for `struct`.
-#[proc_macro_derive(Archetype)]
+///
+/// A single-field tuple struct can be marked with `#[archetype(deref)]` to additionally
+/// implement `Deref` and `DerefMut` to its component.
+///
+/// `#[archetype(layout = "soa")]` makes the storage keep each component in a separate buffer
+/// (see `DataLayout`).
+///
+/// `#[cfg(...)]`, `#[allow(...)]` and doc attributes of the struct are applied to the generated impls.
+/// Fields can be conditionally compiled with `#[cfg(...)]`, excluded fields are not components.
+///
+/// An archetype must have at least one component. Archetypes with more than 32 components
+/// must be marked with `#[archetype(large)]` because their metadata is heap-allocated.
+///
+/// `#[archetype(eq)]` implements `EqArchetype` for a `PartialEq` struct,
+/// so that storages with its states can be compared by `EntityStorage::content_eq`.
+///
+/// `#[archetype(clone)]` implements `CloneArchetype` for a `Clone` struct,
+/// so that its entities can be duplicated by `EntityStorage::clone_entity`.
+#[proc_macro_derive(Archetype, attributes(archetype))]
 pub fn derive_archetype_fn(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let main_crate = quote!(::entity_data);
 
     let DeriveInput {
+        attrs,
         ident,
         data,
         generics,
         ..
     } = parse_macro_input!(input as DeriveInput);
 
+    let mut deref = false;
+    let mut eq = false;
+    let mut clone = false;
+    let mut large = false;
+    let mut data_layout = quote!(Aos);
+    for attr in attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("archetype"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("deref") {
+                deref = true;
+                Ok(())
+            } else if meta.path.is_ident("eq") {
+                eq = true;
+                Ok(())
+            } else if meta.path.is_ident("clone") {
+                clone = true;
+                Ok(())
+            } else if meta.path.is_ident("large") {
+                large = true;
+                Ok(())
+            } else if meta.path.is_ident("layout") {
+                let value: syn::LitStr = meta.value()?.parse()?;
+                data_layout = match value.value().as_str() {
+                    "aos" => quote!(Aos),
+                    "soa" => quote!(Soa),
+                    _ => return Err(meta.error("expected `\"aos\"` or `\"soa\"`")),
+                };
+                Ok(())
+            } else {
+                Err(meta.error("unsupported archetype attribute"))
+            }
+        })
+        .unwrap_or_else(|err| panic!("{}", err));
+    }
+
     let where_clause = &generics.where_clause;
 
+    // Outer attributes of the struct which also apply to the generated impls.
+    let forwarded_attrs: Vec<_> = attrs
+        .iter()
+        .filter(|attr| {
+            let path = attr.path();
+            path.is_ident("cfg") || path.is_ident("allow") || path.is_ident("doc")
+        })
+        .collect();
+
+    let (eq_fn, eq_impl) = if eq {
+        (
+            quote!(Some(<Self as #main_crate::EqArchetype>::eq_fn)),
+            quote!(#(#forwarded_attrs)* impl #generics #main_crate::EqArchetype for #ident #generics #where_clause {}),
+        )
+    } else {
+        (quote!(None), quote!())
+    };
+
+    let (clone_fn, clone_impl) = if clone {
+        (
+            quote!(Some(<Self as #main_crate::CloneArchetype>::clone_fn)),
+            quote!(#(#forwarded_attrs)* impl #generics #main_crate::CloneArchetype for #ident #generics #where_clause {}),
+        )
+    } else {
+        (quote!(None), quote!())
+    };
+
     let fields = if let syn::Data::Struct(data) = data {
         data.fields
     } else {
         panic!("Not a structure!");
     };
 
+    let deref_impl = if deref {
+        let field_ty = match &fields {
+            syn::Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => &unnamed.unnamed[0].ty,
+            _ => panic!("#[archetype(deref)] requires a tuple struct with a single field!"),
+        };
+
+        let mut generics = generics.clone();
+        generics
+            .make_where_clause()
+            .predicates
+            .push(parse_quote!(#field_ty: Sized));
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+        quote! {
+            #(#forwarded_attrs)*
+            impl #impl_generics ::std::ops::Deref for #ident #ty_generics #where_clause {
+                type Target = #field_ty;
+
+                fn deref(&self) -> &Self::Target {
+                    &self.0
+                }
+            }
+
+            #(#forwarded_attrs)*
+            impl #impl_generics ::std::ops::DerefMut for #ident #ty_generics #where_clause {
+                fn deref_mut(&mut self) -> &mut Self::Target {
+                    &mut self.0
+                }
+            }
+        }
+    } else {
+        TokenStream::new()
+    };
+
     let types: Vec<_> = fields
         .iter()
         .map(|field| {
@@ -33,7 +150,8 @@ pub fn derive_archetype_fn(input: proc_macro::TokenStream) -> proc_macro::TokenS
         .collect();
 
     let field_impls: Vec<_> = fields
-        .into_iter().enumerate()
+        .into_iter()
+        .enumerate()
         .map(|(i, field)| {
             let field_ty = field.ty;
 
@@ -51,11 +169,15 @@ pub fn derive_archetype_fn(input: proc_macro::TokenStream) -> proc_macro::TokenS
             quote! {
                 #main_crate::private::ComponentInfo {
                     type_id: ::std::any::TypeId::of::<#field_ty>(),
+                    type_name: ::std::any::type_name::<#field_ty>(),
                     range: {
                         let offset = #offset;
                         let size = ::std::mem::size_of::<#field_ty>();
                         offset..(offset + size)
                     },
+                    align: ::std::mem::align_of::<#field_ty>(),
+                    needs_drop: ::std::mem::needs_drop::<#field_ty>(),
+                    drop_fn: |p: *mut u8| unsafe { ::std::ptr::drop_in_place(p as *mut #field_ty) },
                 },
             }
         })
@@ -63,7 +185,25 @@ pub fn derive_archetype_fn(input: proc_macro::TokenStream) -> proc_macro::TokenS
 
     let fields_len = field_impls.len();
 
-    // Check component uniqueness
+    if fields_len == 0 {
+        return syn::Error::new_spanned(&ident, "Archetype must have at least one component")
+            .to_compile_error()
+            .into();
+    }
+    // Component infos of larger archetypes don't fit into `private::MAX_INFOS_ON_STACK`
+    if fields_len > 32 && !large {
+        let msg = format!(
+            "Archetype has {} components, but component infos of up to 32 components are stored \
+             without heap allocations. Add `#[archetype(large)]` to allow it.",
+            fields_len
+        );
+        return syn::Error::new_spanned(&ident, msg)
+            .to_compile_error()
+            .into();
+    }
+
+    // Check component uniqueness. This only catches types spelled identically,
+    // e.g. aliases are detected by the storage when the archetype is created.
     {
         let mut field_names: Vec<_> = types.iter().map(|v| v.to_string()).collect();
         field_names.sort();
@@ -77,28 +217,39 @@ pub fn derive_archetype_fn(input: proc_macro::TokenStream) -> proc_macro::TokenS
         }
     }
 
-    let mut field_types = TokenStream::new();
-    field_types.extend(types.into_iter());
-
     let mut fields = TokenStream::new();
-    fields.extend(field_impls.into_iter());
+    fields.extend(field_impls);
 
     quote! {
+        #(#forwarded_attrs)*
         impl #generics #main_crate::StaticArchetype for #ident #generics #where_clause {
             const N_COMPONENTS: usize = #fields_len;
 
+            fn component_type_ids() -> #main_crate::private::SmallVec<[::std::any::TypeId; #main_crate::private::MAX_INFOS_ON_STACK]> {
+                #main_crate::private::smallvec![#(#types)*]
+            }
+
             fn metadata() -> #main_crate::private::ArchetypeMetadata {
                 #main_crate::private::ArchetypeMetadata {
                     type_id: ::std::any::TypeId::of::<Self>(),
-                    component_type_ids: || #main_crate::private::smallvec![#field_types],
-                    component_infos: || #main_crate::private::smallvec![#fields],
+                    type_name: ::std::any::type_name::<Self>(),
+                    component_infos: #main_crate::private::smallvec![#fields],
                     size: ::std::mem::size_of::<Self>(),
+                    align: ::std::mem::align_of::<Self>(),
+                    data_layout: #main_crate::private::DataLayout::#data_layout,
                     needs_drop: ::std::mem::needs_drop::<Self>(),
-                    drop_fn: |p: *mut u8| unsafe { ::std::ptr::drop_in_place(p as *mut Self) },
+                    drop_fn: Some(|p: *mut u8| unsafe { ::std::ptr::drop_in_place(p as *mut Self) }),
+                    as_any_fn: Some(|p: *mut u8| p as *mut Self as *mut dyn ::std::any::Any),
+                    into_any_fn: Some(|p: *mut u8| unsafe {
+                        #main_crate::StaticArchetype::into_any((p as *mut Self).read())
+                    }),
+                    eq_fn: #eq_fn,
+                    clone_fn: #clone_fn,
                 }
             }
         }
 
+        #(#forwarded_attrs)*
         impl #generics #main_crate::ArchetypeState for #ident #generics #where_clause {
             fn ty(&self) -> ::std::any::TypeId {
                 ::std::any::TypeId::of::<Self>()
@@ -109,7 +260,7 @@ pub fn derive_archetype_fn(input: proc_macro::TokenStream) -> proc_macro::TokenS
             }
 
             fn forget(self) {
-                ::std::mem::forget(self);
+                let _ = ::std::mem::ManuallyDrop::new(self);
             }
 
             fn metadata(&self) -> #main_crate::private::ArchetypeMetadata {
@@ -120,6 +271,10 @@ pub fn derive_archetype_fn(input: proc_macro::TokenStream) -> proc_macro::TokenS
                 #fields_len
             }
 
+            fn component_ids(&self) -> #main_crate::private::SmallVec<[::std::any::TypeId; #main_crate::private::MAX_INFOS_ON_STACK]> {
+                <Self as #main_crate::StaticArchetype>::component_type_ids()
+            }
+
             fn as_any(&self) -> &dyn ::std::any::Any {
                 self
             }
@@ -128,6 +283,10 @@ pub fn derive_archetype_fn(input: proc_macro::TokenStream) -> proc_macro::TokenS
                 self
             }
         }
+
+        #deref_impl
+        #eq_impl
+        #clone_impl
     }
     .into()
 }
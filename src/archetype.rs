@@ -1,18 +1,26 @@
+pub mod buffer;
 pub mod component;
+pub mod dynamic;
 pub mod entities;
 
+use crate::archetype::buffer::AlignedBuffer;
 use crate::archetype::component::{ComponentStorageMut, ComponentStorageRef, UnsafeVec};
 use crate::entity::ArchEntityId;
+use crate::entity_storage::StorageOptions;
 use crate::private::{ArchetypeMetadata, ComponentInfo};
-use crate::{ArchetypeState, HashMap, StaticArchetype};
+use crate::{AnyState, ArchetypeState, DynamicArchetypeBuilder, HashMap, StaticArchetype};
 use component::Component;
-use entities::ArchetypeEntities;
-use std::any::TypeId;
+use entities::{ArchetypeEntities, EntitiesIter};
+use std::any::{Any, TypeId};
+use std::cmp::Ordering;
 use std::hash::{Hash, Hasher};
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
 use std::slice;
 
-#[derive(Clone, Eq)]
-pub(crate) struct ArchetypeLayout {
+/// A set of component types of an archetype.
+#[derive(Debug, Clone, Eq)]
+pub struct ArchetypeLayout {
     sorted_type_ids: Vec<TypeId>,
     hash_val: u64,
 }
@@ -20,16 +28,85 @@ pub(crate) struct ArchetypeLayout {
 impl ArchetypeLayout {
     pub fn new(mut type_ids: Vec<TypeId>) -> ArchetypeLayout {
         type_ids.sort_unstable();
+        Self::from_sorted(type_ids)
+    }
 
+    fn from_sorted(sorted_type_ids: Vec<TypeId>) -> ArchetypeLayout {
         let mut hasher = ahash::AHasher::default();
-        type_ids.hash(&mut hasher);
+        sorted_type_ids.hash(&mut hasher);
         let hash_val = hasher.finish();
 
         ArchetypeLayout {
-            sorted_type_ids: type_ids,
+            sorted_type_ids,
             hash_val,
         }
     }
+
+    /// Returns the component types sorted by `TypeId`.
+    pub fn type_ids(&self) -> &[TypeId] {
+        &self.sorted_type_ids
+    }
+
+    /// Returns `true` if every component of `self` is also in `other`.
+    pub fn is_subset_of(&self, other: &ArchetypeLayout) -> bool {
+        let mut other_ids = other.sorted_type_ids.iter();
+        self.sorted_type_ids
+            .iter()
+            .all(|ty| other_ids.by_ref().any(|other_ty| other_ty == ty))
+    }
+
+    /// Returns `true` if every component of `other` is also in `self`.
+    pub fn is_superset_of(&self, other: &ArchetypeLayout) -> bool {
+        other.is_subset_of(self)
+    }
+
+    /// Returns a layout of components that are both in `self` and `other`.
+    pub fn intersection(&self, other: &ArchetypeLayout) -> ArchetypeLayout {
+        let (a, b) = (&self.sorted_type_ids, &other.sorted_type_ids);
+        let mut result = Vec::with_capacity(a.len().min(b.len()));
+        let (mut i, mut j) = (0, 0);
+
+        while i < a.len() && j < b.len() {
+            match a[i].cmp(&b[j]) {
+                Ordering::Less => i += 1,
+                Ordering::Greater => j += 1,
+                Ordering::Equal => {
+                    result.push(a[i]);
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        Self::from_sorted(result)
+    }
+
+    /// Returns a layout of components that are in `self` or `other`.
+    pub fn union(&self, other: &ArchetypeLayout) -> ArchetypeLayout {
+        let (a, b) = (&self.sorted_type_ids, &other.sorted_type_ids);
+        let mut result = Vec::with_capacity(a.len() + b.len());
+        let (mut i, mut j) = (0, 0);
+
+        while i < a.len() && j < b.len() {
+            match a[i].cmp(&b[j]) {
+                Ordering::Less => {
+                    result.push(a[i]);
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    result.push(b[j]);
+                    j += 1;
+                }
+                Ordering::Equal => {
+                    result.push(a[i]);
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        result.extend_from_slice(&a[i..]);
+        result.extend_from_slice(&b[j..]);
+        Self::from_sorted(result)
+    }
 }
 
 impl PartialEq for ArchetypeLayout {
@@ -44,64 +121,244 @@ impl Hash for ArchetypeLayout {
     }
 }
 
+/// Describes how states of an archetype are laid out in memory.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum DataLayout {
+    /// Array of structures: every state is stored contiguously as a whole.
+    #[default]
+    Aos,
+    /// Structure of arrays: every component is stored in a separate densely packed buffer.
+    /// Iterating over a single component touches only the memory of that component,
+    /// but the states can't be accessed as a whole (e.g. via [ArchetypeStorage::get_state]).
+    Soa,
+}
+
 /// A collection of entities with unique combination of components.
 /// An archetype can hold a maximum of 2^32-1 entities.
 pub struct ArchetypeStorage {
     pub(crate) meta: ArchetypeMetadata,
+    /// States of the entities if the layout is [DataLayout::Aos], empty otherwise.
     pub(crate) data: UnsafeVec,
+    /// Buffers of the components (in the order of `components`) if the layout is [DataLayout::Soa].
+    pub(crate) columns: Vec<UnsafeVec>,
     pub(crate) components: Vec<ComponentInfo>,
     pub(crate) components_by_types: HashMap<TypeId, usize>,
     pub(crate) entities: ArchetypeEntities,
+    /// Types of the states the entities were added as, indexed by entity id. The archetype
+    /// is shared by all states with the same set of components, so they may differ from the type
+    /// of the archetype. Slots past the end are of the type of the archetype.
+    pub(crate) state_types: Vec<TypeId>,
 }
 
 impl ArchetypeStorage {
-    pub(crate) fn new(meta: ArchetypeMetadata) -> Self {
-        let component_infos = meta.component_infos();
+    pub(crate) fn new(meta: ArchetypeMetadata, options: &StorageOptions) -> Self {
+        // States are stored back to back, so each of them is aligned only if the size is a multiple of the alignment.
+        assert!(
+            meta.align.is_power_of_two() && meta.size.is_multiple_of(meta.align),
+            "Archetype `{}` has invalid alignment {} for size {}",
+            meta.type_name,
+            meta.align,
+            meta.size
+        );
+
+        // Components of dynamic archetypes may be packed without padding. The storage hands out
+        // references to them, so they are laid out aligned here. States described by the packed
+        // metadata are copied component by component (see `add_entity_from`).
+        let meta = if meta.is_dynamic() {
+            meta.component_infos()
+                .iter()
+                .fold(
+                    DynamicArchetypeBuilder::new()
+                        .aligned()
+                        .data_layout(meta.data_layout),
+                    |builder, info| builder.register_info(info),
+                )
+                .build()
+        } else {
+            meta
+        };
+
+        let component_infos = meta.component_infos().to_vec();
         let components_by_types: HashMap<_, _> = component_infos
             .iter()
             .enumerate()
             .map(|(i, info)| (info.type_id, i))
             .collect();
 
+        let columns = match meta.data_layout {
+            DataLayout::Aos => Vec::new(),
+            DataLayout::Soa => component_infos
+                .iter()
+                .map(|info| UnsafeVec::new(info.align))
+                .collect(),
+        };
+
         ArchetypeStorage {
+            data: UnsafeVec::new(meta.align),
             meta,
-            data: Default::default(),
-            components: component_infos.to_vec(),
+            columns,
+            components: component_infos,
             components_by_types,
-            entities: Default::default(),
+            entities: ArchetypeEntities::new(options.reuse_ids),
+            state_types: Vec::new(),
         }
     }
 
-    fn allocate_slot(&mut self) -> ArchEntityId {
-        self.entities.allocate_slot()
+    /// Allocates a slot for a new entity added as a state of type `state_type`.
+    fn allocate_slot(&mut self, state_type: TypeId) -> ArchEntityId {
+        let entity_id = self.entities.allocate_slot();
+        let index = entity_id as usize;
+
+        if index < self.state_types.len() {
+            self.state_types[index] = state_type;
+        } else if state_type != self.meta.type_id {
+            self.state_types.resize(index + 1, self.meta.type_id);
+            self.state_types[index] = state_type;
+        }
+        entity_id
+    }
+
+    /// Returns `true` if the entity was added as a state of type `S`.
+    pub(crate) fn is_state_of<S: StaticArchetype>(&self, entity_id: ArchEntityId) -> bool {
+        let state_type = self
+            .state_types
+            .get(entity_id as usize)
+            .unwrap_or(&self.meta.type_id);
+        *state_type == TypeId::of::<S>()
     }
 
     /// Safety: `S` must be of the same component layout as the archetype.
     pub(crate) unsafe fn add_entity_raw(&mut self, state_ptr: *const u8) -> u32 {
-        let entity_id = self.allocate_slot();
+        let entity_id = self.allocate_slot(self.meta.type_id);
+
+        if self.meta.size == 0 {
+            // Zero-sized states have no data, slots are tracked by `entities` only
+            return entity_id;
+        }
+        if self.meta.data_layout == DataLayout::Soa {
+            self.write_components(entity_id, &self.meta.clone(), state_ptr);
+            return entity_id;
+        }
 
         let data = self.data.get_mut();
         let offset = entity_id as usize * self.meta.size;
 
-        if offset == data.len() {
+        if offset >= data.len() {
+            // Slots past the end may be skipped if ids are not reused
+            data.resize(offset, 0);
             let slice = slice::from_raw_parts(state_ptr, self.meta.size);
-            data.extend(slice);
-        } else if offset < data.len() {
+            data.extend_from_slice(slice);
+        } else {
             let dst_ptr = data.as_mut_ptr().add(offset);
             dst_ptr.copy_from_nonoverlapping(state_ptr, self.meta.size);
-        } else {
-            unreachable!()
         }
 
         entity_id
     }
 
+    /// Creates a new entity from a state which has the same set of components as the archetype
+    /// but possibly different component layout described by `src_meta`.
+    ///
+    /// Safety: `state_ptr` must point to a valid state of `src_meta` archetype,
+    /// `src_meta` must contain the same set of components as the archetype.
+    pub(crate) unsafe fn add_entity_from(
+        &mut self,
+        src_meta: &ArchetypeMetadata,
+        state_ptr: *const u8,
+    ) -> u32 {
+        if !src_meta.is_dynamic() && src_meta.type_id == self.meta.type_id {
+            return self.add_entity_raw(state_ptr);
+        }
+
+        let entity_id = self.allocate_slot(src_meta.type_id);
+
+        if self.meta.size == 0 {
+            return entity_id;
+        }
+
+        // Copy components one by one as they are laid out differently
+        self.write_components(entity_id, src_meta, state_ptr);
+        entity_id
+    }
+
+    /// Copies components of the state at `state_ptr` described by `src_meta` into the slot `entity_id`.
+    ///
+    /// Safety: `state_ptr` must point to a valid state of `src_meta` archetype,
+    /// `src_meta` must contain the same set of components as the archetype.
+    unsafe fn write_components(
+        &mut self,
+        entity_id: ArchEntityId,
+        src_meta: &ArchetypeMetadata,
+        state_ptr: *const u8,
+    ) {
+        self.ensure_slot(entity_id);
+
+        for src_info in src_meta.component_infos() {
+            let index = self.components_by_types[&src_info.type_id];
+            self.component_ptr_unchecked(index, entity_id)
+                .copy_from_nonoverlapping(state_ptr.add(src_info.range.start), src_info.size());
+        }
+    }
+
+    /// Makes the data buffers large enough to hold the slot `entity_id`.
+    fn ensure_slot(&mut self, entity_id: ArchEntityId) {
+        let n_slots = entity_id as usize + 1;
+        match self.meta.data_layout {
+            DataLayout::Aos => {
+                let data = self.data.get_mut();
+                if data.len() < n_slots * self.meta.size {
+                    data.resize(n_slots * self.meta.size, 0);
+                }
+            }
+            DataLayout::Soa => {
+                for (column, info) in self.columns.iter_mut().zip(&self.components) {
+                    let column = column.get_mut();
+                    if column.len() < n_slots * info.size() {
+                        column.resize(n_slots * info.size(), 0);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the buffer containing the component at `index`, the distance in bytes between
+    /// the components of consecutive slots and the offset of the component within this distance.
+    fn component_location(&self, index: usize) -> (&UnsafeVec, usize, usize) {
+        let info = &self.components[index];
+        match self.meta.data_layout {
+            DataLayout::Aos => (&self.data, self.meta.size, info.range.start),
+            DataLayout::Soa => (&self.columns[index], info.size(), 0),
+        }
+    }
+
+    /// Returns a pointer to the component at `index` of the slot `entity_id`.
+    ///
+    /// Safety: the slot must be within the data buffers.
+    pub(crate) unsafe fn component_ptr_unchecked(
+        &self,
+        index: usize,
+        entity_id: ArchEntityId,
+    ) -> *mut u8 {
+        let (buffer, step, offset) = self.component_location(index);
+        let buffer = &mut *buffer.get();
+        if step == 0 {
+            // Zero-sized components don't occupy any memory
+            return buffer.as_mut_ptr();
+        }
+        buffer.as_mut_ptr().add(step * entity_id as usize + offset)
+    }
+
     /// Creates a new entity and returns its identifier.
-    pub fn add_entity<S>(&mut self, state: S) -> u32
+    pub(crate) fn add_entity<S>(&mut self, state: S) -> u32
     where
         S: ArchetypeState,
     {
-        let entity_id = unsafe { self.add_entity_raw(state.as_ptr()) };
+        let entity_id = if state.ty() == self.meta.type_id {
+            unsafe { self.add_entity_raw(state.as_ptr()) }
+        } else {
+            // The state has the same set of components but its type differs from the archetype's one.
+            unsafe { self.add_entity_from(&state.metadata(), state.as_ptr()) }
+        };
         state.forget();
         entity_id
     }
@@ -111,34 +368,128 @@ impl ArchetypeStorage {
         self.entities.contains(entity_id)
     }
 
+    /// Returns the set of component types of the archetype.
+    pub fn layout(&self) -> ArchetypeLayout {
+        ArchetypeLayout::new(self.components.iter().map(|info| info.type_id).collect())
+    }
+
+    /// Returns `true` if the archetype contains the component of type `type_id`.
+    pub fn has_component_id(&self, type_id: &TypeId) -> bool {
+        self.components_by_types.contains_key(type_id)
+    }
+
     #[inline]
-    pub fn component<C: Component>(&self) -> Option<ComponentStorageRef<C>> {
-        let id = *self.components_by_types.get(&TypeId::of::<C>())?;
-        let info = self.components.get(id)?;
+    pub fn component<C: Component>(&self) -> Option<ComponentStorageRef<'_, C>> {
+        let index = *self.components_by_types.get(&TypeId::of::<C>())?;
+        let (data, step, offset) = self.component_location(index);
 
         Some(ComponentStorageRef {
             entities: &self.entities,
-            step: self.meta.size,
-            info,
-            data: &self.data,
+            step,
+            offset,
+            data,
             _ty: Default::default(),
         })
     }
 
     #[inline]
-    pub fn component_mut<C: Component>(&mut self) -> Option<ComponentStorageMut<C>> {
-        let id = *self.components_by_types.get(&TypeId::of::<C>())?;
-        let info = self.components.get_mut(id)?;
+    pub fn component_mut<C: Component>(&mut self) -> Option<ComponentStorageMut<'_, C>> {
+        let index = *self.components_by_types.get(&TypeId::of::<C>())?;
+        let (_, step, offset) = self.component_location(index);
+        let data = match self.meta.data_layout {
+            DataLayout::Aos => &mut self.data,
+            DataLayout::Soa => &mut self.columns[index],
+        };
 
         Some(ComponentStorageMut {
             entities: &self.entities,
-            step: self.meta.size,
-            info,
-            data: &mut self.data,
+            step,
+            offset,
+            data,
             _ty: Default::default(),
         })
     }
 
+    /// Returns an iterator over components `A` and `B` of every entity along with its id.
+    /// Returns `None` if the archetype doesn't have either of the components.
+    pub fn iter_zip<A: Component, B: Component>(
+        &self,
+    ) -> Option<impl Iterator<Item = (ArchEntityId, &A, &B)> + '_> {
+        let a = self.component::<A>()?;
+        let b = self.component::<B>()?;
+        // Safety: the entities exist.
+        Some(
+            self.entities
+                .iter()
+                .map(move |id| unsafe { (id, a.get_unchecked(id), b.get_unchecked(id)) }),
+        )
+    }
+
+    /// Returns an iterator over components `A` and mutable components `B` of every entity
+    /// along with its id. Returns `None` if the archetype doesn't have either of the components.
+    /// Panics if `A` and `B` are the same type.
+    ///
+    /// # Example
+    /// ```
+    /// use entity_data::{Archetype, EntityStorage};
+    ///
+    /// struct Position(f32);
+    /// struct Velocity(f32);
+    ///
+    /// #[derive(Archetype)]
+    /// struct Ball {
+    ///     pos: Position,
+    ///     vel: Velocity,
+    /// }
+    ///
+    /// let mut storage = EntityStorage::new();
+    /// let ball = storage.add(Ball { pos: Position(1.0), vel: Velocity(2.0) });
+    ///
+    /// let dt = 0.5;
+    /// let arch = storage.get_archetype_mut::<Ball>().unwrap();
+    /// for (_, vel, pos) in arch.iter_zip_mut::<Velocity, Position>().unwrap() {
+    ///     pos.0 += vel.0 * dt;
+    /// }
+    ///
+    /// assert_eq!(storage.get::<Position>(&ball).unwrap().0, 2.0);
+    /// ```
+    pub fn iter_zip_mut<A: Component, B: Component>(
+        &mut self,
+    ) -> Option<impl Iterator<Item = (ArchEntityId, &A, &mut B)> + '_> {
+        assert_ne!(
+            TypeId::of::<A>(),
+            TypeId::of::<B>(),
+            "Components must be distinct"
+        );
+        let a = self.component::<A>()?;
+        let b = self.component::<B>()?;
+        // Safety: `self` is borrowed mutably, the entities exist and every entity is visited once.
+        // The components are distinct, so `&A` and `&mut B` don't overlap.
+        Some(
+            self.entities
+                .iter()
+                .map(move |id| unsafe { (id, a.get_unchecked(id), b.get_mut_unsafe(id)) }),
+        )
+    }
+
+    /// Returns all components `C` of the archetype as a slice indexed by entity id.
+    ///
+    /// Returns `None` if:
+    /// * the archetype doesn't have the component `C`;
+    /// * the archetype has other components besides `C` and is stored as whole states
+    ///   ([DataLayout::Aos]), so the components are interleaved with other data;
+    /// * there are freed slots below the highest occupied one (holes left by removed entities).
+    ///   Adding new entities fills the holes if slots are reused.
+    pub fn as_slice<C: Component>(&self) -> Option<&[C]> {
+        self.component::<C>()?.as_slice()
+    }
+
+    /// Returns all components `C` of the archetype as a mutable slice indexed by entity id.
+    /// Returns `None` in the same cases as [as_slice](Self::as_slice).
+    pub fn as_mut_slice<C: Component>(&mut self) -> Option<&mut [C]> {
+        self.component_mut::<C>()?.into_mut_slice()
+    }
+
     /// Returns a reference to the component `C` of the specified entity id.
     pub fn get<C: Component>(&self, entity_id: ArchEntityId) -> Option<&C> {
         let component = self.component::<C>()?;
@@ -147,16 +498,44 @@ impl ArchetypeStorage {
 
     /// Returns a mutable reference to the component `C` of the specified entity id.
     pub fn get_mut<C: Component>(&mut self, entity_id: ArchEntityId) -> Option<&mut C> {
-        let mut component = self.component_mut::<C>()?;
-        component.get_mut(entity_id)
+        self.component_mut::<C>()?.into_mut(entity_id)
+    }
+
+    /// Returns references to the components `C` of the specified entities.
+    /// An element is `None` if the respective entity doesn't exist or the archetype doesn't have the component.
+    pub fn get_many<C: Component, const N: usize>(
+        &self,
+        entity_ids: [ArchEntityId; N],
+    ) -> [Option<&C>; N] {
+        let component = self.component::<C>();
+        entity_ids.map(|id| component.and_then(|comp| comp.get(id)))
+    }
+
+    /// Returns mutable references to the components `C` of the specified entities.
+    /// An element is `None` if the respective entity doesn't exist or the archetype doesn't have the component.
+    /// Panics if the entity ids are not distinct.
+    pub fn get_many_mut<C: Component, const N: usize>(
+        &mut self,
+        entity_ids: [ArchEntityId; N],
+    ) -> [Option<&mut C>; N] {
+        for (i, id) in entity_ids.iter().enumerate() {
+            assert!(!entity_ids[..i].contains(id), "Entity ids must be distinct");
+        }
+        let component = self.component::<C>();
+        entity_ids.map(|id| {
+            let comp = component?;
+            if !comp.contains(id) {
+                return None;
+            }
+            // Safety: the entity exists and the ids are distinct, so the references don't alias.
+            unsafe { Some(comp.get_mut_unsafe(id)) }
+        })
     }
 
     /// Returns a reference to the state at `entity_id`.
     /// Panics if `TypeId` of `S` != `self.ty()`.
     pub fn get_state<S: StaticArchetype>(&self, entity_id: ArchEntityId) -> Option<&S> {
-        if self.meta.type_id != TypeId::of::<S>() {
-            panic!("invalid type");
-        }
+        self.check_state_type::<S>();
         if !self.entities.contains(entity_id) {
             return None;
         }
@@ -169,9 +548,7 @@ impl ArchetypeStorage {
     /// Returns a mutable reference to the state at `entity_id`.
     /// Panics if `TypeId` of `S` != `self.ty()`.
     pub fn get_state_mut<S: StaticArchetype>(&mut self, entity_id: ArchEntityId) -> Option<&mut S> {
-        if self.meta.type_id != TypeId::of::<S>() {
-            panic!("invalid type");
-        }
+        self.check_state_type::<S>();
         if !self.entities.contains(entity_id) {
             return None;
         }
@@ -181,25 +558,349 @@ impl ArchetypeStorage {
         }
     }
 
-    /// Returns a pointer to the entity object. `entity_id` must be valid.
-    unsafe fn get_ptr(&self, entity_id: ArchEntityId) -> *mut u8 {
+    /// Returns an iterator over all states of the archetype along with their entity ids.
+    /// Panics if `TypeId` of `S` != `self.ty()`.
+    pub fn iter_states<S: StaticArchetype>(&self) -> StatesIter<'_, S> {
+        self.check_state_type::<S>();
+        StatesIter {
+            arch: self,
+            entities_iter: self.entities.iter(),
+            _ty: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over mutable references to all states of the archetype
+    /// along with their entity ids. Panics if `TypeId` of `S` != `self.ty()`.
+    pub fn iter_states_mut<S: StaticArchetype>(&mut self) -> StatesIterMut<'_, S> {
+        self.check_state_type::<S>();
+        StatesIterMut {
+            arch: self,
+            entities_iter: self.entities.iter(),
+            _ty: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over bytes of all states of the archetype along with their entity ids
+    /// in ascending order of ids. Every byte slice is of the size of a state.
+    ///
+    /// Panics if the states are stored by components ([DataLayout::Soa]).
+    pub fn iter_all_components(&self) -> impl Iterator<Item = (ArchEntityId, &[u8])> + '_ {
+        if self.meta.data_layout == DataLayout::Soa {
+            panic!(
+                "states of the archetype `{}` are stored by components and can't be accessed as a whole",
+                self.meta.type_name,
+            );
+        }
+        self.entities.iter().map(move |entity_id| {
+            // Safety: the entity exists, so the state lies within the data buffer.
+            let bytes = unsafe { slice::from_raw_parts(self.get_ptr(entity_id), self.meta.size) };
+            (entity_id, bytes)
+        })
+    }
+
+    /// Returns bytes of the component of type `ty` of the specified entity.
+    /// Returns `None` if the entity doesn't exist or doesn't have the component.
+    pub fn component_bytes(&self, entity_id: ArchEntityId, ty: TypeId) -> Option<&[u8]> {
+        let ptr = self.component_ptr(ty, entity_id)?;
+        let size = self.component_size(ty)?;
+        // Safety: the component is initialized and lies within the data buffer.
+        unsafe { Some(slice::from_raw_parts(ptr, size)) }
+    }
+
+    /// Returns mutable bytes of the component of type `ty` of the specified entity.
+    /// Returns `None` if the entity doesn't exist or doesn't have the component.
+    ///
+    /// The caller is responsible for writing bytes that form a valid value of the component type.
+    /// The component is not dropped before being overwritten.
+    pub fn component_bytes_mut(
+        &mut self,
+        entity_id: ArchEntityId,
+        ty: TypeId,
+    ) -> Option<&mut [u8]> {
+        let size = self.component_size(ty)?;
+        let ptr = self.component_ptr_mut(ty, entity_id)?;
+        // Safety: the component is initialized and lies within the data buffer.
+        unsafe { Some(slice::from_raw_parts_mut(ptr, size)) }
+    }
+
+    /// Returns a reference to the state at `entity_id` as `dyn Any`.
+    /// Returns `None` if the entity doesn't exist, the archetype is dynamic
+    /// or its states are stored by components ([DataLayout::Soa]).
+    pub fn get_state_dyn(&self, entity_id: ArchEntityId) -> Option<&dyn Any> {
+        let as_any_fn = self.meta.as_any_fn?;
+        if !self.entities.contains(entity_id) || self.meta.data_layout == DataLayout::Soa {
+            return None;
+        }
+        unsafe { Some(&*as_any_fn(self.get_ptr(entity_id))) }
+    }
+
+    /// Returns a mutable reference to the state at `entity_id` as `dyn Any`.
+    /// Returns `None` if the entity doesn't exist, the archetype is dynamic
+    /// or its states are stored by components ([DataLayout::Soa]).
+    pub fn get_state_dyn_mut(&mut self, entity_id: ArchEntityId) -> Option<&mut dyn Any> {
+        let as_any_fn = self.meta.as_any_fn?;
+        if !self.entities.contains(entity_id) || self.meta.data_layout == DataLayout::Soa {
+            return None;
+        }
+        unsafe { Some(&mut *as_any_fn(self.get_ptr(entity_id))) }
+    }
+
+    /// Panics if `S` is not the type of the archetype or the states are not stored as a whole.
+    pub(crate) fn check_state_type<S: StaticArchetype>(&self) {
+        if self.meta.type_id != TypeId::of::<S>() {
+            panic!(
+                "invalid state type: expected `{}`, but the archetype is `{}`",
+                std::any::type_name::<S>(),
+                self.meta.type_name,
+            );
+        }
+        if self.meta.data_layout == DataLayout::Soa {
+            panic!(
+                "states of the archetype `{}` are stored by components and can't be accessed as a whole",
+                self.meta.type_name,
+            );
+        }
+    }
+
+    /// Panics if states of `S` can't be accessed in place, i.e. `S` is laid out differently
+    /// from the states of the archetype or the states are not stored as a whole.
+    /// Unlike [check_state_type](Self::check_state_type), `S` may be any of the types
+    /// sharing the archetype.
+    pub(crate) fn check_state_layout<S: StaticArchetype>(&self) {
+        if self.meta.type_id != TypeId::of::<S>() {
+            let meta = <S as StaticArchetype>::metadata();
+            let same_layout = meta.size == self.meta.size
+                && meta.align == self.meta.align
+                && meta.component_infos().len() == self.components.len()
+                && meta.component_infos().iter().all(|info| {
+                    self.component_info_by_id(&info.type_id)
+                        .is_some_and(|own| own.range == info.range)
+                });
+            if !same_layout {
+                panic!(
+                    "invalid state type: expected `{}`, but the archetype is `{}`",
+                    std::any::type_name::<S>(),
+                    self.meta.type_name,
+                );
+            }
+        }
+        if self.meta.data_layout == DataLayout::Soa {
+            panic!(
+                "states of the archetype `{}` are stored by components and can't be accessed as a whole",
+                self.meta.type_name,
+            );
+        }
+    }
+
+    /// Returns a pointer to the state of the entity if it was added as a state of type `S`.
+    /// Panics if states of `S` can't be accessed in place, see [check_state_layout](Self::check_state_layout).
+    pub(crate) fn state_of_ptr<S: StaticArchetype>(
+        &self,
+        entity_id: ArchEntityId,
+    ) -> Option<*mut S> {
+        self.check_state_layout::<S>();
+        if !self.entities.contains(entity_id) || !self.is_state_of::<S>(entity_id) {
+            return None;
+        }
+        // Safety: the entity exists and the states are stored as a whole.
+        unsafe { Some(self.get_ptr(entity_id) as *mut S) }
+    }
+
+    /// Returns an iterator over ids of the entities added as states of type `S`.
+    pub(crate) fn entities_of<S: StaticArchetype>(
+        &self,
+    ) -> impl Iterator<Item = ArchEntityId> + '_ {
+        self.entities
+            .iter()
+            .filter(|&entity_id| self.is_state_of::<S>(entity_id))
+    }
+
+    fn component_info_by_id(&self, ty: &TypeId) -> Option<&ComponentInfo> {
+        let id = *self.components_by_types.get(ty)?;
+        self.components.get(id)
+    }
+
+    /// Returns the size of the component of type `ty` in bytes.
+    pub fn component_size(&self, ty: TypeId) -> Option<usize> {
+        self.component_info_by_id(&ty).map(|info| info.range.len())
+    }
+
+    /// Returns a pointer to the component of type `ty` of the specified entity.
+    /// Returns `None` if the entity doesn't exist or doesn't have the component.
+    ///
+    /// The pointer is valid for reads of [component_size](Self::component_size) bytes
+    /// until the archetype is modified. The caller is responsible for casting the pointer
+    /// to the correct type.
+    pub fn component_ptr(&self, ty: TypeId, entity_id: ArchEntityId) -> Option<*const u8> {
+        if !self.entities.contains(entity_id) {
+            return None;
+        }
+        let index = *self.components_by_types.get(&ty)?;
+        // Safety: the entity exists, so the component is within the data buffer.
+        unsafe { Some(self.component_ptr_unchecked(index, entity_id) as *const u8) }
+    }
+
+    /// Returns a mutable pointer to the component of type `ty` of the specified entity.
+    /// Returns `None` if the entity doesn't exist or doesn't have the component.
+    ///
+    /// The pointer is valid for reads and writes of [component_size](Self::component_size) bytes
+    /// until the archetype is modified. The caller is responsible for casting the pointer
+    /// to the correct type.
+    pub fn component_ptr_mut(&mut self, ty: TypeId, entity_id: ArchEntityId) -> Option<*mut u8> {
+        if !self.entities.contains(entity_id) {
+            return None;
+        }
+        let index = *self.components_by_types.get(&ty)?;
+        // Safety: the entity exists, so the component is within the data buffer.
+        unsafe { Some(self.component_ptr_unchecked(index, entity_id)) }
+    }
+
+    /// Returns a pointer to the component of type `type_id` of the specified entity
+    /// for reflection-based access, e.g. from scripting engines.
+    /// Returns `None` if the entity doesn't exist or doesn't have the component.
+    ///
+    /// # Safety
+    /// The pointer must be read only as the component of type `type_id`, i.e. at most
+    /// [component_size](Self::component_size) bytes, and only until the archetype is modified.
+    pub unsafe fn get_component_ptr_by_id(
+        &self,
+        entity_id: ArchEntityId,
+        type_id: TypeId,
+    ) -> Option<*const u8> {
+        self.component_ptr(type_id, entity_id)
+    }
+
+    /// Returns a mutable pointer to the component of type `type_id` of the specified entity
+    /// for reflection-based access, e.g. from scripting engines.
+    /// Returns `None` if the entity doesn't exist or doesn't have the component.
+    ///
+    /// # Safety
+    /// The pointer must be accessed only as the component of type `type_id`, i.e. at most
+    /// [component_size](Self::component_size) bytes, and only until the archetype is modified.
+    /// Written bytes must form a valid value of the component type. The old value
+    /// is not dropped if it's overwritten.
+    pub unsafe fn get_component_mut_ptr_by_id(
+        &mut self,
+        entity_id: ArchEntityId,
+        type_id: TypeId,
+    ) -> Option<*mut u8> {
+        self.component_ptr_mut(type_id, entity_id)
+    }
+
+    /// Returns a pointer to the entity object. `entity_id` must be valid
+    /// and the layout must be [DataLayout::Aos].
+    pub(crate) unsafe fn get_ptr(&self, entity_id: ArchEntityId) -> *mut u8 {
+        debug_assert_eq!(self.meta.data_layout, DataLayout::Aos);
         let data = unsafe { &mut *self.data.get() };
+        if self.meta.size == 0 {
+            // The buffer of a zero-sized archetype is always empty
+            return data.as_mut_ptr();
+        }
         let offset = self.meta.size * entity_id as usize;
         unsafe { data.as_mut_ptr().add(offset) }
     }
 
     /// Removes an entity from the archetype. Returns `true` if the entity was present in the archetype.
     pub(crate) fn remove(&mut self, entity_id: ArchEntityId) -> bool {
-        let was_present = self.entities.free(entity_id);
+        if !self.entities.contains(entity_id) {
+            return false;
+        }
+        self.entities.free(entity_id);
+
+        if self.meta.needs_drop {
+            unsafe { self.drop_entity(entity_id) };
+        }
+        true
+    }
 
-        if was_present && self.meta.needs_drop {
-            unsafe {
-                let ptr = self.get_ptr(entity_id);
-                (self.meta.drop_fn)(ptr);
+    /// Frees the slot `entity_id` without dropping its components
+    /// and passes a pointer to the state to `f`, which must move the state out.
+    /// Returns `None` if the entity doesn't exist.
+    pub(crate) fn take_with<R>(
+        &mut self,
+        entity_id: ArchEntityId,
+        f: impl FnOnce(*mut u8) -> R,
+    ) -> Option<R> {
+        if !self.entities.contains(entity_id) {
+            return None;
+        }
+        self.entities.free(entity_id);
+        Some(unsafe { self.with_state_ptr(entity_id, f) })
+    }
+
+    /// Passes a pointer to the state at `entity_id` to `f`. For [DataLayout::Soa],
+    /// the pointer is to a bitwise copy of the components, which must not be dropped.
+    ///
+    /// Safety: the slot must contain valid components.
+    unsafe fn with_state_ptr<R>(&self, entity_id: ArchEntityId, f: impl FnOnce(*mut u8) -> R) -> R {
+        match self.meta.data_layout {
+            DataLayout::Aos => f(self.get_ptr(entity_id)),
+            DataLayout::Soa => {
+                // Gather the components into a single state
+                let mut state = AlignedBuffer::new(self.meta.align);
+                state.resize(self.meta.size, 0);
+                for (index, info) in self.components.iter().enumerate() {
+                    self.component_ptr_unchecked(index, entity_id)
+                        .copy_to_nonoverlapping(
+                            state.as_mut_ptr().add(info.range.start),
+                            info.range.len(),
+                        );
+                }
+                f(state.as_mut_ptr())
             }
         }
+    }
 
-        was_present
+    /// Returns `true` if both archetypes are of the same type and contain
+    /// the same entities with equal states. See [EntityStorage::content_eq](crate::EntityStorage::content_eq).
+    pub(crate) fn content_eq(&self, other: &ArchetypeStorage) -> bool {
+        if self.meta.type_id != other.meta.type_id
+            || self.layout() != other.layout()
+            || !self.entities.iter().eq(other.entities.iter())
+        {
+            return false;
+        }
+        if self.entities.count() == 0 {
+            return true;
+        }
+        let Some(eq_fn) = self.meta.eq_fn else {
+            panic!(
+                "archetype `{}` doesn't implement `EqArchetype`",
+                self.meta.type_name
+            );
+        };
+        self.entities.iter().all(|entity_id| unsafe {
+            self.with_state_ptr(entity_id, |a| {
+                other.with_state_ptr(entity_id, |b| eq_fn(a, b))
+            })
+        })
+    }
+
+    /// Returns a clone of the state at `entity_id`. Returns `None` if the entity doesn't exist
+    /// or the archetype doesn't implement [CloneArchetype](crate::CloneArchetype).
+    pub(crate) fn clone_state(&self, entity_id: ArchEntityId) -> Option<AnyState> {
+        let clone_fn = self.meta.clone_fn?;
+        if !self.entities.contains(entity_id) {
+            return None;
+        }
+        // Safety: the entity exists, `clone_fn` only reads the state.
+        unsafe { Some(self.with_state_ptr(entity_id, |ptr| clone_fn(ptr))) }
+    }
+
+    /// Drops the components of the slot `entity_id`.
+    ///
+    /// Safety: the slot must contain valid components, which must not be used afterwards.
+    unsafe fn drop_entity(&self, entity_id: ArchEntityId) {
+        match self.meta.data_layout {
+            DataLayout::Aos => self.meta.drop_state(self.get_ptr(entity_id)),
+            DataLayout::Soa => {
+                for (index, info) in self.components.iter().enumerate() {
+                    if info.needs_drop {
+                        (info.drop_fn)(self.component_ptr_unchecked(index, entity_id));
+                    }
+                }
+            }
+        }
     }
 
     /// Returns iterator of archetype constituent components.
@@ -216,6 +917,55 @@ impl ArchetypeStorage {
     pub fn ty(&self) -> &TypeId {
         &self.meta.type_id
     }
+
+    /// Returns the type name of a single state in the archetype.
+    pub fn type_name(&self) -> &'static str {
+        self.meta.type_name
+    }
+
+    /// Returns the size of a single state in bytes.
+    pub fn entity_size(&self) -> usize {
+        self.meta.size
+    }
+
+    /// Returns the layout of the states in memory.
+    pub fn data_layout(&self) -> DataLayout {
+        self.meta.data_layout
+    }
+
+    fn buffers(&self) -> impl Iterator<Item = &AlignedBuffer> {
+        // Safety: the buffers are not mutated while `self` is borrowed.
+        unsafe { std::iter::once(&*self.data.get()).chain(self.columns.iter().map(|c| &*c.get())) }
+    }
+
+    /// Returns the number of bytes used by the entity data buffers.
+    pub fn data_len(&self) -> usize {
+        self.buffers().map(|buffer| buffer.len()).sum()
+    }
+
+    /// Returns the number of bytes allocated for the entity data buffers.
+    pub fn data_capacity(&self) -> usize {
+        self.buffers().map(|buffer| buffer.capacity()).sum()
+    }
+
+    /// Reserves capacity for at least `additional` more entities.
+    pub fn reserve(&mut self, additional: usize) {
+        let n_slots = self.entities.allocated_slots() + additional;
+        match self.meta.data_layout {
+            DataLayout::Aos => {
+                let data = self.data.get_mut();
+                let required_len = n_slots * self.meta.size;
+                data.reserve(required_len.saturating_sub(data.len()));
+            }
+            DataLayout::Soa => {
+                for (column, info) in self.columns.iter_mut().zip(&self.components) {
+                    let column = column.get_mut();
+                    let required_len = n_slots * info.size();
+                    column.reserve(required_len.saturating_sub(column.len()));
+                }
+            }
+        }
+    }
 }
 
 impl Drop for ArchetypeStorage {
@@ -224,12 +974,66 @@ impl Drop for ArchetypeStorage {
             return;
         }
         for entity_id in self.entities.iter() {
-            unsafe {
-                let ptr = self.get_ptr(entity_id);
-                (self.meta.drop_fn)(ptr);
-            }
+            unsafe { self.drop_entity(entity_id) };
         }
     }
 }
 
+// Safety: `UnsafeVec` is `!Sync` because of its `UnsafeCell`. Through `&self` the component data
+// is only read, except for the borrow-checked accesses of systems (see `SystemAccess`) and
+// parallel iterators, which never hand out aliasing mutable references. Components are `Sync`.
+// `Send` is implemented automatically since all the fields are `Send`.
 unsafe impl Sync for ArchetypeStorage {}
+
+/// An iterator over states of an archetype, see [ArchetypeStorage::iter_states].
+pub struct StatesIter<'a, S> {
+    arch: &'a ArchetypeStorage,
+    entities_iter: EntitiesIter<'a>,
+    _ty: PhantomData<S>,
+}
+
+impl<'a, S: StaticArchetype> Iterator for StatesIter<'a, S> {
+    type Item = (ArchEntityId, &'a S);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = self.entities_iter.next()?;
+        // Safety: the type of the states is checked in `iter_states` and the entity exists.
+        let state = unsafe { &*(self.arch.get_ptr(id) as *const S) };
+        Some((id, state))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entities_iter.size_hint()
+    }
+}
+
+impl<S: StaticArchetype> ExactSizeIterator for StatesIter<'_, S> {}
+
+impl<S: StaticArchetype> FusedIterator for StatesIter<'_, S> {}
+
+/// A mutable iterator over states of an archetype, see [ArchetypeStorage::iter_states_mut].
+pub struct StatesIterMut<'a, S> {
+    arch: &'a ArchetypeStorage,
+    entities_iter: EntitiesIter<'a>,
+    _ty: PhantomData<&'a mut S>,
+}
+
+impl<'a, S: StaticArchetype> Iterator for StatesIterMut<'a, S> {
+    type Item = (ArchEntityId, &'a mut S);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = self.entities_iter.next()?;
+        // Safety: the type of the states is checked in `iter_states_mut`, the entity exists,
+        // the archetype is borrowed mutably and every entity is visited once.
+        let state = unsafe { &mut *(self.arch.get_ptr(id) as *mut S) };
+        Some((id, state))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entities_iter.size_hint()
+    }
+}
+
+impl<S: StaticArchetype> ExactSizeIterator for StatesIterMut<'_, S> {}
+
+impl<S: StaticArchetype> FusedIterator for StatesIterMut<'_, S> {}
@@ -0,0 +1,123 @@
+use std::alloc::{self, Layout};
+use std::ptr::{self, NonNull};
+use std::slice;
+
+/// A growable byte buffer whose allocation is aligned to the specified alignment.
+/// Unlike `Vec<u8>`, which only guarantees 1-byte alignment, this allows
+/// accessing properly aligned values stored in the buffer by reference.
+pub struct AlignedBuffer {
+    ptr: NonNull<u8>,
+    len: usize,
+    cap: usize,
+    align: usize,
+}
+
+// Safety: the buffer owns its allocation and is accessed only through `&self`/`&mut self` like `Vec<u8>`.
+unsafe impl Send for AlignedBuffer {}
+unsafe impl Sync for AlignedBuffer {}
+
+impl AlignedBuffer {
+    const MIN_CAPACITY: usize = 64;
+
+    /// Creates an empty buffer. `align` must be a power of two.
+    pub fn new(align: usize) -> Self {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+        AlignedBuffer {
+            ptr: NonNull::new(ptr::without_provenance_mut(align)).unwrap(),
+            len: 0,
+            cap: 0,
+            align,
+        }
+    }
+
+    /// Returns the alignment of the buffer.
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.ptr.as_ptr()
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        // Safety: the first `len` bytes are initialized.
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    /// Reserves capacity for at least `additional` more bytes.
+    pub fn reserve(&mut self, additional: usize) {
+        let required = self.len.checked_add(additional).expect("capacity overflow");
+        if required <= self.cap {
+            return;
+        }
+        let new_cap = required.max(self.cap * 2).max(Self::MIN_CAPACITY);
+        let new_layout = Layout::from_size_align(new_cap, self.align).expect("capacity overflow");
+
+        let new_ptr = unsafe {
+            if self.cap == 0 {
+                alloc::alloc(new_layout)
+            } else {
+                let old_layout = Layout::from_size_align_unchecked(self.cap, self.align);
+                alloc::realloc(self.ptr.as_ptr(), old_layout, new_cap)
+            }
+        };
+
+        self.ptr = NonNull::new(new_ptr).unwrap_or_else(|| alloc::handle_alloc_error(new_layout));
+        self.cap = new_cap;
+    }
+
+    /// Resizes the buffer to `new_len` bytes, filling new bytes with `value`.
+    pub fn resize(&mut self, new_len: usize, value: u8) {
+        if new_len > self.len {
+            self.reserve(new_len - self.len);
+            unsafe {
+                self.ptr
+                    .as_ptr()
+                    .add(self.len)
+                    .write_bytes(value, new_len - self.len);
+            }
+        }
+        self.len = new_len;
+    }
+
+    /// Appends bytes to the end of the buffer.
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.reserve(bytes.len());
+        unsafe {
+            self.ptr
+                .as_ptr()
+                .add(self.len)
+                .copy_from_nonoverlapping(bytes.as_ptr(), bytes.len());
+        }
+        self.len += bytes.len();
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        if self.cap != 0 {
+            unsafe {
+                alloc::dealloc(
+                    self.ptr.as_ptr(),
+                    Layout::from_size_align_unchecked(self.cap, self.align),
+                );
+            }
+        }
+    }
+}
@@ -1,18 +1,26 @@
+use crate::archetype::buffer::AlignedBuffer;
 use crate::archetype::entities::{ArchetypeEntities, EntitiesIter};
 use crate::entity::ArchEntityId;
-use crate::private::ComponentInfo;
+use std::any::TypeId;
 use std::borrow::Borrow;
 use std::cell::UnsafeCell;
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::{mem, slice};
 
-#[derive(Default)]
-pub struct UnsafeVec(UnsafeCell<Vec<u8>>);
+pub struct UnsafeVec(UnsafeCell<AlignedBuffer>);
 
 pub trait Component: Send + Sync + 'static {}
 
+impl UnsafeVec {
+    pub(crate) fn new(align: usize) -> Self {
+        UnsafeVec(UnsafeCell::new(AlignedBuffer::new(align)))
+    }
+}
+
 impl Deref for UnsafeVec {
-    type Target = UnsafeCell<Vec<u8>>;
+    type Target = UnsafeCell<AlignedBuffer>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -27,10 +35,36 @@ impl DerefMut for UnsafeVec {
 
 impl<T> Component for T where T: Send + Sync + 'static {}
 
+/// A set of components represented by a tuple, e.g. `(A, B)`.
+pub trait ComponentSet {
+    /// Returns `TypeId`s of the components in the set.
+    fn type_ids() -> Vec<TypeId>;
+}
+
+macro_rules! impl_component_set {
+    ($($c: ident),*) => {
+        impl<$($c: Component),*> ComponentSet for ($($c,)*) {
+            fn type_ids() -> Vec<TypeId> {
+                vec![$(TypeId::of::<$c>()),*]
+            }
+        }
+    };
+}
+
+impl_component_set!(C0);
+impl_component_set!(C0, C1);
+impl_component_set!(C0, C1, C2);
+impl_component_set!(C0, C1, C2, C3);
+impl_component_set!(C0, C1, C2, C3, C4);
+impl_component_set!(C0, C1, C2, C3, C4, C5);
+impl_component_set!(C0, C1, C2, C3, C4, C5, C6);
+impl_component_set!(C0, C1, C2, C3, C4, C5, C6, C7);
+
 pub struct ComponentStorage<'a, C, D> {
     pub(crate) entities: &'a ArchetypeEntities,
     pub(crate) step: usize,
-    pub(crate) info: &'a ComponentInfo,
+    /// Byte offset of the component within a step.
+    pub(crate) offset: usize,
     pub(crate) data: D,
     pub(crate) _ty: PhantomData<C>,
 }
@@ -40,38 +74,62 @@ pub type ComponentStorageMut<'a, C> = ComponentStorage<'a, C, &'a mut UnsafeVec>
 
 impl<'a, C, D: Borrow<UnsafeVec> + Copy> Clone for ComponentStorage<'a, C, D> {
     fn clone(&self) -> Self {
-        Self {
-            entities: &self.entities,
-            step: self.step,
-            info: self.info,
-            data: self.data,
-            _ty: Default::default(),
-        }
+        *self
     }
 }
 
 impl<'a, C, D: Borrow<UnsafeVec> + Copy> Copy for ComponentStorage<'a, C, D> {}
 
 impl<'a, C: Component, D: Borrow<UnsafeVec>> ComponentStorage<'a, C, D> {
+    /// Returns the number of components, i.e. the number of entities in the archetype.
+    pub fn len(&self) -> usize {
+        self.entities.count()
+    }
+
+    /// Returns `true` if the archetype contains no entities.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Checks whether `self` container specific entity.
     pub fn contains(&self, entity_id: ArchEntityId) -> bool {
         self.entities.contains(entity_id)
     }
 
+    /// Returns an iterator over the ids of the entities that have the component.
+    pub fn entity_ids(&self) -> EntitiesIter<'a> {
+        self.entities.iter()
+    }
+
     /// Returns a mutable reference to the component `C` of the specified entity id.
     /// # Safety:
     /// To not cause any undefined behavior, the following conditions must be met:
     /// * Entity at `entity_id` must exist.
     /// * `&mut C` must always be unique.
     pub(crate) unsafe fn get_mut_unsafe(&self, entity_id: ArchEntityId) -> &'a mut C {
+        if mem::size_of::<C>() == 0 {
+            // Zero-sized components don't occupy any memory in the data buffer.
+            return &mut *NonNull::dangling().as_ptr();
+        }
         let ptr = ((&*self.data.borrow().get()).as_ptr())
             .add(self.step * entity_id as usize)
-            .add(self.info.range.start);
+            .add(self.offset);
         &mut *(ptr as *mut C)
     }
 
+    /// Returns `true` if the components are stored in a contiguous array, i.e. the archetype
+    /// consists only of component `C` or is stored by components ([DataLayout::Soa](crate::DataLayout::Soa)),
+    /// and its entities have no freed slots in between.
+    fn is_dense(&self) -> bool {
+        self.step == mem::size_of::<C>() && self.entities.count() == self.entities.allocated_slots()
+    }
+}
+
+impl<'a, C: Component> ComponentStorageRef<'a, C> {
     /// Returns a reference to the component `C` of the specified entity.
-    /// Safety: entity must exist.
+    ///
+    /// # Safety
+    /// Entity must exist.
     pub unsafe fn get_unchecked(&self, entity_id: ArchEntityId) -> &'a C {
         // Safety: the method does not mutate `self`
         self.get_mut_unsafe(entity_id)
@@ -84,9 +142,83 @@ impl<'a, C: Component, D: Borrow<UnsafeVec>> ComponentStorage<'a, C, D> {
         }
         unsafe { Some(self.get_unchecked(entity_id)) }
     }
-}
 
-impl<'a, C: Component> ComponentStorageRef<'a, C> {
+    /// Returns references to the components `C` of the specified entities.
+    /// An item is `None` if the respective entity doesn't exist.
+    pub fn get_many<'b>(
+        &self,
+        entity_ids: &'b [ArchEntityId],
+    ) -> impl Iterator<Item = Option<&'a C>> + 'b
+    where
+        'a: 'b,
+    {
+        let comps = *self;
+        entity_ids.iter().map(move |&id| comps.get(id))
+    }
+
+    /// Returns all components as a slice indexed by entity id. Returns `None` if the archetype
+    /// contains other components besides `C` (unless it is stored by components)
+    /// or has freed slots (after an entity removal).
+    pub fn as_slice(&self) -> Option<&'a [C]> {
+        if !self.is_dense() {
+            return None;
+        }
+        let len = self.entities.count();
+        if len == 0 {
+            return Some(&[]);
+        }
+        // Safety: the archetype is dense, so all the slots `0..len` are occupied.
+        unsafe { Some(slice::from_raw_parts(self.get_unchecked(0), len)) }
+    }
+
+    /// Returns raw strided parts of the component storage: a pointer to the component `C`
+    /// of the entity at slot 0, the stride between components of consecutive slots in bytes,
+    /// and an iterator over occupied slots. Component of slot `i` is located at `ptr + i * stride`.
+    ///
+    /// # Safety
+    /// Only occupied slots may be accessed through the pointer. The pointer is valid
+    /// for reads only while the storage is borrowed (for lifetime `'a`).
+    ///
+    /// # Example
+    /// ```
+    /// use entity_data::{Archetype, EntityStorage};
+    ///
+    /// #[derive(Archetype)]
+    /// struct Particle {
+    ///     mass: f32,
+    ///     id: u64,
+    /// }
+    ///
+    /// let mut storage = EntityStorage::new();
+    /// for i in 0..10 {
+    ///     storage.add(Particle { mass: i as f32, id: i });
+    /// }
+    ///
+    /// let arch = storage.get_archetype::<Particle>().unwrap();
+    /// let masses = arch.component::<f32>().unwrap();
+    ///
+    /// let mut sum = 0.0;
+    /// unsafe {
+    ///     let (ptr, stride, slots) = masses.raw_parts();
+    ///     for slot in slots {
+    ///         let mass = ptr.as_ptr().cast::<u8>().add(slot as usize * stride).cast::<f32>();
+    ///         sum += *mass;
+    ///     }
+    /// }
+    /// assert_eq!(sum, 45.0);
+    /// ```
+    pub unsafe fn raw_parts(&self) -> (NonNull<C>, usize, EntitiesIter<'a>) {
+        if mem::size_of::<C>() == 0 {
+            return (NonNull::dangling(), 0, self.entities.iter());
+        }
+        let ptr = (*self.data.get()).as_mut_ptr().wrapping_add(self.offset);
+        (
+            NonNull::new_unchecked(ptr as *mut C),
+            self.step,
+            self.entities.iter(),
+        )
+    }
+
     /// Returns an iterator over all components.
     pub fn iter(self) -> Iter<'a, C, Self> {
         Iter {
@@ -95,6 +227,90 @@ impl<'a, C: Component> ComponentStorageRef<'a, C> {
             _ty: Default::default(),
         }
     }
+
+    /// Returns an iterator over all components along with their entity ids.
+    pub fn iter_with_ids(self) -> IterWithIds<'a, C> {
+        IterWithIds {
+            entities_iter: self.entities.iter(),
+            data: self,
+        }
+    }
+
+    /// Returns a parallel iterator over all components.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter(self) -> impl rayon::iter::ParallelIterator<Item = &'a C> + 'a {
+        use rayon::prelude::*;
+
+        let comps = SharedComponents(self);
+        self.par_entities()
+            .map(move |entity_id| unsafe { comps.get(entity_id) })
+    }
+
+    /// Returns a parallel mutable iterator over all components.
+    ///
+    /// # Safety
+    /// The components must not be accessed by anything else while the references are in use.
+    #[cfg(feature = "rayon")]
+    pub(crate) unsafe fn par_iter_mut_unsafe(
+        self,
+    ) -> impl rayon::iter::ParallelIterator<Item = &'a mut C> + 'a {
+        use rayon::prelude::*;
+
+        let comps = SharedComponents(self);
+        self.par_entities()
+            .map(move |entity_id| unsafe { comps.get_mut(entity_id) })
+    }
+
+    /// Same as [par_iter_mut_unsafe](Self::par_iter_mut_unsafe), but yields entity ids too.
+    ///
+    /// # Safety
+    /// The components must not be accessed by anything else while the references are in use.
+    #[cfg(feature = "rayon")]
+    pub(crate) unsafe fn par_iter_mut_with_ids_unsafe(
+        self,
+    ) -> impl rayon::iter::ParallelIterator<Item = (ArchEntityId, &'a mut C)> + 'a {
+        use rayon::prelude::*;
+
+        let comps = SharedComponents(self);
+        self.par_entities()
+            .map(move |entity_id| (entity_id, unsafe { comps.get_mut(entity_id) }))
+    }
+
+    /// Returns a parallel iterator over occupied slots. The slots are split
+    /// into runs of consecutive ids to skip the holes.
+    #[cfg(feature = "rayon")]
+    fn par_entities(&self) -> impl rayon::iter::ParallelIterator<Item = ArchEntityId> {
+        use rayon::prelude::*;
+
+        self.entities
+            .occupied_runs()
+            .into_par_iter()
+            .flat_map(|run| run.into_par_iter())
+    }
+}
+
+/// Component storage shared between threads by parallel iterators.
+#[cfg(feature = "rayon")]
+struct SharedComponents<'a, C>(ComponentStorageRef<'a, C>);
+
+// Safety: components are `Send + Sync`, the users of `get_mut` ensure that every slot
+// is accessed by one thread only.
+#[cfg(feature = "rayon")]
+unsafe impl<C: Component> Send for SharedComponents<'_, C> {}
+#[cfg(feature = "rayon")]
+unsafe impl<C: Component> Sync for SharedComponents<'_, C> {}
+
+#[cfg(feature = "rayon")]
+impl<'a, C: Component> SharedComponents<'a, C> {
+    /// Safety: entity must exist.
+    unsafe fn get(&self, entity_id: ArchEntityId) -> &'a C {
+        self.0.get_unchecked(entity_id)
+    }
+
+    /// Safety: entity must exist and `&mut C` must be unique.
+    unsafe fn get_mut(&self, entity_id: ArchEntityId) -> &'a mut C {
+        self.0.get_mut_unsafe(entity_id)
+    }
 }
 
 impl<'a, C: Component + 'a> IntoIterator for ComponentStorageRef<'a, C> {
@@ -108,28 +324,191 @@ impl<'a, C: Component + 'a> IntoIterator for ComponentStorageRef<'a, C> {
 }
 
 impl<'a, C: Component> ComponentStorageMut<'a, C> {
+    /// Returns an immutable view of the components borrowing `self`.
+    fn reborrow(&self) -> ComponentStorageRef<'_, C> {
+        ComponentStorage {
+            entities: self.entities,
+            step: self.step,
+            offset: self.offset,
+            data: &*self.data,
+            _ty: Default::default(),
+        }
+    }
+
+    /// Returns a reference to the component `C` of the specified entity.
+    ///
+    /// # Safety
+    /// Entity must exist.
+    pub unsafe fn get_unchecked(&self, entity_id: ArchEntityId) -> &C {
+        self.reborrow().get_unchecked(entity_id)
+    }
+
+    /// Returns a reference to component `C` of the specified entity.
+    pub fn get(&self, entity_id: ArchEntityId) -> Option<&C> {
+        self.reborrow().get(entity_id)
+    }
+
     /// Returns a mutable reference to the component `C` of the specified entity id.
-    /// Safety: component at `entity_id` must exist.
-    pub unsafe fn get_unchecked_mut(&mut self, entity_id: ArchEntityId) -> &'a mut C {
+    ///
+    /// # Safety
+    /// Component at `entity_id` must exist.
+    pub unsafe fn get_unchecked_mut(&mut self, entity_id: ArchEntityId) -> &mut C {
         self.get_mut_unsafe(entity_id)
     }
 
     /// Returns a mutable reference to the component `C` of the specified entity id.
-    pub fn get_mut(&mut self, entity_id: ArchEntityId) -> Option<&'a mut C> {
+    /// The reference borrows `self`, use [into_mut](Self::into_mut) to borrow the component for `'a`.
+    ///
+    /// Two references to the same component can't be obtained:
+    /// ```compile_fail
+    /// use entity_data::{Archetype, EntityStorage};
+    ///
+    /// #[derive(Archetype)]
+    /// struct Counter(u32);
+    ///
+    /// let mut storage = EntityStorage::new();
+    /// let e = storage.add(Counter(0));
+    /// let arch = storage.get_archetype_mut::<Counter>().unwrap();
+    /// let mut counters = arch.component_mut::<u32>().unwrap();
+    ///
+    /// let a = counters.get_mut(e.id).unwrap();
+    /// let b = counters.get_mut(e.id).unwrap();
+    /// *a += *b;
+    /// ```
+    pub fn get_mut(&mut self, entity_id: ArchEntityId) -> Option<&mut C> {
         if !self.contains(entity_id) {
             return None;
         }
         unsafe { Some(self.get_unchecked_mut(entity_id)) }
     }
 
+    /// Returns mutable references to the components `C` of the specified entities.
+    /// An item is `None` if the respective entity doesn't exist.
+    /// Panics if the ids are not sorted in ascending order or contain duplicates.
+    pub fn get_many_mut<'b>(
+        &'b mut self,
+        entity_ids: &'b [ArchEntityId],
+    ) -> impl Iterator<Item = Option<&'b mut C>> + 'b {
+        assert!(
+            entity_ids.windows(2).all(|w| w[0] < w[1]),
+            "Entity ids must be sorted and distinct"
+        );
+        let comps = self.reborrow();
+        entity_ids.iter().map(move |&id| {
+            // Safety: the ids are distinct, so the references don't alias.
+            comps
+                .contains(id)
+                .then(|| unsafe { comps.get_mut_unsafe(id) })
+        })
+    }
+
+    /// Returns a parallel mutable iterator over all components.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter_mut(&mut self) -> impl rayon::iter::ParallelIterator<Item = &mut C> + '_ {
+        // Safety: `self` is borrowed mutably.
+        unsafe { self.reborrow().par_iter_mut_unsafe() }
+    }
+
+    /// Returns a parallel mutable iterator over all components along with their entity ids.
+    #[cfg(feature = "rayon")]
+    pub fn into_par_iter_mut_with_ids(
+        self,
+    ) -> impl rayon::iter::ParallelIterator<Item = (ArchEntityId, &'a mut C)> + 'a {
+        let comps = ComponentStorage {
+            entities: self.entities,
+            step: self.step,
+            offset: self.offset,
+            data: &*self.data,
+            _ty: Default::default(),
+        };
+        // Safety: `self` is consumed and every slot is yielded once.
+        unsafe { comps.par_iter_mut_with_ids_unsafe() }
+    }
+
+    /// Calls a closure on every component in parallel allowing to modify it.
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each<F>(&mut self, f: F)
+    where
+        F: Fn(ArchEntityId, &mut C) + Send + Sync,
+    {
+        use rayon::prelude::*;
+
+        // Safety: `self` is borrowed mutably.
+        unsafe { self.reborrow().par_iter_mut_with_ids_unsafe() }
+            .for_each(|(id, comp)| f(id, comp));
+    }
+
+    /// Converts the view into a mutable reference to the component `C` of the specified entity id.
+    pub fn into_mut(self, entity_id: ArchEntityId) -> Option<&'a mut C> {
+        if !self.contains(entity_id) {
+            return None;
+        }
+        // Safety: `self` is consumed, so the reference is the only mutable access.
+        unsafe { Some(self.get_mut_unsafe(entity_id)) }
+    }
+
+    /// Returns all components as a mutable slice indexed by entity id. Returns `None` if the archetype
+    /// contains other components besides `C` (unless it is stored by components)
+    /// or has freed slots (after an entity removal).
+    pub fn as_mut_slice(&mut self) -> Option<&mut [C]> {
+        if !self.is_dense() {
+            return None;
+        }
+        let len = self.entities.count();
+        if len == 0 {
+            return Some(&mut []);
+        }
+        // Safety: the archetype is dense, so all the slots `0..len` are occupied.
+        unsafe { Some(slice::from_raw_parts_mut(self.get_unchecked_mut(0), len)) }
+    }
+
+    /// Same as [as_mut_slice](Self::as_mut_slice), but borrows the components for the whole lifetime `'a`.
+    pub(crate) fn into_mut_slice(self) -> Option<&'a mut [C]> {
+        if !self.is_dense() {
+            return None;
+        }
+        let len = self.entities.count();
+        if len == 0 {
+            return Some(&mut []);
+        }
+        // Safety: the archetype is dense, so all the slots `0..len` are occupied,
+        // and `self` is consumed, so the slice is the only mutable access.
+        unsafe { Some(slice::from_raw_parts_mut(self.get_mut_unsafe(0), len)) }
+    }
+
+    /// Returns an iterator over components which satisfy the predicate.
+    pub fn iter_mut_where<P>(self, pred: P) -> impl Iterator<Item = &'a mut C>
+    where
+        C: 'a,
+        P: Fn(&C) -> bool,
+    {
+        self.into_iter().filter(move |comp| pred(comp))
+    }
+
     /// Returns an iterator over all components.
-    pub fn iter_mut(&'a mut self) -> IterMut<'a, C, &mut Self> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, C, ComponentStorageRef<'_, C>> {
         IterMut {
             entities_iter: self.entities.iter(),
-            data: self,
+            data: self.reborrow(),
             _ty: Default::default(),
         }
     }
+
+    /// Calls a closure on every component allowing to modify it.
+    pub fn for_each<F: FnMut(ArchEntityId, &mut C)>(&mut self, mut f: F) {
+        for id in self.entities.iter() {
+            // Safety: the entity exists and `self` is borrowed mutably.
+            f(id, unsafe { self.get_unchecked_mut(id) });
+        }
+    }
+
+    /// Assigns a clone of `value` to every component.
+    pub fn fill(&mut self, value: C)
+    where
+        C: Clone,
+    {
+        self.for_each(|_, comp| comp.clone_from(&value));
+    }
 }
 
 impl<'a, C: Component + 'a> IntoIterator for ComponentStorageMut<'a, C> {
@@ -143,7 +522,7 @@ impl<'a, C: Component + 'a> IntoIterator for ComponentStorageMut<'a, C> {
             data: ComponentStorageRef {
                 entities: self.entities,
                 step: self.step,
-                info: self.info,
+                offset: self.offset,
                 data: self.data,
                 _ty: Default::default(),
             },
@@ -171,6 +550,17 @@ where
             .next()
             .map(|entity_id| unsafe { self.data.borrow().get_unchecked(entity_id) })
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entities_iter.size_hint()
+    }
+}
+
+impl<'a, C, D> ExactSizeIterator for Iter<'a, C, D>
+where
+    C: Component + 'a,
+    D: Borrow<ComponentStorageRef<'a, C>>,
+{
 }
 
 pub struct IterMut<'a, C, D> {
@@ -191,4 +581,37 @@ where
             .next()
             .map(|entity_id| unsafe { self.data.borrow().get_mut_unsafe(entity_id) })
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entities_iter.size_hint()
+    }
+}
+
+impl<'a, C, D> ExactSizeIterator for IterMut<'a, C, D>
+where
+    C: Component + 'a,
+    D: Borrow<ComponentStorageRef<'a, C>>,
+{
+}
+
+#[derive(Clone)]
+pub struct IterWithIds<'a, C> {
+    pub(crate) entities_iter: EntitiesIter<'a>,
+    pub(crate) data: ComponentStorageRef<'a, C>,
+}
+
+impl<'a, C: Component> Iterator for IterWithIds<'a, C> {
+    type Item = (ArchEntityId, &'a C);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entities_iter
+            .next()
+            .map(|entity_id| (entity_id, unsafe { self.data.get_unchecked(entity_id) }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entities_iter.size_hint()
+    }
 }
+
+impl<C: Component> ExactSizeIterator for IterWithIds<'_, C> {}
@@ -0,0 +1,143 @@
+use crate::private::{ArchetypeMetadata, ComponentInfo};
+use crate::{Component, DataLayout};
+use std::any::TypeId;
+use std::{mem, ptr};
+
+/// A marker type of archetypes constructed at runtime.
+pub(crate) struct DynamicArchetype;
+
+struct DynamicComponent {
+    type_id: TypeId,
+    type_name: &'static str,
+    size: usize,
+    align: usize,
+    needs_drop: bool,
+    drop_fn: unsafe fn(*mut u8),
+}
+
+/// Constructs metadata of an archetype whose set of components is known only at runtime.
+///
+/// By default the components are tightly packed one after another without any padding.
+/// In this case the components of a state passed to [EntityStorage::add_raw](crate::EntityStorage::add_raw)
+/// are not necessarily aligned, so they must be written using unaligned writes.
+/// Use [aligned](Self::aligned) to respect alignment of the components.
+/// The storage itself always keeps the components aligned.
+///
+/// # Example
+/// ```
+/// use entity_data::{DynamicArchetypeBuilder, EntityStorage};
+///
+/// let meta = DynamicArchetypeBuilder::new()
+///     .aligned()
+///     .register::<u32>()
+///     .register::<u64>()
+///     .build();
+///
+/// let infos = meta.component_infos();
+/// let mut state = vec![0_u64; meta.size.div_ceil(8)];
+/// let ptr = state.as_mut_ptr() as *mut u8;
+/// unsafe {
+///     (ptr.add(infos[0].range.start) as *mut u32).write(123);
+///     (ptr.add(infos[1].range.start) as *mut u64).write(456);
+/// }
+///
+/// let mut storage = EntityStorage::new();
+/// let entity = unsafe { storage.add_raw(meta, ptr) };
+///
+/// assert_eq!(storage.get::<u32>(&entity), Some(&123));
+/// assert_eq!(storage.get::<u64>(&entity), Some(&456));
+/// ```
+#[derive(Default)]
+pub struct DynamicArchetypeBuilder {
+    components: Vec<DynamicComponent>,
+    aligned: bool,
+    data_layout: DataLayout,
+}
+
+impl DynamicArchetypeBuilder {
+    /// Creates an empty builder.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Makes the builder respect alignment of the components by inserting padding between them.
+    pub fn aligned(mut self) -> Self {
+        self.aligned = true;
+        self
+    }
+
+    /// Sets the layout of the states in the archetype storage ([DataLayout::Aos] by default).
+    pub fn data_layout(mut self, data_layout: DataLayout) -> Self {
+        self.data_layout = data_layout;
+        self
+    }
+
+    /// Adds the component `C` to the archetype.
+    pub fn register<C: Component>(mut self) -> Self {
+        self.components.push(DynamicComponent {
+            type_id: TypeId::of::<C>(),
+            type_name: std::any::type_name::<C>(),
+            size: mem::size_of::<C>(),
+            align: mem::align_of::<C>(),
+            needs_drop: mem::needs_drop::<C>(),
+            drop_fn: |p: *mut u8| unsafe { ptr::drop_in_place(p as *mut C) },
+        });
+        self
+    }
+
+    /// Adds a component described by `info` to the archetype.
+    pub(crate) fn register_info(mut self, info: &ComponentInfo) -> Self {
+        self.components.push(DynamicComponent {
+            type_id: info.type_id,
+            type_name: info.type_name,
+            size: info.size(),
+            align: info.align,
+            needs_drop: info.needs_drop,
+            drop_fn: info.drop_fn,
+        });
+        self
+    }
+
+    /// Computes the component layout and returns the archetype metadata.
+    pub fn build(self) -> ArchetypeMetadata {
+        let mut offset = 0_usize;
+        let mut align = 1_usize;
+
+        let component_infos = self
+            .components
+            .iter()
+            .map(|comp| {
+                if self.aligned {
+                    offset = offset.next_multiple_of(comp.align);
+                    align = align.max(comp.align);
+                }
+                let range = offset..(offset + comp.size);
+                offset += comp.size;
+
+                ComponentInfo {
+                    type_id: comp.type_id,
+                    type_name: comp.type_name,
+                    range,
+                    align: comp.align,
+                    needs_drop: comp.needs_drop,
+                    drop_fn: comp.drop_fn,
+                }
+            })
+            .collect();
+
+        ArchetypeMetadata {
+            type_id: TypeId::of::<DynamicArchetype>(),
+            type_name: std::any::type_name::<DynamicArchetype>(),
+            component_infos,
+            size: offset.next_multiple_of(align),
+            align,
+            data_layout: self.data_layout,
+            needs_drop: self.components.iter().any(|comp| comp.needs_drop),
+            drop_fn: None,
+            as_any_fn: None,
+            into_any_fn: None,
+            eq_fn: None,
+            clone_fn: None,
+        }
+    }
+}
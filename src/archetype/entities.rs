@@ -1,59 +1,191 @@
 use crate::entity::ArchEntityId;
 use index_pool::IndexPool;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+/// An index pool which tracks the generation of each slot.
+/// The generation of a slot is incremented every time the slot is freed.
+#[derive(Default)]
+struct GenerationalPool {
+    indices: IndexPool,
+    generations: Vec<u16>,
+    /// If `Some`, freed ids are never reused and new ids are allocated monotonically starting from this id.
+    next_monotonic_id: Option<usize>,
+}
+
+impl GenerationalPool {
+    fn new_id(&mut self) -> usize {
+        let id = match &mut self.next_monotonic_id {
+            Some(next_id) => {
+                let id = *next_id;
+                *next_id += 1;
+                self.indices.request_id(id).unwrap();
+                id
+            }
+            None => self.indices.new_id(),
+        };
+        if id >= self.generations.len() {
+            self.generations.resize(id + 1, 0);
+        }
+        id
+    }
+
+    /// Returns `true` if the slot was occupied. Panics in debug mode if the slot is already free.
+    fn return_id(&mut self, id: usize) -> bool {
+        if self.indices.return_id(id).is_err() {
+            if cfg!(debug_assertions) {
+                panic!(
+                    "entity {} (generation {}) already freed",
+                    id,
+                    self.generation(id)
+                );
+            }
+            return false;
+        }
+        self.generations[id] = self.generations[id].wrapping_add(1);
+        true
+    }
+
+    fn generation(&self, id: usize) -> u16 {
+        self.generations.get(id).copied().unwrap_or(0)
+    }
+}
+
+/// An error returned when an archetype can't allocate a slot for a new entity
+/// because the maximum number of entities is reached.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SlotsExhausted;
+
+impl fmt::Display for SlotsExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Out of slots. A maximum number of entities ({}) is reached.",
+            ArchetypeEntities::MAX_ENTITIES
+        )
+    }
+}
+
+impl Error for SlotsExhausted {}
 
 #[derive(Default)]
 pub struct ArchetypeEntities {
-    occupied_ids: IndexPool,
+    occupied_ids: GenerationalPool,
 }
 
 impl<'a> ArchetypeEntities {
     pub const MAX_ENTITIES: usize = u32::MAX as usize - 1;
 
+    /// Creates an empty set of entities. If `reuse_ids` is `false`,
+    /// slots of removed entities are never allocated again.
+    pub(crate) fn new(reuse_ids: bool) -> Self {
+        ArchetypeEntities {
+            occupied_ids: GenerationalPool {
+                next_monotonic_id: (!reuse_ids).then_some(0),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Returns `true` if no more slots can be allocated.
+    pub(crate) fn is_full(&self) -> bool {
+        match self.occupied_ids.next_monotonic_id {
+            Some(next_id) => next_id >= Self::MAX_ENTITIES,
+            None => self.occupied_ids.indices.in_use() >= Self::MAX_ENTITIES,
+        }
+    }
+
     pub(crate) fn allocate_slot(&mut self) -> ArchEntityId {
-        if self.occupied_ids.in_use() >= Self::MAX_ENTITIES {
-            panic!(
-                "Out of slots. A maximum number of entities ({}) is reached.",
-                ArchetypeEntities::MAX_ENTITIES
-            );
+        if self.is_full() {
+            panic!("{}", SlotsExhausted);
         }
 
         let new_id = self.occupied_ids.new_id();
         new_id as ArchEntityId
     }
 
-    /// Returns `true` if the entity was present.
+    /// Frees the slot of the entity. Returns `true` if the entity was present.
+    /// Freeing a slot that is already free is a bug, so it panics in debug mode.
     pub(crate) fn free(&mut self, entity_id: ArchEntityId) -> bool {
-        let result = self.occupied_ids.return_id(entity_id as usize);
-        result != Err(index_pool::AlreadyReturned)
+        self.occupied_ids.return_id(entity_id as usize)
+    }
+
+    /// Occupies the free slot `entity_id` again.
+    pub(crate) fn reoccupy(&mut self, entity_id: ArchEntityId) {
+        self.occupied_ids
+            .indices
+            .request_id(entity_id as usize)
+            .expect("the slot must be free");
     }
 
     /// Returns `true` if the storage contains the specified entity.
     pub fn contains(&self, entity_id: ArchEntityId) -> bool {
-        !self.occupied_ids.is_free(entity_id as usize)
+        !self.occupied_ids.indices.is_free(entity_id as usize)
+    }
+
+    /// Returns the generation of the slot, i.e. the number of times the slot has been freed (wrapping).
+    pub fn generation(&self, entity_id: ArchEntityId) -> u16 {
+        self.occupied_ids.generation(entity_id as usize)
     }
 
     /// Returns an iterator over all entities of the archetype.
-    pub fn iter(&'a self) -> EntitiesIter {
-        EntitiesIter(self.occupied_ids.all_indices().into_iter())
+    pub fn iter(&'a self) -> EntitiesIter<'a> {
+        EntitiesIter {
+            indices: self.occupied_ids.indices.all_indices(),
+            remaining: self.count(),
+        }
+    }
+
+    /// Returns ranges of consecutive occupied slots in ascending order.
+    pub(crate) fn occupied_runs(&self) -> Vec<Range<ArchEntityId>> {
+        let mut runs: Vec<Range<ArchEntityId>> = Vec::new();
+        for id in self.iter() {
+            match runs.last_mut() {
+                Some(run) if run.end == id => run.end += 1,
+                _ => runs.push(id..id + 1),
+            }
+        }
+        runs
     }
 
     /// Returns the number of entities in the archetype.
     pub fn count(&self) -> usize {
-        self.occupied_ids.in_use()
+        self.occupied_ids.indices.in_use()
+    }
+
+    /// Returns the number of allocated slots, i.e. the highest occupied slot id + 1.
+    pub fn allocated_slots(&self) -> usize {
+        self.occupied_ids.indices.maximum()
+    }
+
+    /// Returns the number of slots ever allocated, i.e. the highest slot id ever occupied + 1.
+    /// Unlike [allocated_slots](Self::allocated_slots), it doesn't decrease
+    /// when the entities of the highest slots are removed.
+    pub fn max_allocated_slots(&self) -> usize {
+        // Generations are tracked for every slot ever allocated
+        self.occupied_ids.generations.len()
     }
 }
 
 #[derive(Clone)]
-pub struct EntitiesIter<'a>(index_pool::iter::IndexIter<'a>);
+pub struct EntitiesIter<'a> {
+    indices: index_pool::iter::IndexIter<'a>,
+    remaining: usize,
+}
 
 impl Iterator for EntitiesIter<'_> {
     type Item = ArchEntityId;
 
     fn next(&mut self) -> Option<Self::Item> {
-        Some(self.0.next()? as ArchEntityId)
+        let id = self.indices.next()?;
+        self.remaining -= 1;
+        Some(id as ArchEntityId)
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.0.size_hint()
+        (self.remaining, Some(self.remaining))
     }
 }
+
+impl ExactSizeIterator for EntitiesIter<'_> {}
@@ -1,3 +1,7 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
 /// An archetype identifier.
 pub type ArchetypeId = u32;
 /// An entity identifier within an archetype.
@@ -11,6 +15,8 @@ pub struct EntityId {
 }
 
 impl EntityId {
+    /// An identifier which never refers to an entity. Lookups with it (as well as with any other
+    /// nonexistent entity) return `None` or `false` instead of panicking.
     pub const NULL: Self = EntityId {
         archetype_id: u32::MAX,
         id: u32::MAX,
@@ -20,6 +26,67 @@ impl EntityId {
     pub fn new(archetype_id: ArchetypeId, id: ArchEntityId) -> EntityId {
         EntityId { archetype_id, id }
     }
+
+    /// Packs the identifier into a single `u64` with `archetype_id` in the high 32 bits
+    /// and `id` in the low 32 bits. [EntityId::NULL] is packed into `u64::MAX`.
+    pub fn to_u64(&self) -> u64 {
+        ((self.archetype_id as u64) << 32) | self.id as u64
+    }
+
+    /// Unpacks the identifier packed by [to_u64](Self::to_u64).
+    pub fn from_u64(value: u64) -> EntityId {
+        EntityId {
+            archetype_id: (value >> 32) as ArchetypeId,
+            id: value as ArchEntityId,
+        }
+    }
+}
+
+/// Serializes the identifier as a single `u64` (see [EntityId::to_u64]).
+#[cfg(feature = "serde")]
+impl serde::Serialize for EntityId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.to_u64())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for EntityId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u64::deserialize(deserializer).map(EntityId::from_u64)
+    }
+}
+
+/// Formats the identifier as `{archetype_id}:{id}`.
+impl fmt::Display for EntityId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.archetype_id, self.id)
+    }
+}
+
+/// An error returned when parsing an [EntityId] from a string fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseEntityIdError;
+
+impl fmt::Display for ParseEntityIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid entity id, expected `{{archetype_id}}:{{id}}`")
+    }
+}
+
+impl Error for ParseEntityIdError {}
+
+/// Parses the identifier in the format `{archetype_id}:{id}` produced by `Display`.
+impl FromStr for EntityId {
+    type Err = ParseEntityIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (archetype_id, id) = s.split_once(':').ok_or(ParseEntityIdError)?;
+        Ok(EntityId {
+            archetype_id: archetype_id.parse().map_err(|_| ParseEntityIdError)?,
+            id: id.parse().map_err(|_| ParseEntityIdError)?,
+        })
+    }
 }
 
 impl Default for EntityId {
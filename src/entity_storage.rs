@@ -1,12 +1,18 @@
-use crate::archetype::component::Component;
-use crate::archetype::entities::EntitiesIter;
+use crate::archetype::component::{Component, ComponentSet};
+use crate::archetype::entities::{EntitiesIter, SlotsExhausted};
 use crate::archetype::{ArchetypeLayout, ArchetypeStorage};
-use crate::entity::ArchetypeId;
+use crate::entity::{ArchEntityId, ArchetypeId};
 use crate::entry::{Entry, EntryMut};
-use crate::{ArchetypeState, StaticArchetype};
+use crate::private::ArchetypeMetadata;
+use crate::sparse::{AnySparseSet, SparseSet};
+use crate::system::component::ComponentGlobalIterWithIds;
+use crate::{AnyState, ArchetypeState, DynamicArchetypeBuilder, StaticArchetype};
 use crate::{EntityId, HashMap};
-use std::any::TypeId;
+use std::any::{Any, TypeId};
 use std::collections::hash_map;
+use std::iter::FusedIterator;
+use std::mem;
+use std::sync::{Arc, RwLock};
 
 /// A container of entities.
 #[derive(Default)]
@@ -15,6 +21,37 @@ pub struct EntityStorage {
     pub(crate) archetypes_by_types: HashMap<TypeId, usize>,
     pub(crate) archetypes_by_layout: HashMap<ArchetypeLayout, usize>,
     pub(crate) component_to_archetypes_map: HashMap<TypeId, Vec<usize>>,
+    /// Total number of entities across all archetypes.
+    pub(crate) n_entities: usize,
+    /// Incremented every time a new archetype is created.
+    pub(crate) archetype_generation: u64,
+    pub(crate) options: StorageOptions,
+    /// Cached results of [query_filtered](Self::query_filtered).
+    /// Cleared when a new archetype is created or the cache is full.
+    pub(crate) query_cache: RwLock<HashMap<QueryKey, Arc<[ArchetypeId]>>>,
+    /// Components registered via [register_sparse](Self::register_sparse).
+    sparse_sets: HashMap<TypeId, Box<dyn AnySparseSet>>,
+}
+
+/// Sorted required and excluded components of a query.
+type QueryKey = (Vec<TypeId>, Vec<TypeId>);
+
+/// The maximum number of queries kept in the cache of [EntityStorage::query_filtered].
+pub(crate) const MAX_CACHED_QUERIES: usize = 256;
+
+/// Configuration of an [EntityStorage].
+#[derive(Debug, Clone)]
+pub struct StorageOptions {
+    /// Whether slots of removed entities can be reused by new entities (`true` by default).
+    /// If `false`, entity ids are allocated monotonically within each archetype and are never
+    /// handed out again. In this case the memory of removed entities is not reclaimed.
+    pub reuse_ids: bool,
+}
+
+impl Default for StorageOptions {
+    fn default() -> Self {
+        StorageOptions { reuse_ids: true }
+    }
 }
 
 impl EntityStorage {
@@ -25,40 +62,193 @@ impl EntityStorage {
             archetypes_by_types: Default::default(),
             archetypes_by_layout: Default::default(),
             component_to_archetypes_map: Default::default(),
+            n_entities: 0,
+            archetype_generation: 0,
+            options: Default::default(),
+            query_cache: Default::default(),
+            sparse_sets: Default::default(),
+        }
+    }
+
+    /// Creates an empty `EntityStorage` with the specified options.
+    pub fn with_options(options: StorageOptions) -> EntityStorage {
+        EntityStorage {
+            options,
+            ..Self::new()
         }
     }
 
+    /// Creates an empty `EntityStorage` with preallocated archetype bookkeeping
+    /// for approximately `entity_count_estimate` entities.
+    ///
+    /// The archetype list is preallocated for 16 archetypes, and the archetype lookup maps
+    /// are preallocated for `entity_count_estimate / 8` entries, assuming that there is
+    /// roughly one archetype per 8 entities. If your entities are much more (or less) diverse,
+    /// scale the estimate accordingly. Entity data of individual archetypes is still allocated on demand.
+    pub fn with_capacity(entity_count_estimate: usize) -> EntityStorage {
+        const ARCHETYPES_CAPACITY: usize = 16;
+        const ENTITIES_PER_ARCHETYPE: usize = 8;
+
+        let map_capacity = entity_count_estimate / ENTITIES_PER_ARCHETYPE;
+
+        EntityStorage {
+            archetypes: Vec::with_capacity(ARCHETYPES_CAPACITY),
+            archetypes_by_types: HashMap::with_capacity(map_capacity),
+            archetypes_by_layout: HashMap::with_capacity(map_capacity),
+            component_to_archetypes_map: HashMap::with_capacity(map_capacity),
+            n_entities: 0,
+            archetype_generation: 0,
+            options: Default::default(),
+            query_cache: Default::default(),
+            sparse_sets: Default::default(),
+        }
+    }
+
+    /// Creates an empty `EntityStorage` with archetypes registered in the order of `archetypes`.
+    /// This makes archetype ids independent of the order in which entities are added,
+    /// so storages created with the same list of archetypes assign the same ids to the same archetypes.
+    ///
+    /// Archetypes with the same set of components share a single id.
+    ///
+    /// # Example
+    /// ```
+    /// use entity_data::{Archetype, EntityStorage, StaticArchetype};
+    ///
+    /// #[derive(Archetype)]
+    /// struct Dog(u32);
+    /// #[derive(Archetype)]
+    /// struct Cat(u64);
+    ///
+    /// let mut storage = EntityStorage::with_registered_archetypes(&[
+    ///     <Dog as StaticArchetype>::metadata,
+    ///     <Cat as StaticArchetype>::metadata,
+    /// ]);
+    ///
+    /// let cat = storage.add(Cat(1));
+    /// assert_eq!(cat.archetype_id, 1);
+    /// ```
+    pub fn with_registered_archetypes(archetypes: &[fn() -> ArchetypeMetadata]) -> EntityStorage {
+        let mut storage = Self::new();
+        for metadata_fn in archetypes {
+            storage.get_or_create_archetype_by_layout(metadata_fn());
+        }
+        storage
+    }
+
+    /// Returns the archetype of the state, creates one if necessary. Unlike
+    /// [get_or_create_archetype_static](Self::get_or_create_archetype_static)
+    /// this supports states whose type is known only at runtime, e.g. [AnyState].
     fn get_or_create_archetype<S: ArchetypeState>(&mut self, state: &S) -> usize {
-        match self.archetypes_by_types.entry(state.ty()) {
+        match self.archetypes_by_types.get(&state.ty()) {
+            Some(arch_id) => *arch_id,
+            None => self.get_or_create_archetype_by_layout(state.metadata()),
+        }
+    }
+
+    /// Returns the archetype `A`, creates one if necessary.
+    fn get_or_create_archetype_static<A: StaticArchetype>(&mut self) -> usize {
+        match self.archetypes_by_types.get(&TypeId::of::<A>()) {
+            Some(arch_id) => *arch_id,
+            None => self.get_or_create_archetype_by_layout(<A as StaticArchetype>::metadata()),
+        }
+    }
+
+    /// Returns an archetype with the component layout of `meta`, creates one if necessary.
+    fn get_or_create_archetype_by_layout(&mut self, meta: ArchetypeMetadata) -> usize {
+        // The rest of the storage relies on archetypes having unique component types.
+        // The derive macro only catches types spelled the same way, so aliases
+        // and dynamic archetypes are checked here.
+        if cfg!(debug_assertions) {
+            if let Some((a, b)) = meta.find_duplicate_components() {
+                panic!(
+                    "Archetype `{}` contains multiple components of the same type: `{}` and `{}`",
+                    meta.type_name, a.type_name, b.type_name
+                );
+            }
+        }
+        let layout = ArchetypeLayout::new(meta.component_type_ids().into_vec());
+        let type_id = meta.type_id;
+        let is_dynamic = meta.is_dynamic();
+
+        let arch_id = match self.archetypes_by_layout.entry(layout) {
             hash_map::Entry::Vacant(e) => {
-                let meta = state.metadata();
-                let layout = ArchetypeLayout::new((meta.component_type_ids)().into_vec());
-
-                let arch_id = match self.archetypes_by_layout.entry(layout) {
-                    hash_map::Entry::Vacant(e) => {
-                        let new_arch_id = self.archetypes.len();
-                        let archetype = ArchetypeStorage::new(meta);
-
-                        // Map components to the new archetype
-                        for info in &archetype.components {
-                            self.component_to_archetypes_map
-                                .entry(info.type_id)
-                                .or_insert(Default::default())
-                                .push(new_arch_id);
-                        }
-
-                        self.archetypes.push(archetype);
-
-                        e.insert(new_arch_id);
-                        new_arch_id
-                    }
-                    hash_map::Entry::Occupied(e) => *e.get(),
-                };
+                let new_arch_id = self.archetypes.len();
+                let archetype = ArchetypeStorage::new(meta, &self.options);
+
+                // Map components to the new archetype
+                for info in &archetype.components {
+                    self.component_to_archetypes_map
+                        .entry(info.type_id)
+                        .or_default()
+                        .push(new_arch_id);
+                }
 
-                e.insert(arch_id);
-                arch_id
+                self.archetypes.push(archetype);
+                self.archetype_generation += 1;
+                // Cached queries don't include the new archetype
+                self.query_cache
+                    .get_mut()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .clear();
+
+                e.insert(new_arch_id);
+                new_arch_id
             }
             hash_map::Entry::Occupied(e) => *e.get(),
+        };
+
+        // Dynamic archetypes don't have a distinct type
+        if !is_dynamic {
+            self.archetypes_by_types.insert(type_id, arch_id);
+        }
+        arch_id
+    }
+
+    /// Creates the archetype `A` if it doesn't exist yet and returns its id.
+    pub fn register_archetype<A: StaticArchetype>(&mut self) -> ArchetypeId {
+        self.get_or_create_archetype_static::<A>() as ArchetypeId
+    }
+
+    /// Reserves capacity for at least `additional` more entities of the archetype `S`.
+    pub fn reserve<S: StaticArchetype>(&mut self, additional: usize) {
+        let arch_id = self.get_or_create_archetype_static::<S>();
+        self.archetypes[arch_id].reserve(additional);
+    }
+
+    /// Creates a new entity for each state of the iterator and returns their identifiers.
+    pub fn extend_collect<S: StaticArchetype, I: IntoIterator<Item = S>>(
+        &mut self,
+        iter: I,
+    ) -> Vec<EntityId> {
+        let iter = iter.into_iter();
+        let mut entities = Vec::with_capacity(iter.size_hint().0);
+        self.extend_with(iter, |entity| entities.push(entity));
+        entities
+    }
+
+    /// Creates a storage with an entity for each state of the iterator.
+    /// Returns the storage along with identifiers of the entities.
+    pub fn from_states<S: StaticArchetype, I: IntoIterator<Item = S>>(
+        iter: I,
+    ) -> (Self, Vec<EntityId>) {
+        let mut storage = Self::new();
+        let entities = storage.extend_collect(iter);
+        (storage, entities)
+    }
+
+    fn extend_with<S: StaticArchetype, I: Iterator<Item = S>>(
+        &mut self,
+        iter: I,
+        mut on_add: impl FnMut(EntityId),
+    ) {
+        // Reserve the memory once for all the states
+        let n_states = iter.size_hint().0;
+        if n_states > 0 {
+            self.reserve::<S>(n_states);
+        }
+
+        for state in iter {
+            on_add(self.add(state));
         }
     }
 
@@ -71,6 +261,40 @@ impl EntityStorage {
 
         // Safety: layout of the archetype is ensured by `get_or_create_archetype_any`.
         let entity_id = arch.add_entity(state);
+        self.n_entities += 1;
+
+        EntityId {
+            archetype_id: arch_id as u32,
+            id: entity_id,
+        }
+    }
+
+    /// Creates a new entity and returns its identifier. Returns an error
+    /// if the archetype of the state has no free slots left, the state is dropped in this case.
+    pub fn try_add<S: ArchetypeState>(&mut self, state: S) -> Result<EntityId, SlotsExhausted> {
+        let arch_id = self.get_or_create_archetype::<S>(&state);
+        if self.archetypes[arch_id].entities.is_full() {
+            return Err(SlotsExhausted);
+        }
+        Ok(self.add(state))
+    }
+
+    /// Creates a new entity of the archetype described by `meta` and returns its identifier.
+    /// The metadata can be constructed at runtime using [DynamicArchetypeBuilder](crate::DynamicArchetypeBuilder).
+    ///
+    /// # Safety
+    /// `state_ptr` must point to a valid state with the component layout of `meta`.
+    /// The ownership of the state is transferred to the storage, so it must not be dropped afterwards.
+    pub unsafe fn add_raw(&mut self, meta: ArchetypeMetadata, state_ptr: *const u8) -> EntityId {
+        let arch_id = match self.archetypes_by_types.get(&meta.type_id) {
+            Some(arch_id) if !meta.is_dynamic() => *arch_id,
+            _ => self.get_or_create_archetype_by_layout(meta.clone()),
+        };
+
+        // Safety: archetype at `arch_id` exists because it is created above if not present.
+        let arch = self.archetypes.get_unchecked_mut(arch_id);
+        let entity_id = arch.add_entity_from(&meta, state_ptr);
+        self.n_entities += 1;
 
         EntityId {
             archetype_id: arch_id as u32,
@@ -92,12 +316,154 @@ impl EntityStorage {
         unsafe { Some(self.archetypes.get_unchecked_mut(arch_id)) }
     }
 
+    /// Moves all entities of the archetype `A` into a new storage. An empty archetype is left
+    /// in place of the moved one, so archetype ids of this storage remain valid.
+    ///
+    /// The moved archetype has id `0` in the returned storage, and its entities keep their slot indices,
+    /// i.e. an entity `EntityId::new(arch_id, id)` of this storage becomes `EntityId::new(0, id)`.
+    /// Slots of the moved entities may be reused by new entities of this storage.
+    /// Sparse components of the moved entities are dropped.
+    pub fn split_off_archetype<A: StaticArchetype>(&mut self) -> EntityStorage {
+        let mut storage = EntityStorage::with_options(self.options.clone());
+
+        let Some(&arch_id) = self.archetypes_by_types.get(&TypeId::of::<A>()) else {
+            return storage;
+        };
+        let arch = &mut self.archetypes[arch_id];
+
+        let new_arch_id = storage.get_or_create_archetype_by_layout(arch.meta.clone());
+        storage
+            .archetypes_by_types
+            .insert(TypeId::of::<A>(), new_arch_id);
+
+        let empty_arch = ArchetypeStorage::new(arch.meta.clone(), &self.options);
+        storage.archetypes[new_arch_id] = mem::replace(arch, empty_arch);
+
+        let n_moved = storage.archetypes[new_arch_id].count_entities();
+        storage.n_entities = n_moved;
+        self.n_entities -= n_moved;
+
+        // Sparse components are not moved along with the archetype
+        for set in self.sparse_sets.values_mut() {
+            set.remove_archetype(arch_id as ArchetypeId);
+        }
+
+        storage
+    }
+
     /// Maps the specified `TypeId` to respective `ArchetypeId`.
     /// If the storage doesn't contain an archetype of type `type_id`, it returns `None`.
+    #[inline]
     pub fn type_id_to_archetype_id(&self, type_id: &TypeId) -> Option<ArchetypeId> {
         self.archetypes_by_types.get(type_id).map(|id| *id as u32)
     }
 
+    /// Returns an iterator over ids of all archetypes which contain the component `C`.
+    pub fn archetypes_with_component<C: Component>(
+        &self,
+    ) -> impl ExactSizeIterator<Item = ArchetypeId> + '_ {
+        self.archetypes_with_component_id(&TypeId::of::<C>())
+    }
+
+    /// Returns an iterator over ids of all archetypes which contain the component of type `type_id`.
+    /// If there is no such component in the storage, the iterator is empty.
+    pub fn archetypes_with_component_id(
+        &self,
+        type_id: &TypeId,
+    ) -> impl ExactSizeIterator<Item = ArchetypeId> + '_ {
+        self.component_to_archetypes_map
+            .get(type_id)
+            .map_or(&[][..], |ids| ids.as_slice())
+            .iter()
+            .map(|id| *id as ArchetypeId)
+    }
+
+    /// Returns ids of all archetypes which contain all the `required` components
+    /// and none of the `excluded` ones. The ids are sorted in ascending order.
+    pub fn archetypes_matching(
+        &self,
+        required: &[TypeId],
+        excluded: &[TypeId],
+    ) -> Vec<ArchetypeId> {
+        let matches = |arch_id: &usize| {
+            let arch = &self.archetypes[*arch_id];
+            required.iter().all(|ty| arch.has_component_id(ty))
+                && !excluded.iter().any(|ty| arch.has_component_id(ty))
+        };
+
+        // Archetype ids in `component_to_archetypes_map` are already sorted,
+        // so start from the shortest list of the required components.
+        let candidates = required
+            .iter()
+            .map(|ty| {
+                self.component_to_archetypes_map
+                    .get(ty)
+                    .map_or(&[][..], |ids| ids.as_slice())
+            })
+            .min_by_key(|ids| ids.len());
+
+        match candidates {
+            Some(ids) => ids
+                .iter()
+                .filter(|id| matches(id))
+                .map(|id| *id as ArchetypeId)
+                .collect(),
+            None => (0..self.archetypes.len())
+                .filter(matches)
+                .map(|id| id as ArchetypeId)
+                .collect(),
+        }
+    }
+
+    /// Returns ids of all archetypes which contain all the components of the set `S`,
+    /// e.g. `archetypes_with::<(A, B)>()`. The ids are sorted in ascending order.
+    pub fn archetypes_with<S: ComponentSet>(&self) -> Vec<ArchetypeId> {
+        self.archetypes_matching(&S::type_ids(), &[])
+    }
+
+    /// Returns ids of all archetypes which contain all the `required` components.
+    /// The result is cached, see [query_filtered](Self::query_filtered).
+    pub fn query(&self, required: &[TypeId]) -> Arc<[ArchetypeId]> {
+        self.query_filtered(required, &[])
+    }
+
+    /// Returns ids of all archetypes which contain all the `required` components
+    /// and none of the `excluded` ones, the same as [archetypes_matching](Self::archetypes_matching).
+    /// The result is cached and recomputed only when a new archetype is created.
+    /// Up to 256 distinct queries are cached, the cache is cleared when it's full.
+    pub fn query_filtered(&self, required: &[TypeId], excluded: &[TypeId]) -> Arc<[ArchetypeId]> {
+        let mut key = (required.to_vec(), excluded.to_vec());
+        key.0.sort_unstable();
+        key.1.sort_unstable();
+
+        let cached = self
+            .query_cache
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(&key)
+            .cloned();
+        if let Some(archetypes) = cached {
+            return archetypes;
+        }
+
+        let archetypes: Arc<[ArchetypeId]> = self.archetypes_matching(required, excluded).into();
+        let mut cache = self
+            .query_cache
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if cache.len() >= MAX_CACHED_QUERIES {
+            cache.clear();
+        }
+        cache.insert(key, Arc::clone(&archetypes));
+        archetypes
+    }
+
+    /// Returns the archetype generation which is incremented whenever a new archetype is created.
+    /// Can be used to invalidate cached results of [archetypes_matching](Self::archetypes_matching).
+    pub fn archetype_generation(&self) -> u64 {
+        self.archetype_generation
+    }
+
     /// Returns a reference to the specified archetype.
     pub fn get_archetype_by_id(&self, id: ArchetypeId) -> Option<&ArchetypeStorage> {
         self.archetypes.get(id as usize)
@@ -113,60 +479,1012 @@ impl EntityStorage {
         self.entities().contains(entity)
     }
 
+    /// Returns an iterator over component types of the specified entity.
+    /// Returns `None` if the entity doesn't exist.
+    pub fn components_of(
+        &self,
+        entity: &EntityId,
+    ) -> Option<impl ExactSizeIterator<Item = TypeId> + '_> {
+        let arch = self.archetypes.get(entity.archetype_id as usize)?;
+        if !arch.contains(entity.id) {
+            return None;
+        }
+        Some(arch.components.iter().map(|info| info.type_id))
+    }
+
+    /// Returns `true` if the specified entity exists and has the component of type `ty`.
+    pub fn has_component(&self, entity: &EntityId, ty: TypeId) -> bool {
+        self.archetypes
+            .get(entity.archetype_id as usize)
+            .is_some_and(|arch| arch.contains(entity.id) && arch.has_component_id(&ty))
+    }
+
     /// Returns a reference to the component `C` of the specified entity.
     pub fn get<C: Component>(&self, entity: &EntityId) -> Option<&C> {
+        if let Some(comp) = self
+            .sparse_set::<C>()
+            .and_then(|set| set.components.get(entity))
+        {
+            return Some(comp);
+        }
         let arch = self.archetypes.get(entity.archetype_id as usize)?;
         arch.get(entity.id)
     }
 
     /// Returns a mutable reference to the component `C` of the specified entity.
     pub fn get_mut<C: Component>(&mut self, entity: &EntityId) -> Option<&mut C> {
+        if self
+            .sparse_set::<C>()
+            .is_some_and(|set| set.components.contains_key(entity))
+        {
+            return self.sparse_set_mut::<C>()?.components.get_mut(entity);
+        }
         let arch = self.archetypes.get_mut(entity.archetype_id as usize)?;
         arch.get_mut(entity.id)
     }
 
+    /// Returns mutable references to the components `C` of two distinct entities.
+    /// Returns `None` if any of the entities doesn't exist or doesn't have the component `C`.
+    /// Panics if `a` and `b` are the same entity.
+    pub fn get_two_mut<C: Component>(
+        &mut self,
+        a: &EntityId,
+        b: &EntityId,
+    ) -> Option<(&mut C, &mut C)> {
+        assert_ne!(a, b, "Entities must be distinct");
+
+        let comp_a = self
+            .archetypes
+            .get(a.archetype_id as usize)?
+            .component::<C>()?;
+        let comp_b = self
+            .archetypes
+            .get(b.archetype_id as usize)?
+            .component::<C>()?;
+        if !comp_a.contains(a.id) || !comp_b.contains(b.id) {
+            return None;
+        }
+
+        // Safety: `self` is borrowed mutably and the entities are distinct,
+        // so the components are located at different addresses.
+        unsafe { Some((comp_a.get_mut_unsafe(a.id), comp_b.get_mut_unsafe(b.id))) }
+    }
+
+    /// Calls a closure on every unordered pair of distinct components `C` in the storage
+    /// exactly once. This is a closure rather than an iterator because every component
+    /// takes part in several pairs, so the references must not outlive a single call.
+    ///
+    /// # Example
+    /// ```
+    /// use entity_data::{EntityStorage, Archetype};
+    ///
+    /// #[derive(Archetype)]
+    /// struct Body {
+    ///     force: f32,
+    /// }
+    ///
+    /// let mut storage = EntityStorage::new();
+    /// for _ in 0..3 {
+    ///     storage.add(Body { force: 0.0 });
+    /// }
+    ///
+    /// storage.for_each_pair_mut::<f32, _>(|a, b| {
+    ///     *a += 1.0;
+    ///     *b -= 1.0;
+    /// });
+    ///
+    /// let total: f32 = storage.iter_components::<f32>().map(|(_, f)| *f).sum();
+    /// assert_eq!(total, 0.0);
+    /// ```
+    pub fn for_each_pair_mut<C: Component, F: FnMut(&mut C, &mut C)>(&mut self, mut f: F) {
+        let Some(arch_ids) = self.component_to_archetypes_map.get(&TypeId::of::<C>()) else {
+            return;
+        };
+
+        let comps: Vec<_> = arch_ids
+            .iter()
+            .flat_map(|&arch_id| {
+                let comps = self.archetypes[arch_id].component::<C>().unwrap();
+                comps.entity_ids().map(move |id| (comps, id))
+            })
+            .collect();
+
+        for (i, (comps_a, id_a)) in comps.iter().enumerate() {
+            for (comps_b, id_b) in &comps[i + 1..] {
+                // Safety: `self` is borrowed mutably and every entity occurs in `comps` once,
+                // so the components of a pair are located at different addresses.
+                unsafe { f(comps_a.get_mut_unsafe(*id_a), comps_b.get_mut_unsafe(*id_b)) };
+            }
+        }
+    }
+
+    /// Returns memory usage of every archetype in the storage.
+    pub fn memory_report(&self) -> StorageMemoryReport {
+        let archetypes: Vec<_> = self
+            .archetypes
+            .iter()
+            .map(|arch| ArchetypeMemoryReport {
+                type_id: *arch.ty(),
+                type_name: arch.type_name(),
+                entity_size: arch.entity_size(),
+                n_entities: arch.count_entities(),
+                n_allocated_slots: arch.entities.max_allocated_slots(),
+                data_capacity: arch.data_capacity(),
+            })
+            .collect();
+
+        StorageMemoryReport {
+            total_entities: archetypes.iter().map(|v| v.n_entities).sum(),
+            total_data_capacity: archetypes.iter().map(|v| v.data_capacity).sum(),
+            archetypes,
+        }
+    }
+
+    /// Returns statistics of entity distribution among archetypes.
+    pub fn archetype_stats(&self) -> Vec<ArchetypeStats> {
+        self.archetypes
+            .iter()
+            .map(|arch| {
+                let n_entities = arch.count_entities();
+                ArchetypeStats {
+                    type_id: *arch.ty(),
+                    n_components: arch.components.len(),
+                    n_entities,
+                    capacity_bytes: arch.data_capacity(),
+                    hole_count: arch.entities.allocated_slots() - n_entities,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns an iterator over all components `C` in the storage along with their entity ids.
+    pub fn iter_components<C: Component>(&self) -> ComponentGlobalIterWithIds<'_, C> {
+        let filtered_archetype_ids = self
+            .component_to_archetypes_map
+            .get(&TypeId::of::<C>())
+            .map_or(&[][..], |v| v.as_slice());
+
+        ComponentGlobalIterWithIds {
+            filtered_archetype_ids: filtered_archetype_ids.iter(),
+            all_archetypes: &self.archetypes,
+            curr_arch_id: 0,
+            curr_iter: None,
+            remaining_entities: filtered_archetype_ids
+                .iter()
+                .map(|v| self.archetypes[*v].count_entities())
+                .sum(),
+        }
+    }
+
+    /// Returns the first entity whose component `C` satisfies the predicate.
+    pub fn find<C: Component, F: Fn(&C) -> bool>(&self, pred: F) -> Option<EntityId> {
+        self.find_mut(pred)
+    }
+
+    /// Returns the first entity whose component `C` satisfies the mutable predicate.
+    pub fn find_mut<C: Component, F: FnMut(&C) -> bool>(&self, mut pred: F) -> Option<EntityId> {
+        self.iter_components::<C>()
+            .find(|(_, comp)| pred(comp))
+            .map(|(entity, _)| entity)
+    }
+
+    /// Calls a closure on every component `C` in the storage.
+    pub fn for_each<C: Component, F: FnMut(EntityId, &C)>(&self, mut f: F) {
+        let Some(arch_ids) = self.component_to_archetypes_map.get(&TypeId::of::<C>()) else {
+            return;
+        };
+
+        for &arch_id in arch_ids {
+            let comp = self.archetypes[arch_id].component::<C>().unwrap();
+
+            for id in comp.entities.iter() {
+                let entity = EntityId::new(arch_id as ArchetypeId, id);
+                f(entity, unsafe { comp.get_unchecked(id) });
+            }
+        }
+    }
+
+    /// Calls a closure on every component `C` in the storage allowing to modify it.
+    ///
+    /// # Example
+    /// ```
+    /// use entity_data::{Archetype, EntityStorage};
+    ///
+    /// struct Position(f32);
+    /// struct Velocity(f32);
+    ///
+    /// #[derive(Archetype)]
+    /// struct Ball {
+    ///     pos: Position,
+    ///     vel: Velocity,
+    /// }
+    ///
+    /// let mut storage = EntityStorage::new();
+    /// let ball = storage.add(Ball { pos: Position(0.0), vel: Velocity(1.0) });
+    ///
+    /// storage.for_each_mut::<Position, _>(|_, pos| pos.0 += 2.0);
+    ///
+    /// assert_eq!(storage.get::<Position>(&ball).unwrap().0, 2.0);
+    /// ```
+    pub fn for_each_mut<C: Component, F: FnMut(EntityId, &mut C)>(&mut self, mut f: F) {
+        let Some(arch_ids) = self.component_to_archetypes_map.get(&TypeId::of::<C>()) else {
+            return;
+        };
+
+        for &arch_id in arch_ids {
+            let mut comp = self.archetypes[arch_id].component_mut::<C>().unwrap();
+            let entities = comp.entities;
+
+            for id in entities.iter() {
+                let entity = EntityId::new(arch_id as ArchetypeId, id);
+                f(entity, unsafe { comp.get_unchecked_mut(id) });
+            }
+        }
+    }
+
+    /// Calls a closure on every component `C` in the storage.
+    /// Equivalent to `for_each_mut::<C>(|_, c| f(c))`.
+    pub fn update_all<C: Component, F: FnMut(&mut C)>(&mut self, mut f: F) {
+        let Some(arch_ids) = self.component_to_archetypes_map.get(&TypeId::of::<C>()) else {
+            return;
+        };
+
+        for &arch_id in arch_ids {
+            let comp = self.archetypes[arch_id].component_mut::<C>().unwrap();
+            comp.into_iter().for_each(&mut f);
+        }
+    }
+
+    /// Replaces every component `C` in the storage with the value returned by `f`.
+    /// The old value is dropped after `f` returns, so a panicking `f` leaves it intact.
+    ///
+    /// # Example
+    /// ```
+    /// use entity_data::{EntityStorage, Archetype};
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct Name(String);
+    ///
+    /// #[derive(Archetype)]
+    /// struct Player {
+    ///     name: Name,
+    /// }
+    ///
+    /// let mut storage = EntityStorage::new();
+    /// let player = storage.add(Player { name: Name("bob".into()) });
+    ///
+    /// storage.map_component::<Name, _>(|name| Name(name.0.to_uppercase()));
+    ///
+    /// assert_eq!(storage.get::<Name>(&player).unwrap().0, "BOB");
+    /// ```
+    pub fn map_component<C: Component, F: FnMut(&C) -> C>(&mut self, mut f: F) {
+        self.update_all::<C, _>(|comp| *comp = f(comp));
+    }
+
+    /// Calls `f` on every run of components `C` in the storage. `f` receives a pointer
+    /// to the first component of a run and the number of consecutive components in the run.
+    /// Components are consecutive in memory if the archetype stores them by components
+    /// ([DataLayout::Soa](crate::DataLayout::Soa)) or consists only of `C`,
+    /// otherwise `f` is called on every component separately with the count of 1.
+    ///
+    /// # Safety
+    /// `f` must access only `count` components starting at the pointer.
+    pub unsafe fn update_all_unchecked<C: Component>(&mut self, f: unsafe fn(*mut C, usize)) {
+        let Some(arch_ids) = self.component_to_archetypes_map.get(&TypeId::of::<C>()) else {
+            return;
+        };
+
+        for &arch_id in arch_ids {
+            let mut comp = self.archetypes[arch_id].component_mut::<C>().unwrap();
+            let entities = comp.entities;
+            let contiguous = comp.step == mem::size_of::<C>();
+
+            for run in entities.occupied_runs() {
+                if contiguous {
+                    f(comp.get_unchecked_mut(run.start), run.len());
+                } else {
+                    for id in run {
+                        f(comp.get_unchecked_mut(id), 1);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Registers `C` as a sparse component. Sparse components are stored in a per-component
+    /// hash map keyed by entity instead of archetype buffers. Inserting or removing them doesn't move
+    /// the entity into another archetype, so they suit components which are present only on a small
+    /// fraction of entities or are frequently attached and detached.
+    ///
+    /// The tradeoffs are:
+    /// * Access is slower than to archetype components: a hash map lookup per entity,
+    ///   and [get](Self::get)/[get_mut](Self::get_mut) look into the sparse set before the archetype.
+    /// * Sparse components are not part of archetypes, so they are not visible to systems, queries,
+    ///   [Entry] or archetype iteration. Use [get](Self::get), [get_mut](Self::get_mut),
+    ///   [iter_sparse](Self::iter_sparse) and [iter_sparse_mut](Self::iter_sparse_mut) instead.
+    ///
+    /// Registering an already registered component does nothing.
+    ///
+    /// # Example
+    /// ```
+    /// use entity_data::{Archetype, EntityStorage};
+    ///
+    /// #[derive(Archetype)]
+    /// struct Dog(u32);
+    ///
+    /// struct Selected;
+    ///
+    /// let mut storage = EntityStorage::new();
+    /// storage.register_sparse::<Selected>();
+    ///
+    /// let dog = storage.add(Dog(1));
+    /// storage.insert_sparse(&dog, Selected).ok().unwrap();
+    ///
+    /// assert!(storage.get::<Selected>(&dog).is_some());
+    /// assert_eq!(storage.get::<u32>(&dog), Some(&1));
+    /// ```
+    pub fn register_sparse<C: Component>(&mut self) {
+        self.sparse_sets
+            .entry(TypeId::of::<C>())
+            .or_insert_with(|| Box::<SparseSet<C>>::default());
+    }
+
+    /// Returns `true` if `C` is registered as a sparse component.
+    pub fn is_sparse<C: Component>(&self) -> bool {
+        self.sparse_sets.contains_key(&TypeId::of::<C>())
+    }
+
+    fn sparse_set<C: Component>(&self) -> Option<&SparseSet<C>> {
+        if self.sparse_sets.is_empty() {
+            return None;
+        }
+        let set = self.sparse_sets.get(&TypeId::of::<C>())?;
+        set.as_any().downcast_ref()
+    }
+
+    fn sparse_set_mut<C: Component>(&mut self) -> Option<&mut SparseSet<C>> {
+        let set = self.sparse_sets.get_mut(&TypeId::of::<C>())?;
+        set.as_any_mut().downcast_mut()
+    }
+
+    /// Attaches the sparse component `C` to the entity. Returns the previous value of the component.
+    /// If the entity doesn't exist, the `value` is given back as `Err`.
+    ///
+    /// Panics if `C` is not registered as a sparse component.
+    pub fn insert_sparse<C: Component>(
+        &mut self,
+        entity: &EntityId,
+        value: C,
+    ) -> Result<Option<C>, C> {
+        if !self.contains(entity) {
+            return Err(value);
+        }
+        let set = self
+            .sparse_set_mut::<C>()
+            .expect("Component must be registered as sparse");
+        Ok(set.components.insert(*entity, value))
+    }
+
+    /// Detaches the sparse component `C` from the entity and returns it.
+    /// Returns `None` if the entity doesn't have the component or `C` is not registered as sparse.
+    pub fn remove_sparse<C: Component>(&mut self, entity: &EntityId) -> Option<C> {
+        self.sparse_set_mut::<C>()?.components.remove(entity)
+    }
+
+    /// Returns an iterator over all sparse components `C` along with their entities in arbitrary order.
+    /// The iterator is empty if `C` is not registered as sparse.
+    pub fn iter_sparse<C: Component>(&self) -> impl Iterator<Item = (EntityId, &C)> + '_ {
+        self.sparse_set::<C>()
+            .into_iter()
+            .flat_map(|set| set.components.iter().map(|(entity, comp)| (*entity, comp)))
+    }
+
+    /// Returns an iterator over all sparse components `C` along with their entities in arbitrary order.
+    /// The iterator is empty if `C` is not registered as sparse.
+    pub fn iter_sparse_mut<C: Component>(
+        &mut self,
+    ) -> impl Iterator<Item = (EntityId, &mut C)> + '_ {
+        self.sparse_set_mut::<C>().into_iter().flat_map(|set| {
+            set.components
+                .iter_mut()
+                .map(|(entity, comp)| (*entity, comp))
+        })
+    }
+
+    /// Overwrites the component `C` of the specified entity. Returns the previous value of the component.
+    /// If the entity doesn't exist or doesn't have the component `C`, the `value` is given back as `Err`.
+    pub fn set<C: Component>(&mut self, entity: &EntityId, value: C) -> Result<C, C> {
+        match self.get_mut::<C>(entity) {
+            Some(comp) => Ok(mem::replace(comp, value)),
+            None => Err(value),
+        }
+    }
+
+    /// Moves the component `C` of `entity` out, leaving `C::default()` in its place.
+    /// Returns `None` if the entity doesn't exist or doesn't have the component `C`.
+    pub fn take_component<C: Component + Default>(&mut self, entity: &EntityId) -> Option<C> {
+        self.get_mut::<C>(entity).map(mem::take)
+    }
+
+    /// Replaces the component `C` of `entity` with `value`, returning the previous value.
+    /// Returns `None` if the entity doesn't exist or doesn't have the component `C`.
+    pub fn replace_component<C: Component>(&mut self, entity: &EntityId, value: C) -> Option<C> {
+        self.get_mut::<C>(entity)
+            .map(|comp| mem::replace(comp, value))
+    }
+
+    /// Returns a mutable reference to the component `C` of `entity`, inserting the value
+    /// returned by `default` if the entity doesn't have the component. The insertion moves
+    /// the entity into another archetype, so the returned entity id must be used afterwards.
+    /// Returns `None` if the entity doesn't exist.
+    pub fn get_or_insert_component<C: Component>(
+        &mut self,
+        entity: &EntityId,
+        default: impl FnOnce() -> C,
+    ) -> Option<(EntityId, &mut C)> {
+        let arch = self.archetypes.get(entity.archetype_id as usize)?;
+        if !arch.contains(entity.id) {
+            return None;
+        }
+
+        let entity = if arch.has_component_id(&TypeId::of::<C>()) {
+            *entity
+        } else {
+            self.migrate_with_component(entity, default())
+        };
+
+        let comp = self.get_mut::<C>(&entity)?;
+        Some((entity, comp))
+    }
+
+    /// Moves `entity` into the archetype with the components of its current archetype plus `C`.
+    /// Returns the new id of the entity.
+    ///
+    /// `entity` must exist and must not have the component `C`.
+    fn migrate_with_component<C: Component>(&mut self, entity: &EntityId, value: C) -> EntityId {
+        let src_arch = &self.archetypes[entity.archetype_id as usize];
+
+        let meta = src_arch
+            .components
+            .iter()
+            .fold(
+                DynamicArchetypeBuilder::new()
+                    .aligned()
+                    .data_layout(src_arch.meta.data_layout),
+                |builder, info| builder.register_info(info),
+            )
+            .register::<C>()
+            .build();
+
+        // Assemble the new state from the old components and the new one
+        let mut state = vec![0_u8; meta.size];
+        let (new_info, old_infos) = meta.component_infos().split_last().unwrap();
+
+        unsafe {
+            for info in old_infos {
+                let src_index = src_arch.components_by_types[&info.type_id];
+                state
+                    .as_mut_ptr()
+                    .add(info.range.start)
+                    .copy_from_nonoverlapping(
+                        src_arch.component_ptr_unchecked(src_index, entity.id),
+                        info.size(),
+                    );
+            }
+            (state.as_mut_ptr().add(new_info.range.start) as *mut C).write_unaligned(value);
+        }
+
+        // The components are moved into `state`, so free the old slot without dropping them
+        self.archetypes[entity.archetype_id as usize]
+            .entities
+            .free(entity.id);
+
+        let arch_id = self.get_or_create_archetype_by_layout(meta.clone());
+        // Safety: the state is assembled according to `meta`.
+        let id = unsafe { self.archetypes[arch_id].add_entity_from(&meta, state.as_ptr()) };
+        let new_entity = EntityId::new(arch_id as ArchetypeId, id);
+
+        for set in self.sparse_sets.values_mut() {
+            set.move_entity(entity, new_entity);
+        }
+        new_entity
+    }
+
     /// Returns a reference to the state at `entity_id`.
-    /// Panics if `TypeId` of `S` is not equal to the type of the underlying archetype.
+    /// Returns `None` if the entity doesn't exist or wasn't added as a state of type `S`.
+    /// Panics if states of `S` can't be stored in the underlying archetype.
     pub fn get_state<S: StaticArchetype>(&self, entity_id: &EntityId) -> Option<&S> {
         let arch = self.archetypes.get(entity_id.archetype_id as usize)?;
-        arch.get_state(entity_id.id)
+        // Safety: the state is of type `S`.
+        unsafe { Some(&*arch.state_of_ptr(entity_id.id)?) }
     }
 
     /// Returns a mutable reference to the state at `entity_id`.
-    /// Panics if `TypeId` of `S` is not equal to the type of the underlying archetype.
+    /// Returns `None` if the entity doesn't exist or wasn't added as a state of type `S`.
+    /// Panics if states of `S` can't be stored in the underlying archetype.
     pub fn get_state_mut<S: StaticArchetype>(&mut self, entity_id: &EntityId) -> Option<&mut S> {
         let arch = self.archetypes.get_mut(entity_id.archetype_id as usize)?;
-        arch.get_state_mut(entity_id.id)
+        // Safety: the state is of type `S` and the archetype is borrowed mutably.
+        unsafe { Some(&mut *arch.state_of_ptr(entity_id.id)?) }
+    }
+
+    /// Converts the state of `entity` from `Old` to `New` using `f` and returns the new id of the entity.
+    /// If `Old` and `New` are of the same type, the state is replaced in place and the id stays the same.
+    /// Otherwise the entity is moved into the archetype of `New`. Returns `None` if the entity doesn't exist.
+    ///
+    /// Panics if the entity doesn't belong to the archetype `Old`. If `f` panics, the entity is removed.
+    ///
+    /// # Example
+    /// ```
+    /// use entity_data::{Archetype, EntityStorage};
+    ///
+    /// #[derive(Archetype)]
+    /// struct Egg(u32);
+    ///
+    /// #[derive(Archetype)]
+    /// struct Chicken(u32, String);
+    ///
+    /// let mut storage = EntityStorage::new();
+    /// let egg = storage.add(Egg(3));
+    ///
+    /// let chicken = storage
+    ///     .transform_entity(&egg, |Egg(age): Egg| Chicken(age, "Cluck".to_string()))
+    ///     .unwrap();
+    ///
+    /// assert!(!storage.contains(&egg));
+    /// assert_eq!(storage.get::<String>(&chicken).unwrap(), "Cluck");
+    /// ```
+    pub fn transform_entity<Old, New, F>(&mut self, entity: &EntityId, f: F) -> Option<EntityId>
+    where
+        Old: StaticArchetype,
+        New: StaticArchetype,
+        F: FnOnce(Old) -> New,
+    {
+        let arch = self.archetypes.get_mut(entity.archetype_id as usize)?;
+        let old_ptr = arch.state_of_ptr::<Old>(entity.id)? as *const Old;
+
+        // Move the state out and free the slot without dropping it,
+        // so that the state is dropped only once if `f` panics.
+        let old = unsafe { old_ptr.read() };
+        arch.entities.free(entity.id);
+        self.n_entities -= 1;
+
+        /// Removes sparse components of the entity if `f` panics,
+        /// so that they aren't inherited by an entity reusing the slot.
+        struct SparseGuard<'a> {
+            sparse_sets: &'a mut HashMap<TypeId, Box<dyn AnySparseSet>>,
+            entity: EntityId,
+        }
+
+        impl Drop for SparseGuard<'_> {
+            fn drop(&mut self) {
+                for set in self.sparse_sets.values_mut() {
+                    set.remove_entity(&self.entity);
+                }
+            }
+        }
+
+        let guard = SparseGuard {
+            sparse_sets: &mut self.sparse_sets,
+            entity: *entity,
+        };
+        let new = f(old);
+        mem::forget(guard);
+
+        let new_entity = if TypeId::of::<Old>() == TypeId::of::<New>() {
+            let arch = &mut self.archetypes[entity.archetype_id as usize];
+            arch.entities.reoccupy(entity.id);
+            // Safety: the slot is of the same type as `New` and its previous state is moved out.
+            unsafe { (arch.get_ptr(entity.id) as *mut New).write(new) };
+            self.n_entities += 1;
+            *entity
+        } else {
+            let new_entity = self.add(new);
+            for set in self.sparse_sets.values_mut() {
+                set.move_entity(entity, new_entity);
+            }
+            new_entity
+        };
+
+        Some(new_entity)
+    }
+
+    /// Returns bytes of the component of type `component` of the specified entity.
+    /// Returns `None` if the entity doesn't exist or doesn't have the component.
+    pub fn get_raw(&self, entity: &EntityId, component: TypeId) -> Option<&[u8]> {
+        let arch = self.archetypes.get(entity.archetype_id as usize)?;
+        arch.component_bytes(entity.id, component)
+    }
+
+    /// Returns mutable bytes of the component of type `component` of the specified entity.
+    /// Returns `None` if the entity doesn't exist or doesn't have the component.
+    ///
+    /// The caller is responsible for writing bytes that form a valid value of the component type.
+    pub fn get_raw_mut(&mut self, entity: &EntityId, component: TypeId) -> Option<&mut [u8]> {
+        let arch = self.archetypes.get_mut(entity.archetype_id as usize)?;
+        arch.component_bytes_mut(entity.id, component)
+    }
+
+    /// Returns a reference to the state of `entity` as `dyn Any` without knowing its archetype.
+    /// Returns `None` if the entity doesn't exist or belongs to a dynamic archetype.
+    ///
+    /// # Example
+    /// ```
+    /// use entity_data::{Archetype, EntityStorage};
+    ///
+    /// #[derive(Archetype)]
+    /// struct Dog(u32);
+    ///
+    /// #[derive(Archetype)]
+    /// struct Bird(f32);
+    ///
+    /// let mut storage = EntityStorage::new();
+    /// storage.add(Dog(3));
+    /// storage.add(Bird(0.5));
+    ///
+    /// for entity in storage.entities().iter() {
+    ///     let state = storage.get_state_dyn(&entity).unwrap();
+    ///     if let Some(dog) = state.downcast_ref::<Dog>() {
+    ///         assert_eq!(dog.0, 3);
+    ///     } else if let Some(bird) = state.downcast_ref::<Bird>() {
+    ///         assert_eq!(bird.0, 0.5);
+    ///     } else {
+    ///         unreachable!();
+    ///     }
+    /// }
+    /// ```
+    pub fn get_state_dyn(&self, entity: &EntityId) -> Option<&dyn Any> {
+        let arch = self.archetypes.get(entity.archetype_id as usize)?;
+        arch.get_state_dyn(entity.id)
+    }
+
+    /// Returns a mutable reference to the state of `entity` as `dyn Any` without knowing its archetype.
+    /// Returns `None` if the entity doesn't exist or belongs to a dynamic archetype.
+    pub fn get_state_dyn_mut(&mut self, entity: &EntityId) -> Option<&mut dyn Any> {
+        let arch = self.archetypes.get_mut(entity.archetype_id as usize)?;
+        arch.get_state_dyn_mut(entity.id)
+    }
+
+    /// Returns an iterator over all entities added as states of the archetype `A`
+    /// along with their states. Entities of other archetypes with the same set of components
+    /// share the storage with `A`, but are skipped.
+    ///
+    /// Panics if the states of `A` are stored by components ([DataLayout::Soa](crate::DataLayout::Soa))
+    /// or laid out differently from the states of the archetype sharing the storage.
+    pub fn iter_of<A: StaticArchetype>(&self) -> impl Iterator<Item = (EntityId, &A)> + '_ {
+        let arch_id = self.archetypes_by_types.get(&TypeId::of::<A>()).copied();
+
+        arch_id.into_iter().flat_map(|arch_id| {
+            let arch = &self.archetypes[arch_id];
+            arch.check_state_layout::<A>();
+
+            arch.entities_of::<A>().map(move |id| {
+                // Safety: the entity exists and its state is of type `A`.
+                let state = unsafe { &*(arch.get_ptr(id) as *const A) };
+                (EntityId::new(arch_id as ArchetypeId, id), state)
+            })
+        })
+    }
+
+    /// Returns an iterator over all entities added as states of the archetype `A`
+    /// along with their mutable states. Panics in the same cases as [iter_of](Self::iter_of).
+    pub fn iter_of_mut<A: StaticArchetype>(
+        &mut self,
+    ) -> impl Iterator<Item = (EntityId, &mut A)> + '_ {
+        let arch = self
+            .archetypes_by_types
+            .get(&TypeId::of::<A>())
+            .map(|&arch_id| (arch_id, &self.archetypes[arch_id]));
+
+        arch.into_iter().flat_map(|(arch_id, arch)| {
+            arch.check_state_layout::<A>();
+
+            arch.entities_of::<A>().map(move |id| {
+                // Safety: the entity exists and its state is of type `A`, the storage is borrowed
+                // mutably and every entity is visited once.
+                let state = unsafe { &mut *(arch.get_ptr(id) as *mut A) };
+                (EntityId::new(arch_id as ArchetypeId, id), state)
+            })
+        })
+    }
+
+    /// Returns the only entity of the archetype `A` along with its state.
+    /// Returns `None` if there are no entities of `A`. Panics if there is more than one entity.
+    pub fn single<A: StaticArchetype>(&self) -> Option<(EntityId, &A)> {
+        let arch_id = *self.archetypes_by_types.get(&TypeId::of::<A>())?;
+        let arch = &self.archetypes[arch_id];
+        let id = Self::single_entity_id::<A>(arch)?;
+        // Safety: the state is of type `A`.
+        let state = unsafe { &*arch.state_of_ptr(id)? };
+        Some((EntityId::new(arch_id as ArchetypeId, id), state))
+    }
+
+    /// Returns the only entity of the archetype `A` along with its mutable state.
+    /// Returns `None` if there are no entities of `A`. Panics if there is more than one entity.
+    pub fn single_mut<A: StaticArchetype>(&mut self) -> Option<(EntityId, &mut A)> {
+        let arch_id = *self.archetypes_by_types.get(&TypeId::of::<A>())?;
+        let arch = &mut self.archetypes[arch_id];
+        let id = Self::single_entity_id::<A>(arch)?;
+        // Safety: the state is of type `A` and the archetype is borrowed mutably.
+        let state = unsafe { &mut *arch.state_of_ptr(id)? };
+        Some((EntityId::new(arch_id as ArchetypeId, id), state))
+    }
+
+    fn single_entity_id<A: StaticArchetype>(arch: &ArchetypeStorage) -> Option<ArchEntityId> {
+        let mut ids = arch.entities_of::<A>();
+        let id = ids.next()?;
+        let n_others = ids.count();
+        if n_others > 0 {
+            panic!(
+                "expected a single entity of `{}`, found {}",
+                std::any::type_name::<A>(),
+                n_others + 1
+            );
+        }
+        Some(id)
+    }
+
+    /// Calls a closure on every state of the archetype `A` in the storage.
+    pub fn for_each_state_mut<A: StaticArchetype, F: FnMut(EntityId, &mut A)>(&mut self, mut f: F) {
+        let Some(&arch_id) = self.archetypes_by_types.get(&TypeId::of::<A>()) else {
+            return;
+        };
+        let arch = &mut self.archetypes[arch_id];
+        arch.check_state_layout::<A>();
+
+        let states = arch.data.get_mut().as_mut_ptr() as *mut A;
+
+        for id in arch.entities_of::<A>() {
+            let entity = EntityId::new(arch_id as ArchetypeId, id);
+            // Safety: the state of the entity is of type `A` and the entity exists.
+            f(entity, unsafe { &mut *states.add(id as usize) });
+        }
+    }
+
+    /// Calls a closure on every component `C` in the storage in parallel allowing to modify it.
+    /// Archetypes are processed in parallel, as well as the entities within every archetype.
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each<C, F>(&mut self, f: F)
+    where
+        C: Component,
+        F: Fn(EntityId, &mut C) + Send + Sync,
+    {
+        use rayon::prelude::*;
+
+        self.archetypes
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(arch_id, arch)| {
+                let Some(comps) = arch.component_mut::<C>() else {
+                    return;
+                };
+                comps
+                    .into_par_iter_mut_with_ids()
+                    .for_each(|(id, comp)| f(EntityId::new(arch_id as ArchetypeId, id), comp));
+            });
+    }
+
+    /// Calls a closure on every state of the archetype `A` in the storage in parallel.
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each_state_mut<A, F>(&mut self, f: F)
+    where
+        A: StaticArchetype,
+        F: Fn(EntityId, &mut A) + Send + Sync,
+    {
+        use rayon::prelude::*;
+
+        struct StatesPtr<A>(*mut A);
+
+        impl<A> StatesPtr<A> {
+            fn get(&self) -> *mut A {
+                self.0
+            }
+        }
+
+        // Safety: every state is accessed by one thread only.
+        unsafe impl<A: Send> Send for StatesPtr<A> {}
+        unsafe impl<A: Send> Sync for StatesPtr<A> {}
+
+        let Some(&arch_id) = self.archetypes_by_types.get(&TypeId::of::<A>()) else {
+            return;
+        };
+        let arch = &mut self.archetypes[arch_id];
+        arch.check_state_layout::<A>();
+
+        // Split occupied slots into runs of consecutive ids to skip the holes.
+        let runs = arch.entities.occupied_runs();
+        let states = StatesPtr(arch.data.get_mut().as_mut_ptr() as *mut A);
+        let arch = &*arch;
+
+        runs.into_par_iter()
+            .flat_map(|run| run.into_par_iter())
+            .filter(|&id| arch.is_state_of::<A>(id))
+            .for_each(|id| {
+                let entity = EntityId::new(arch_id as ArchetypeId, id);
+                // Safety: the state of the entity is of type `A`, the entity exists,
+                // and each slot is yielded exactly once.
+                f(entity, unsafe { &mut *states.get().add(id as usize) });
+            });
     }
 
     /// Returns an entry of `entity` in the corresponding archetype.
-    pub fn entry(&self, entity: &EntityId) -> Option<Entry> {
+    pub fn entry(&self, entity: &EntityId) -> Option<Entry<'_>> {
+        let arch = self.archetypes.get(entity.archetype_id as usize)?;
+        if !arch.contains(entity.id) {
+            return None;
+        }
         Some(Entry {
-            arch: self.archetypes.get(entity.archetype_id as usize)?,
+            arch,
             entity: *entity,
         })
     }
 
     /// Returns a mutable entry of `entity` in the corresponding archetype.
-    pub fn entry_mut(&mut self, entity: &EntityId) -> Option<EntryMut> {
+    pub fn entry_mut(&mut self, entity: &EntityId) -> Option<EntryMut<'_>> {
+        let arch = self.archetypes.get_mut(entity.archetype_id as usize)?;
+        if !arch.contains(entity.id) {
+            return None;
+        }
         Some(EntryMut {
-            arch: self.archetypes.get_mut(entity.archetype_id as usize)?,
+            arch,
             entity: *entity,
         })
     }
 
+    /// Returns an iterator over entries of all entities in the storage.
+    pub fn entries(&self) -> EntriesIter<'_> {
+        EntriesIter {
+            entities_iter: self.entities().iter(),
+        }
+    }
+
+    /// Returns an iterator over all entities in the storage along with their entries.
+    /// The same iterator is produced by iterating over `&EntityStorage`.
+    ///
+    /// # Example
+    /// ```
+    /// use entity_data::{Archetype, EntityStorage};
+    ///
+    /// #[derive(Archetype)]
+    /// struct Particle {
+    ///     mass: f32,
+    ///     id: u64,
+    /// }
+    ///
+    /// let mut storage = EntityStorage::new();
+    /// storage.add(Particle { mass: 1.0, id: 0 });
+    /// storage.add(Particle { mass: 3.0, id: 1 });
+    ///
+    /// let mut sum = 0.0;
+    /// for (entity, entry) in &storage {
+    ///     assert_eq!(entry.entity(), &entity);
+    ///     sum += entry.get::<f32>().unwrap();
+    /// }
+    /// assert_eq!(sum, 4.0);
+    /// ```
+    pub fn iter(&self) -> StorageIter<'_> {
+        StorageIter {
+            entries: self.entries(),
+        }
+    }
+
+    /// Calls a closure on mutable entry of every entity in the storage.
+    pub fn for_each_entry_mut<F: FnMut(EntryMut)>(&mut self, mut f: F) {
+        let mut entity_ids = Vec::new();
+
+        for (arch_id, arch) in self.archetypes.iter_mut().enumerate() {
+            entity_ids.clear();
+            entity_ids.extend(arch.entities.iter());
+
+            for id in &entity_ids {
+                f(EntryMut {
+                    arch,
+                    entity: EntityId::new(arch_id as ArchetypeId, *id),
+                });
+            }
+        }
+    }
+
     /// Removes an entity from the storage. Returns `true` if the entity was present in the storage.
     pub fn remove(&mut self, entity: &EntityId) -> bool {
-        if let Some(arch) = self.archetypes.get_mut(entity.archetype_id as usize) {
-            arch.remove(entity.id)
-        } else {
-            false
+        let Some(arch) = self.archetypes.get_mut(entity.archetype_id as usize) else {
+            return false;
+        };
+        // `remove` returns `false` for already removed entities,
+        // so the entity count is decremented only once per entity.
+        let removed = arch.remove(entity.id);
+        if removed {
+            self.on_removed(entity);
+        }
+        removed
+    }
+
+    /// Removes an entity from the storage and returns its state without dropping it.
+    /// Returns `None` if the entity doesn't exist.
+    /// Panics if `TypeId` of `S` is not equal to the type of the underlying archetype.
+    pub fn take<S: StaticArchetype>(&mut self, entity: &EntityId) -> Option<S> {
+        let arch = self.archetypes.get_mut(entity.archetype_id as usize)?;
+        if arch.meta.type_id != TypeId::of::<S>() {
+            panic!(
+                "invalid state type: expected `{}`, but the archetype is `{}`",
+                std::any::type_name::<S>(),
+                arch.meta.type_name,
+            );
+        }
+        // Safety: the state is of type `S` and is moved out of the freed slot.
+        let state = arch.take_with(entity.id, |ptr| unsafe { (ptr as *mut S).read() })?;
+        self.on_removed(entity);
+        Some(state)
+    }
+
+    /// Removes an entity from the storage and returns its type-erased state without dropping it.
+    /// Returns `None` if the entity doesn't exist or belongs to a dynamic archetype.
+    pub fn take_any(&mut self, entity: &EntityId) -> Option<AnyState> {
+        let arch = self.archetypes.get_mut(entity.archetype_id as usize)?;
+        let into_any_fn = arch.meta.into_any_fn?;
+        // Safety: `into_any_fn` moves the state out of the freed slot.
+        let state = arch.take_with(entity.id, |ptr| unsafe { into_any_fn(ptr) })?;
+        self.on_removed(entity);
+        Some(state)
+    }
+
+    /// Creates a new entity with a clone of the state of `entity` and returns its identifier.
+    /// Returns `None` if the entity doesn't exist or its archetype doesn't implement
+    /// [CloneArchetype](crate::CloneArchetype). Sparse components are not cloned.
+    ///
+    /// # Example
+    /// ```
+    /// use entity_data::{Archetype, EntityStorage};
+    ///
+    /// #[derive(Clone, Archetype)]
+    /// #[archetype(clone)]
+    /// struct Prefab {
+    ///     name: String,
+    /// }
+    ///
+    /// let mut storage = EntityStorage::new();
+    /// let prefab = storage.add(Prefab { name: "tree".to_string() });
+    /// let copy = storage.clone_entity(&prefab).unwrap();
+    ///
+    /// assert_eq!(storage.get::<String>(&copy).unwrap(), "tree");
+    /// ```
+    pub fn clone_entity(&mut self, entity: &EntityId) -> Option<EntityId> {
+        let state = self
+            .archetypes
+            .get(entity.archetype_id as usize)?
+            .clone_state(entity.id)?;
+        Some(self.add(state))
+    }
+
+    /// Updates the bookkeeping after `entity` has been removed from its archetype.
+    fn on_removed(&mut self, entity: &EntityId) {
+        self.n_entities -= 1;
+        for set in self.sparse_sets.values_mut() {
+            set.remove_entity(entity);
         }
     }
 
-    pub fn entities(&self) -> AllEntities {
+    /// Returns `true` if both storages contain the same archetypes in the same order
+    /// and the same entities (by id) with equal states.
+    ///
+    /// Entity ids depend on the order of insertion and removal, so storages filled with the same
+    /// states in a different order are not equal. Sparse components are not compared.
+    ///
+    /// Panics if an archetype with entities doesn't implement [EqArchetype](crate::EqArchetype).
+    pub fn content_eq(&self, other: &EntityStorage) -> bool {
+        self.n_entities == other.n_entities
+            && self.archetypes.len() == other.archetypes.len()
+            && self
+                .archetypes
+                .iter()
+                .zip(&other.archetypes)
+                .all(|(a, b)| a.content_eq(b))
+    }
+
+    pub fn entities(&self) -> AllEntities<'_> {
         AllEntities {
             archetypes: &self.archetypes,
+            n_entities: self.n_entities,
         }
     }
 
@@ -177,40 +1495,121 @@ impl EntityStorage {
 
     /// Returns the number of entities in the storage.
     pub fn count_entities(&self) -> usize {
-        self.entities().count()
+        self.n_entities
+    }
+
+    /// Returns the number of entities of the archetype `A`. Returns 0 if there is no such archetype.
+    pub fn count_entities_in_archetype<A: StaticArchetype>(&self) -> usize {
+        self.get_archetype::<A>()
+            .map_or(0, |arch| arch.count_entities())
     }
+
+    /// Returns the number of entities in the archetype with the specified id.
+    /// Returns 0 if there is no such archetype.
+    pub fn count_entities_in_archetype_by_id(&self, id: ArchetypeId) -> usize {
+        self.get_archetype_by_id(id)
+            .map_or(0, |arch| arch.count_entities())
+    }
+
+    /// Returns the number of entities in the storage.
+    pub fn len(&self) -> usize {
+        self.n_entities
+    }
+
+    /// Returns `true` if the storage contains no entities.
+    pub fn is_empty(&self) -> bool {
+        self.n_entities == 0
+    }
+}
+
+/// Memory usage of a single archetype.
+#[derive(Debug, Clone)]
+pub struct ArchetypeMemoryReport {
+    /// The `TypeId` of a single state in the archetype.
+    pub type_id: TypeId,
+    /// The type name of a single state in the archetype.
+    pub type_name: &'static str,
+    /// Size of a single state in bytes.
+    pub entity_size: usize,
+    /// The number of live entities.
+    pub n_entities: usize,
+    /// The number of allocated slots, i.e. the highest slot id ever occupied + 1 (high-water mark).
+    /// It doesn't decrease when entities are removed, as the data buffer doesn't shrink either.
+    pub n_allocated_slots: usize,
+    /// The number of bytes allocated for the entity data buffer.
+    pub data_capacity: usize,
+}
+
+/// Memory usage of an [EntityStorage].
+#[derive(Debug, Clone)]
+pub struct StorageMemoryReport {
+    pub archetypes: Vec<ArchetypeMemoryReport>,
+    /// The number of live entities across all archetypes.
+    pub total_entities: usize,
+    /// The number of bytes allocated for entity data across all archetypes.
+    pub total_data_capacity: usize,
+}
+
+/// Entity distribution statistics of a single archetype.
+#[derive(Debug, Clone)]
+pub struct ArchetypeStats {
+    /// The `TypeId` of a single state in the archetype.
+    pub type_id: TypeId,
+    /// The number of components in the archetype.
+    pub n_components: usize,
+    /// The number of live entities.
+    pub n_entities: usize,
+    /// The number of bytes allocated for the entity data buffer.
+    pub capacity_bytes: usize,
+    /// The number of free slots below the highest occupied slot.
+    pub hole_count: usize,
 }
 
 #[derive(Copy, Clone)]
 pub struct AllEntities<'a> {
     pub(crate) archetypes: &'a [ArchetypeStorage],
+    pub(crate) n_entities: usize,
 }
 
-impl AllEntities<'_> {
+impl<'a> AllEntities<'a> {
     /// Returns `true` if the storage contains the specified entity.
     pub fn contains(&self, entity: &EntityId) -> bool {
         self.archetypes
             .get(entity.archetype_id as usize)
-            .map_or(false, |arch| arch.contains(entity.id))
+            .is_some_and(|arch| arch.contains(entity.id))
     }
 
     /// Returns the number of entities in the storage.
     pub fn count(&self) -> usize {
-        self.archetypes
-            .iter()
-            .fold(0, |acc, arch| acc + arch.count_entities())
+        self.n_entities
+    }
+
+    /// Returns `true` if the storage contains no entities.
+    pub fn is_empty(&self) -> bool {
+        self.n_entities == 0
     }
 
-    pub fn iter(&self) -> AllEntitiesIter {
+    pub fn iter(&self) -> AllEntitiesIter<'a> {
         AllEntitiesIter {
-            remaining_entities: self.count(),
-            archetypes: &self.archetypes,
+            remaining_entities: self.n_entities,
+            archetypes: self.archetypes,
             curr_arch_id: 0,
-            curr_iter: self.archetypes.get(0).map(|arch| arch.entities.iter()),
+            curr_iter: self.archetypes.first().map(|arch| arch.entities.iter()),
         }
     }
 }
 
+/// Allows `for entity in storage.entities()`. `&EntityStorage` itself iterates
+/// over `(EntityId, Entry)` pairs.
+impl<'a> IntoIterator for AllEntities<'a> {
+    type Item = EntityId;
+    type IntoIter = AllEntitiesIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
 #[derive(Clone)]
 pub struct AllEntitiesIter<'a> {
     remaining_entities: usize,
@@ -224,14 +1623,20 @@ impl Iterator for AllEntitiesIter<'_> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            if let Some(arch_entity_id) = self.curr_iter.as_mut().map(|v| v.next()).flatten() {
+            let curr_iter = self.curr_iter.as_mut()?;
+
+            if let Some(arch_entity_id) = curr_iter.next() {
                 self.remaining_entities -= 1;
                 return Some(EntityId::new(self.curr_arch_id, arch_entity_id));
-            } else {
-                self.curr_arch_id += 1;
-                let arch = self.archetypes.get(self.curr_arch_id as usize)?;
-                self.curr_iter = Some(arch.entities.iter());
             }
+
+            // Skip to the next archetype (possibly empty).
+            // If there are no archetypes left, the iterator stays exhausted.
+            self.curr_arch_id += 1;
+            self.curr_iter = self
+                .archetypes
+                .get(self.curr_arch_id as usize)
+                .map(|arch| arch.entities.iter());
         }
     }
 
@@ -239,3 +1644,73 @@ impl Iterator for AllEntitiesIter<'_> {
         (self.remaining_entities, Some(self.remaining_entities))
     }
 }
+
+impl ExactSizeIterator for AllEntitiesIter<'_> {}
+
+impl FusedIterator for AllEntitiesIter<'_> {}
+
+/// An iterator over entries of all entities in a storage.
+#[derive(Clone)]
+pub struct EntriesIter<'a> {
+    entities_iter: AllEntitiesIter<'a>,
+}
+
+impl<'a> Iterator for EntriesIter<'a> {
+    type Item = Entry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entity = self.entities_iter.next()?;
+
+        // The entity belongs to the current archetype of the iterator,
+        // so there is no need to look up the archetype by entity.
+        let arch = &self.entities_iter.archetypes[self.entities_iter.curr_arch_id as usize];
+
+        Some(Entry { arch, entity })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entities_iter.size_hint()
+    }
+}
+
+impl ExactSizeIterator for EntriesIter<'_> {}
+
+impl FusedIterator for EntriesIter<'_> {}
+
+/// An iterator over all entities of a storage along with their entries.
+#[derive(Clone)]
+pub struct StorageIter<'a> {
+    entries: EntriesIter<'a>,
+}
+
+impl<'a> Iterator for StorageIter<'a> {
+    type Item = (EntityId, Entry<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.next()?;
+        Some((entry.entity, entry))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl ExactSizeIterator for StorageIter<'_> {}
+
+impl FusedIterator for StorageIter<'_> {}
+
+impl<S: StaticArchetype> Extend<S> for EntityStorage {
+    fn extend<I: IntoIterator<Item = S>>(&mut self, iter: I) {
+        self.extend_with(iter.into_iter(), |_| {});
+    }
+}
+
+impl<'a> IntoIterator for &'a EntityStorage {
+    type Item = (EntityId, Entry<'a>);
+    type IntoIter = StorageIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
@@ -1,4 +1,6 @@
 use crate::{ArchetypeStorage, Component, EntityId};
+use std::any::TypeId;
+use std::mem;
 
 /// A immutable entry of an entity in an `ArchetypeStorage`.
 /// Provides convenient and faster access to entity components.
@@ -13,6 +15,13 @@ impl<'a> Entry<'a> {
         &self.entity
     }
 
+    /// Returns `true` if the entity has the component `C`.
+    pub fn has<C: Component>(&self) -> bool {
+        self.arch
+            .components_by_types
+            .contains_key(&TypeId::of::<C>())
+    }
+
     /// Returns a reference to the component `C` of the specified entity.
     pub fn get<C: Component>(&self) -> Option<&C> {
         let comp = self.arch.component::<C>()?;
@@ -33,6 +42,13 @@ impl EntryMut<'_> {
         &self.entity
     }
 
+    /// Returns `true` if the entity has the component `C`.
+    pub fn has<C: Component>(&self) -> bool {
+        self.arch
+            .components_by_types
+            .contains_key(&TypeId::of::<C>())
+    }
+
     /// Returns a reference to the component `C` of the specified entity.
     pub fn get<C: Component>(&self) -> Option<&C> {
         let comp = self.arch.component::<C>()?;
@@ -41,7 +57,27 @@ impl EntryMut<'_> {
 
     /// Returns a mutable reference to the component `C` of the specified entity.
     pub fn get_mut<C: Component>(&mut self) -> Option<&mut C> {
-        let mut comp = self.arch.component_mut::<C>()?;
-        Some(unsafe { comp.get_unchecked_mut(self.entity.id) })
+        self.arch.component_mut::<C>()?.into_mut(self.entity.id)
+    }
+
+    /// Overwrites the component `C` of the entity. Returns the previous value of the component.
+    /// If the entity doesn't have the component `C`, the `value` is given back as `Err`.
+    pub fn set<C: Component>(&mut self, value: C) -> Result<C, C> {
+        match self.get_mut::<C>() {
+            Some(comp) => Ok(mem::replace(comp, value)),
+            None => Err(value),
+        }
+    }
+
+    /// Moves the component `C` out, leaving `C::default()` in its place.
+    /// Returns `None` if the entity doesn't have the component `C`.
+    pub fn take_component<C: Component + Default>(&mut self) -> Option<C> {
+        self.get_mut::<C>().map(mem::take)
+    }
+
+    /// Replaces the component `C` with `value`, returning the previous value.
+    /// Returns `None` if the entity doesn't have the component `C`.
+    pub fn replace_component<C: Component>(&mut self, value: C) -> Option<C> {
+        self.get_mut::<C>().map(|comp| mem::replace(comp, value))
     }
 }
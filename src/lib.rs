@@ -5,7 +5,7 @@
 //! But you don't want to use Rust's dynamic-dispatch feature for the following reasons:
 //! 1. Virtual dispatch induces indirection.
 //! 2. You will have to store every object somewhere on heap.
-//! That leads to cache-misses and hence slower iteration over the objects.
+//!    That leads to cache-misses and hence slower iteration over the objects.
 //!
 //! Data-oriented programming helps to overcome these issues.
 //!
@@ -92,19 +92,28 @@ pub mod archetype;
 pub mod entity;
 pub mod entity_storage;
 pub mod entry;
+pub mod owned_entity;
 pub mod private;
+mod sparse;
 pub mod state;
 pub mod system;
 
-pub use archetype::component::Component;
-pub use archetype::entities::ArchetypeEntities;
-pub use archetype::ArchetypeStorage;
-pub use entity::EntityId;
-pub use entity_storage::EntityStorage;
+pub use archetype::component::{Component, ComponentSet, ComponentStorageMut, ComponentStorageRef};
+pub use archetype::dynamic::DynamicArchetypeBuilder;
+pub use archetype::entities::{ArchetypeEntities, SlotsExhausted};
+pub use archetype::{ArchetypeLayout, ArchetypeStorage, DataLayout};
+pub use entity::{EntityId, ParseEntityIdError};
+pub use entity_storage::{EntityStorage, StorageOptions};
 pub use entry::{Entry, EntryMut};
 pub use macros::Archetype;
-pub use state::{AnyState, ArchetypeState, StaticArchetype};
-pub use system::component::{GenericComponentGlobalAccess, GlobalComponentAccess};
+pub use owned_entity::OwnedEntity;
+pub use state::{AnyState, ArchetypeState, CloneArchetype, EqArchetype, StaticArchetype};
+pub use std::cell::{BorrowError, BorrowMutError};
+pub use system::component::{
+    ComponentGlobalIter, ComponentGlobalIterMut, ComponentGlobalIterMutWithIds,
+    ComponentGlobalIterWithIds, GenericComponentGlobalAccess, GlobalComponentAccess,
+    GlobalComponentAccessMut,
+};
 pub use system::{System, SystemAccess, SystemHandler};
 
 pub(crate) type HashMap<K, V> = ahash::AHashMap<K, V>;
@@ -0,0 +1,102 @@
+use crate::{ArchetypeState, EntityId, EntityStorage};
+use std::cell::RefCell;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::ptr;
+use std::rc::{Rc, Weak};
+
+/// An entity which is removed from its storage when dropped.
+///
+/// The storage is referenced weakly, so owned entities kept inside the same storage
+/// don't create a reference cycle. If the storage is already dropped,
+/// dropping the owned entity does nothing.
+///
+/// The state of the entity is dropped after the storage is released, so entities owned
+/// by its components are removed too. If the storage is borrowed when the owned entity
+/// is dropped (e.g. it's dropped by [EntityStorage::remove] called on its owner),
+/// the entity is left in the storage. Use [EntityStorage::take] to remove the owner instead
+/// and drop the returned state after releasing the storage.
+///
+/// # Example
+/// ```
+/// use entity_data::{Archetype, EntityStorage};
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+///
+/// #[derive(Archetype)]
+/// struct Dog(u32);
+///
+/// let storage = Rc::new(RefCell::new(EntityStorage::new()));
+///
+/// let dog = EntityStorage::add_owned(&storage, Dog(1));
+/// assert!(storage.borrow().contains(&dog));
+///
+/// let entity = *dog;
+/// drop(dog);
+/// assert!(!storage.borrow().contains(&entity));
+/// ```
+pub struct OwnedEntity {
+    entity: EntityId,
+    storage: Weak<RefCell<EntityStorage>>,
+}
+
+impl OwnedEntity {
+    /// Takes ownership of `entity` in `storage`.
+    pub fn new(entity: EntityId, storage: Rc<RefCell<EntityStorage>>) -> Self {
+        OwnedEntity {
+            entity,
+            storage: Rc::downgrade(&storage),
+        }
+    }
+
+    /// Releases the ownership of the entity without removing it from the storage.
+    pub fn into_inner(self) -> EntityId {
+        let this = ManuallyDrop::new(self);
+        // Safety: `this` is not dropped, so the storage reference is released only once.
+        drop(unsafe { ptr::read(&this.storage) });
+        this.entity
+    }
+}
+
+impl Deref for OwnedEntity {
+    type Target = EntityId;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entity
+    }
+}
+
+impl Drop for OwnedEntity {
+    /// Removes the entity from the storage.
+    /// Does nothing if the storage is dropped or borrowed at the moment.
+    fn drop(&mut self) {
+        let Some(storage) = self.storage.upgrade() else {
+            return;
+        };
+        let Ok(mut storage) = storage.try_borrow_mut() else {
+            return;
+        };
+        let state = storage.take_any(&self.entity);
+        if state.is_none() {
+            // Dynamic archetypes can't be taken.
+            storage.remove(&self.entity);
+        }
+        drop(storage);
+        // Components of the state may own other entities of the storage.
+        drop(state);
+    }
+}
+
+impl EntityStorage {
+    /// Creates a new entity which is removed from `storage` when the returned guard is dropped.
+    pub fn add_owned<S: ArchetypeState>(
+        storage: &Rc<RefCell<EntityStorage>>,
+        state: S,
+    ) -> OwnedEntity {
+        let entity = storage.borrow_mut().add(state);
+        OwnedEntity {
+            entity,
+            storage: Rc::downgrade(storage),
+        }
+    }
+}
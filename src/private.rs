@@ -1,7 +1,9 @@
+pub use crate::archetype::DataLayout;
+use crate::AnyState;
 pub use memoffset::offset_of;
 pub use smallvec::smallvec;
 pub use smallvec::SmallVec;
-use std::any::TypeId;
+use std::any::{Any, TypeId};
 use std::ops::Range;
 
 pub const MAX_INFOS_ON_STACK: usize = 32;
@@ -9,21 +11,103 @@ pub const MAX_INFOS_ON_STACK: usize = 32;
 #[derive(Clone)]
 pub struct ComponentInfo {
     pub type_id: TypeId,
+    pub type_name: &'static str,
     pub range: Range<usize>,
+    pub align: usize,
+    pub needs_drop: bool,
+    pub drop_fn: unsafe fn(*mut u8),
+}
+
+impl ComponentInfo {
+    /// Returns the `TypeId` of the component.
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+
+    /// Returns the name of the component type.
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// Returns the size of the component in bytes.
+    pub fn size(&self) -> usize {
+        self.range.len()
+    }
+
+    /// Returns the byte range of the component within a state.
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct ArchetypeMetadata {
     pub type_id: TypeId,
-    pub component_type_ids: fn() -> SmallVec<[TypeId; MAX_INFOS_ON_STACK]>,
-    pub component_infos: fn() -> SmallVec<[ComponentInfo; MAX_INFOS_ON_STACK]>,
+    pub type_name: &'static str,
+    pub component_infos: SmallVec<[ComponentInfo; MAX_INFOS_ON_STACK]>,
     pub size: usize,
+    /// Alignment of the state. `size` must be a multiple of it.
+    pub align: usize,
+    /// Memory layout of the states in the archetype storage.
+    pub data_layout: DataLayout,
     pub needs_drop: bool,
-    pub drop_fn: unsafe fn(*mut u8),
+    /// Drops the whole state. If `None`, the components are dropped individually.
+    pub drop_fn: Option<unsafe fn(*mut u8)>,
+    /// Converts a pointer to the state into `dyn Any`. `None` for dynamic archetypes.
+    pub as_any_fn: Option<unsafe fn(*mut u8) -> *mut dyn Any>,
+    /// Moves the state at the pointer into [AnyState]. `None` for dynamic archetypes.
+    pub into_any_fn: Option<unsafe fn(*mut u8) -> AnyState>,
+    /// Compares two states. `None` if the archetype doesn't implement [EqArchetype](crate::EqArchetype).
+    pub eq_fn: Option<unsafe fn(*const u8, *const u8) -> bool>,
+    /// Clones the state at the pointer. `None` if the archetype doesn't implement [CloneArchetype](crate::CloneArchetype).
+    pub clone_fn: Option<unsafe fn(*const u8) -> AnyState>,
 }
 
 impl ArchetypeMetadata {
-    pub fn component_infos(&self) -> SmallVec<[ComponentInfo; MAX_INFOS_ON_STACK]> {
-        (self.component_infos)()
+    pub fn component_infos(&self) -> &[ComponentInfo] {
+        &self.component_infos
+    }
+
+    pub fn component_type_ids(&self) -> SmallVec<[TypeId; MAX_INFOS_ON_STACK]> {
+        self.component_infos
+            .iter()
+            .map(|info| info.type_id)
+            .collect()
+    }
+
+    /// Returns a pair of components that share the same type.
+    pub fn find_duplicate_components(&self) -> Option<(&ComponentInfo, &ComponentInfo)> {
+        let infos = &self.component_infos;
+        infos.iter().enumerate().find_map(|(i, a)| {
+            infos[i + 1..]
+                .iter()
+                .find(|b| b.type_id == a.type_id)
+                .map(|b| (a, b))
+        })
+    }
+
+    /// Returns `true` if the archetype is constructed at runtime
+    /// using [DynamicArchetypeBuilder](crate::DynamicArchetypeBuilder).
+    pub fn is_dynamic(&self) -> bool {
+        self.type_id == TypeId::of::<crate::archetype::dynamic::DynamicArchetype>()
+    }
+
+    /// Drops the state at `ptr`.
+    ///
+    /// # Safety
+    /// `ptr` must point to a valid state of this archetype.
+    pub unsafe fn drop_state(&self, ptr: *mut u8) {
+        if !self.needs_drop {
+            return;
+        }
+        if let Some(drop_fn) = self.drop_fn {
+            drop_fn(ptr);
+            return;
+        }
+        for info in &self.component_infos {
+            if info.needs_drop {
+                (info.drop_fn)(ptr.add(info.range.start));
+            }
+        }
     }
 }
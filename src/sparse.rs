@@ -0,0 +1,50 @@
+use crate::entity::ArchetypeId;
+use crate::{Component, EntityId, HashMap};
+use std::any::Any;
+
+/// Type-erased operations on a [SparseSet] needed to keep it in sync with the entities of a storage.
+pub(crate) trait AnySparseSet: Send + Sync {
+    fn remove_entity(&mut self, entity: &EntityId);
+    fn move_entity(&mut self, from: &EntityId, to: EntityId);
+    fn remove_archetype(&mut self, archetype_id: ArchetypeId);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// Components `C` stored outside of archetypes, keyed by entity.
+pub(crate) struct SparseSet<C> {
+    pub(crate) components: HashMap<EntityId, C>,
+}
+
+impl<C> Default for SparseSet<C> {
+    fn default() -> Self {
+        SparseSet {
+            components: Default::default(),
+        }
+    }
+}
+
+impl<C: Component> AnySparseSet for SparseSet<C> {
+    fn remove_entity(&mut self, entity: &EntityId) {
+        self.components.remove(entity);
+    }
+
+    fn move_entity(&mut self, from: &EntityId, to: EntityId) {
+        if let Some(component) = self.components.remove(from) {
+            self.components.insert(to, component);
+        }
+    }
+
+    fn remove_archetype(&mut self, archetype_id: ArchetypeId) {
+        self.components
+            .retain(|entity, _| entity.archetype_id != archetype_id);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
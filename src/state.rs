@@ -1,4 +1,5 @@
-use crate::private::ArchetypeMetadata;
+use crate::private::{ArchetypeMetadata, MAX_INFOS_ON_STACK};
+use crate::{Component, DataLayout};
 use smallvec::SmallVec;
 use std::alloc;
 use std::any::{Any, TypeId};
@@ -14,23 +15,68 @@ pub trait ArchetypeState: Send + Sync + 'static {
     fn as_any_mut(&mut self) -> &mut dyn Any;
     fn num_components(&self) -> usize;
 
-    fn component_ids(&self) -> SmallVec<[TypeId; 32]> {
-        let meta = self.metadata();
-        (meta.component_type_ids)()
+    fn component_ids(&self) -> SmallVec<[TypeId; MAX_INFOS_ON_STACK]> {
+        self.metadata().component_type_ids()
     }
 }
 
 /// Defines archetype objects (entity states).
+///
+/// An archetype must have at least one component:
+/// ```compile_fail
+/// #[derive(entity_data::Archetype)]
+/// struct Empty {}
+/// ```
+///
+/// More than 32 components require `#[archetype(large)]`:
+/// ```compile_fail
+/// #[derive(entity_data::Archetype)]
+/// struct Large([u8; 1], [u8; 2], [u8; 3], [u8; 4], [u8; 5], [u8; 6], [u8; 7], [u8; 8], [u8; 9], [u8; 10], [u8; 11], [u8; 12], [u8; 13], [u8; 14], [u8; 15], [u8; 16], [u8; 17], [u8; 18], [u8; 19], [u8; 20], [u8; 21], [u8; 22], [u8; 23], [u8; 24], [u8; 25], [u8; 26], [u8; 27], [u8; 28], [u8; 29], [u8; 30], [u8; 31], [u8; 32], [u8; 33]);
+/// ```
 pub trait StaticArchetype: Sized + ArchetypeState {
     const N_COMPONENTS: usize;
 
     fn metadata() -> ArchetypeMetadata;
 
+    /// Returns type ids of the components in declaration order.
+    /// Unlike `metadata().component_type_ids()`, this doesn't construct the metadata.
+    fn component_type_ids() -> SmallVec<[TypeId; MAX_INFOS_ON_STACK]> {
+        <Self as StaticArchetype>::metadata().component_type_ids()
+    }
+
     fn into_any(self) -> AnyState {
         AnyState(Box::new(self))
     }
 }
 
+/// Archetypes whose states can be compared by [EntityStorage::content_eq](crate::EntityStorage::content_eq).
+/// Implemented by `#[derive(Archetype)]` with the `#[archetype(eq)]` attribute.
+pub trait EqArchetype: StaticArchetype + PartialEq {
+    /// Compares the states at `a` and `b`.
+    ///
+    /// # Safety
+    /// Both pointers must point to valid states of `Self`.
+    unsafe fn eq_fn(a: *const u8, b: *const u8) -> bool {
+        *(a as *const Self) == *(b as *const Self)
+    }
+}
+
+impl EqArchetype for () {}
+
+/// Archetypes whose entities can be duplicated by [EntityStorage::clone_entity](crate::EntityStorage::clone_entity).
+/// Implemented by `#[derive(Archetype)]` with the `#[archetype(clone)]` attribute.
+pub trait CloneArchetype: StaticArchetype + Clone {
+    /// Clones the state at `src`.
+    ///
+    /// # Safety
+    /// `src` must point to a valid state of `Self`.
+    unsafe fn clone_fn(src: *const u8) -> AnyState {
+        (*(src as *const Self)).clone().into_any()
+    }
+}
+
+impl CloneArchetype for () {}
+
 pub struct AnyState(Box<dyn ArchetypeState>);
 
 /// Entity state with arbitrary components.
@@ -55,6 +101,31 @@ impl AnyState {
             None
         }
     }
+
+    /// Returns a reference to the component `C` of the state.
+    pub fn get<C: Component>(&self) -> Option<&C> {
+        let offset = self.component_offset::<C>()?;
+        // Safety: the component is located at `offset` within the state.
+        unsafe { Some(&*(self.0.as_ptr().add(offset) as *const C)) }
+    }
+
+    /// Returns a mutable reference to the component `C` of the state.
+    pub fn get_mut<C: Component>(&mut self) -> Option<&mut C> {
+        let offset = self.component_offset::<C>()?;
+        let ptr = self.0.as_any_mut() as *mut dyn Any as *mut u8;
+        // Safety: the component is located at `offset` within the state.
+        unsafe { Some(&mut *(ptr.add(offset) as *mut C)) }
+    }
+
+    /// Returns the byte offset of the component `C` within the state.
+    fn component_offset<C: Component>(&self) -> Option<usize> {
+        self.0
+            .metadata()
+            .component_infos()
+            .iter()
+            .find(|info| info.type_id == TypeId::of::<C>())
+            .map(|info| info.range.start)
+    }
 }
 
 impl<T: StaticArchetype> From<T> for AnyState {
@@ -101,6 +172,10 @@ impl ArchetypeState for AnyState {
     fn num_components(&self) -> usize {
         self.0.num_components()
     }
+
+    fn component_ids(&self) -> SmallVec<[TypeId; MAX_INFOS_ON_STACK]> {
+        self.0.component_ids()
+    }
 }
 
 impl ArchetypeState for () {
@@ -117,11 +192,17 @@ impl ArchetypeState for () {
     fn metadata(&self) -> ArchetypeMetadata {
         ArchetypeMetadata {
             type_id: TypeId::of::<Self>(),
-            component_type_ids: || Default::default(),
-            component_infos: || Default::default(),
+            type_name: std::any::type_name::<Self>(),
+            component_infos: Default::default(),
             size: 0,
+            align: 1,
+            data_layout: DataLayout::Aos,
             needs_drop: false,
-            drop_fn: |_| {},
+            drop_fn: None,
+            as_any_fn: Some(|p: *mut u8| p as *mut Self as *mut dyn Any),
+            into_any_fn: Some(|_| ().into_any()),
+            eq_fn: Some(|_, _| true),
+            clone_fn: Some(|_| ().into_any()),
         }
     }
 
@@ -141,14 +222,24 @@ impl ArchetypeState for () {
 impl StaticArchetype for () {
     const N_COMPONENTS: usize = 0;
 
+    fn component_type_ids() -> SmallVec<[TypeId; MAX_INFOS_ON_STACK]> {
+        SmallVec::new()
+    }
+
     fn metadata() -> ArchetypeMetadata {
         ArchetypeMetadata {
             type_id: TypeId::of::<Self>(),
-            component_type_ids: || Default::default(),
-            component_infos: || Default::default(),
+            type_name: std::any::type_name::<Self>(),
+            component_infos: Default::default(),
             size: 0,
+            align: 1,
+            data_layout: DataLayout::Aos,
             needs_drop: false,
-            drop_fn: |_| {},
+            drop_fn: None,
+            as_any_fn: Some(|p: *mut u8| p as *mut Self as *mut dyn Any),
+            into_any_fn: Some(|_| ().into_any()),
+            eq_fn: Some(|_, _| true),
+            clone_fn: Some(|_| ().into_any()),
         }
     }
 }
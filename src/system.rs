@@ -1,18 +1,24 @@
 pub(crate) mod component;
 
-use crate::entity::ArchetypeId;
+use crate::entity::{ArchEntityId, ArchetypeId};
 use crate::system::component::{
     CompMutability, GenericComponentGlobalAccess, GlobalComponentAccess, GlobalComponentAccessMut,
 };
-use crate::{Component, EntityStorage, HashMap};
+use crate::{Component, EntityId, EntityStorage, HashMap, StaticArchetype};
 use std::any::TypeId;
-use std::cell::{RefCell, UnsafeCell};
+use std::cell::{BorrowError, BorrowMutError, RefCell, UnsafeCell};
 use std::collections::hash_map;
 use std::pin::Pin;
 use std::vec;
 
 pub trait SystemHandler: Send + Sync {
     fn run(&mut self, data: SystemAccess);
+
+    /// Runs a system declared with [System::with_exclusive]. The whole storage is available,
+    /// so entities can be added or removed. Runs [run](Self::run) with access to all components by default.
+    fn run_exclusive(&mut self, storage: &mut EntityStorage) {
+        self.run(storage.access());
+    }
 }
 
 impl<F: FnMut(SystemAccess) + Send + Sync> SystemHandler for F {
@@ -23,22 +29,46 @@ impl<F: FnMut(SystemAccess) + Send + Sync> SystemHandler for F {
 
 /// A system context.
 pub struct System<'a> {
-    handler: Box<&'a mut (dyn SystemHandler)>,
+    handler: &'a mut dyn SystemHandler,
     components: HashMap<TypeId, CompMutability>,
+    condition: Option<Box<dyn Fn() -> bool + Send + Sync + 'a>>,
+    exclusive: bool,
 }
 
 impl<'a> System<'a> {
     /// Creates a system with data handler.
     pub fn new(handler: &'a mut impl SystemHandler) -> Self {
         Self {
-            handler: Box::new(handler),
+            handler,
             components: Default::default(),
+            condition: None,
+            exclusive: false,
         }
     }
 
+    /// Makes the system require exclusive access to the whole storage.
+    /// The system is run via [SystemHandler::run_exclusive] after all the preceding systems complete
+    /// and before any of the subsequent ones start. Declared components are ignored.
+    pub fn with_exclusive(mut self) -> Self {
+        self.exclusive = true;
+        self
+    }
+
+    /// Makes the system run only if `condition` returns `true`. The condition is checked
+    /// on every dispatch, the handler is not invoked and no data is prepared otherwise.
+    pub fn run_if(mut self, condition: impl Fn() -> bool + Send + Sync + 'a) -> Self {
+        self.condition = Some(Box::new(condition));
+        self
+    }
+
+    fn should_run(&self) -> bool {
+        self.condition.as_ref().is_none_or(|condition| condition())
+    }
+
     /// Makes component accessible from the system.
+    /// If the component is already declared mutable, it stays mutable.
     pub fn with<C: Component>(mut self) -> Self {
-        self.components.insert(TypeId::of::<C>(), false);
+        self.components.entry(TypeId::of::<C>()).or_insert(false);
         self
     }
 
@@ -47,6 +77,23 @@ impl<'a> System<'a> {
         self.components.insert(TypeId::of::<C>(), true);
         self
     }
+
+    /// Makes all components of the archetype `S` immutably accessible from the system.
+    /// This allows reading whole states of `S` via [SystemAccess::state].
+    pub fn with_archetype<S: StaticArchetype>(mut self) -> Self {
+        for ty in S::component_type_ids() {
+            self.components.entry(ty).or_insert(false);
+        }
+        self
+    }
+
+    /// Makes all components of the archetype `S` mutably accessible from the system.
+    pub fn with_archetype_mut<S: StaticArchetype>(mut self) -> Self {
+        for ty in S::component_type_ids() {
+            self.components.insert(ty, true);
+        }
+        self
+    }
 }
 
 /// Represents all available components to a system.
@@ -91,45 +138,95 @@ impl<'a> SystemAccess<'a> {
     }
 
     /// Returns `ArchetypeId` corresponding to the specified `TypeId`.
+    #[inline]
     pub fn type_id_to_archetype_id(&self, type_id: &TypeId) -> Option<ArchetypeId> {
         self.storage.type_id_to_archetype_id(type_id)
     }
 
+    /// Returns an iterator over all entities of the specified archetype.
+    /// Entity ids aren't component data, so this doesn't require any component to be declared.
+    pub fn archetype_entities(&self, arch_id: ArchetypeId) -> impl Iterator<Item = EntityId> + '_ {
+        self.storage
+            .archetypes
+            .get(arch_id as usize)
+            .into_iter()
+            .flat_map(move |arch| {
+                arch.entities
+                    .iter()
+                    .map(move |id: ArchEntityId| EntityId::new(arch_id, id))
+            })
+    }
+
+    /// Returns a reference to the whole state of `entity`. Returns `None` if the entity doesn't exist
+    /// or wasn't added as a state of the archetype `S`.
+    ///
+    /// Panics if any component of `S` is not available to this system or is declared as mutable.
+    /// Reading a whole state is only safe if none of its components can be mutated concurrently:
+    /// immutable declarations guarantee that neither this system (see [component_mut](Self::component_mut))
+    /// nor any system running in parallel with it is able to mutate these components.
+    pub fn state<S: StaticArchetype>(&self, entity: &EntityId) -> Option<&S> {
+        for ty in S::component_type_ids() {
+            let generic = self.get_component(ty).expect("Component must be available");
+            if generic.borrow().mutable {
+                panic!("Component must be declared immutable to read the whole state");
+            }
+        }
+
+        let arch = self.storage.archetypes.get(entity.archetype_id as usize)?;
+        if !arch.is_state_of::<S>(entity.id) {
+            return None;
+        }
+        // Safety: the state is of type `S`.
+        unsafe { Some(&*arch.state_of_ptr(entity.id)?) }
+    }
+
     /// Borrows the component.
     /// Panics if the component is mutably borrowed or not available to this system.
-    pub fn component<C: Component>(&self) -> GlobalComponentAccess<C> {
+    pub fn component<C: Component>(&self) -> GlobalComponentAccess<'_, C> {
+        self.try_component()
+            .expect("Component must not be mutably borrowed")
+    }
+
+    /// Borrows the component. Returns an error if the component is mutably borrowed.
+    /// Panics if the component is not available to this system.
+    pub fn try_component<C: Component>(&self) -> Result<GlobalComponentAccess<'_, C>, BorrowError> {
         let ty = TypeId::of::<C>();
 
         // This is safe because the mutable reference gets dropped afterwards.
         let generic = self.get_component(ty).expect("Component must be available");
 
-        GlobalComponentAccess {
-            generic: generic
-                .try_borrow()
-                .expect("Component must not be mutably borrowed"),
+        Ok(GlobalComponentAccess {
+            generic: generic.try_borrow()?,
             _ty: Default::default(),
-        }
+        })
     }
 
     /// Mutably borrows the component.
     /// Panics if the component is already borrowed or not available to this system.
     pub fn component_mut<'b, C: Component>(&'b self) -> GlobalComponentAccessMut<'a, 'b, C> {
+        self.try_component_mut()
+            .expect("Component must not be borrowed")
+    }
+
+    /// Mutably borrows the component. Returns an error if the component is already borrowed.
+    /// Panics if the component is not available to this system or is not allowed to be mutated.
+    pub fn try_component_mut<'b, C: Component>(
+        &'b self,
+    ) -> Result<GlobalComponentAccessMut<'a, 'b, C>, BorrowMutError> {
         let generic = self
             .get_component(TypeId::of::<C>())
             .expect("Component must be available");
 
-        let guard = generic
-            .try_borrow_mut()
-            .expect("Component must not be borrowed");
+        let guard = generic.try_borrow_mut()?;
 
         if !guard.mutable {
             panic!("Component is not allowed to be mutated");
         }
 
-        GlobalComponentAccessMut {
+        Ok(GlobalComponentAccessMut {
             generic: guard,
             _ty: Default::default(),
-        }
+        })
     }
 }
 
@@ -150,8 +247,8 @@ mod parallel {
     impl ParallelSystems {
         fn take(&mut self) -> Self {
             Self {
-                systems: mem::replace(&mut self.systems, vec![]),
-                all_components: mem::replace(&mut self.all_components, Default::default()),
+                systems: mem::take(&mut self.systems),
+                all_components: mem::take(&mut self.all_components),
             }
         }
 
@@ -183,7 +280,7 @@ mod parallel {
         a_components.iter().any(|(ty, mutable_a)| {
             b_components
                 .get(ty)
-                .map_or(false, |mutable_b| *mutable_a || *mutable_b)
+                .is_some_and(|mutable_b| *mutable_a || *mutable_b)
         })
     }
 
@@ -301,7 +398,7 @@ impl EntityStorage {
         &self,
         ty: TypeId,
         mutable: bool,
-    ) -> GenericComponentGlobalAccess {
+    ) -> GenericComponentGlobalAccess<'_> {
         let filtered_archetype_ids: Vec<usize> = self
             .component_to_archetypes_map
             .get(&ty)
@@ -315,7 +412,10 @@ impl EntityStorage {
     }
 
     /// Safety: the same component aren't allowed to be mutated on different threads simultaneously.
-    unsafe fn get_system_data(&self, components: &HashMap<TypeId, CompMutability>) -> SystemAccess {
+    unsafe fn get_system_data(
+        &self,
+        components: &HashMap<TypeId, CompMutability>,
+    ) -> SystemAccess<'_> {
         let global_components = components
             .iter()
             .map(|(&ty, mutable)| {
@@ -335,7 +435,7 @@ impl EntityStorage {
     }
 
     /// Provides access to all components. Allows simultaneous mutable access to multiple components.
-    pub fn access(&mut self) -> SystemAccess {
+    pub fn access(&mut self) -> SystemAccess<'_> {
         SystemAccess {
             storage: self,
             // Safety: `self` is &mut, therefore this is valid.
@@ -349,47 +449,64 @@ impl EntityStorage {
     /// Dispatches systems sequentially. For parallel execution,
     /// see [dispatch_par](Self::dispatch_par) (requires `rayon` feature).
     ///
+    /// The systems are run one after another in the order of the slice.
+    /// Exclusive systems (see [System::with_exclusive]) are run with the storage borrowed mutably. Handlers are borrowed mutably,
+    /// so a handler can accumulate results (counts, collision lists, etc.) in its own fields,
+    /// which can be read after `dispatch` returns.
+    ///
     /// # Example
     /// ```
     /// use entity_data::{EntityId, EntityStorage, System, SystemHandler};
     /// use entity_data::system::SystemAccess;
     /// use macros::Archetype;
     ///
-    /// #[derive(Default, Debug)]
-    /// struct Position {
-    ///     x: f32,
-    ///     y: f32,
-    /// }
+    /// #[derive(Default, Debug, PartialEq)]
+    /// struct Position(f32);
+    ///
+    /// struct Velocity(f32);
     ///
     /// #[derive(Archetype)]
     /// struct Dog {
     ///     pos: Position,
+    ///     vel: Velocity,
     /// }
     ///
     /// let mut storage = EntityStorage::new();
-    /// let dog0 = storage.add(Dog { pos: Default::default() });
-    /// let dog1 = storage.add(Dog { pos: Position { x: 3.0, y: 5.0 } });
+    /// let dog0 = storage.add(Dog { pos: Position(0.0), vel: Velocity(1.0) });
+    /// let dog1 = storage.add(Dog { pos: Position(3.0), vel: Velocity(-2.0) });
     ///
-    /// struct PositionsPrintSystem {
-    ///     to_process: Vec<EntityId>,
+    /// struct MoveSystem {
+    ///     dt: f32,
+    ///     moved: Vec<EntityId>,
     /// }
     ///
-    /// impl SystemHandler for PositionsPrintSystem {
+    /// impl SystemHandler for MoveSystem {
     ///     fn run(&mut self, data: SystemAccess) {
-    ///         let positions = data.component::<Position>();
-    ///         for entity in &self.to_process {
-    ///             println!("{:?}", positions.get(entity));
+    ///         let velocities = data.component::<Velocity>();
+    ///         let mut positions = data.component_mut::<Position>();
+    ///         for (entity, pos) in positions.iter_mut_with_ids() {
+    ///             pos.0 += velocities.get(&entity).unwrap().0 * self.dt;
+    ///             self.moved.push(entity);
     ///         }
     ///     }
     /// }
     ///
-    /// let mut sys = PositionsPrintSystem {
-    ///     to_process: vec![dog0, dog1]
-    /// };
-    /// storage.dispatch(&mut [System::new(&mut sys).with::<Position>()]);
+    /// let mut sys = MoveSystem { dt: 0.5, moved: vec![] };
+    /// storage.dispatch(&mut [System::new(&mut sys).with::<Velocity>().with_mut::<Position>()]);
+    ///
+    /// assert_eq!(sys.moved.len(), 2);
+    /// assert_eq!(storage.get::<Position>(&dog0), Some(&Position(0.5)));
+    /// assert_eq!(storage.get::<Position>(&dog1), Some(&Position(2.0)));
     /// ```
-    pub fn dispatch<'a>(&self, mut systems: impl AsMut<[System<'a>]>) {
+    pub fn dispatch<'a>(&mut self, mut systems: impl AsMut<[System<'a>]>) {
         for sys in systems.as_mut() {
+            if !sys.should_run() {
+                continue;
+            }
+            if sys.exclusive {
+                sys.handler.run_exclusive(self);
+                continue;
+            }
             let data = unsafe { self.get_system_data(&sys.components) };
             sys.handler.run(data);
         }
@@ -397,31 +514,83 @@ impl EntityStorage {
 
     /// Dispatches systems in parallel if possible. Two systems won't execute in parallel if they
     /// access the same component and one of the systems mutates this component.
+    ///
+    /// Unlike [dispatch](Self::dispatch), the order of execution of non-conflicting systems
+    /// is not specified. Results accumulated by each handler in its own fields are still
+    /// available after the call, but must not depend on the order in which the systems are run.
+    ///
+    /// An exclusive system (see [System::with_exclusive]) splits the slice: the systems preceding it
+    /// complete before it is run, and the systems following it are started after it returns.
+    ///
+    /// # Panics
+    /// If a system panics, the systems running alongside it are completed, the remaining ones
+    /// are skipped and the panic of the first failed system is resumed on the calling thread.
+    #[cfg(feature = "rayon")]
+    pub fn dispatch_par<'a>(&mut self, mut systems: impl AsMut<[System<'a>]>) {
+        let mut systems = systems.as_mut();
+
+        while !systems.is_empty() {
+            let n_shared = systems
+                .iter()
+                .position(|sys| sys.exclusive)
+                .unwrap_or(systems.len());
+            let (shared, rest) = systems.split_at_mut(n_shared);
+            self.dispatch_shared_par(shared);
+
+            let Some((exclusive, rest)) = rest.split_first_mut() else {
+                break;
+            };
+            if exclusive.should_run() {
+                exclusive.handler.run_exclusive(self);
+            }
+            systems = rest;
+        }
+    }
+
+    /// Dispatches non-exclusive systems in parallel.
     #[cfg(feature = "rayon")]
-    pub fn dispatch_par<'a>(&self, mut systems: impl AsMut<[System<'a>]>) {
-        let systems = systems.as_mut();
+    fn dispatch_shared_par(&self, systems: &mut [System]) {
+        use std::panic::{self, AssertUnwindSafe};
+        use std::sync::Mutex;
 
         if systems.is_empty() {
             return;
         }
 
         let parallel_runs = parallel::partition_parallel_systems(systems);
+        let mut systems: Vec<_> = systems.iter_mut().map(Some).collect();
 
-        rayon::scope(|s| {
-            for mut run in parallel_runs {
-                for sys_i in &mut run.systems {
-                    let system = &systems[*sys_i];
+        // Systems within a single run don't conflict with each other,
+        // but different runs must be executed one after another.
+        for run in parallel_runs {
+            let first_panic = Mutex::new(None);
 
-                    // The cast from *const to *mut is safe because the slice itself is &mut.
-                    let system_mut: &mut System = unsafe { &mut *(system as *const _ as *mut _) };
+            rayon::scope(|s| {
+                for sys_i in run.systems {
+                    let system = systems[sys_i].take().unwrap();
+                    let first_panic = &first_panic;
 
-                    s.spawn(|_| {
-                        let data = unsafe { self.get_system_data(&system.components) };
-                        system_mut.handler.run(data);
+                    s.spawn(move |_| {
+                        if !system.should_run() {
+                            return;
+                        }
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            let data = unsafe { self.get_system_data(&system.components) };
+                            system.handler.run(data);
+                        }));
+                        if let Err(payload) = result {
+                            let mut first_panic =
+                                first_panic.lock().unwrap_or_else(|e| e.into_inner());
+                            first_panic.get_or_insert(payload);
+                        }
                     });
                 }
+            });
+
+            if let Some(payload) = first_panic.into_inner().unwrap_or_else(|e| e.into_inner()) {
+                panic::resume_unwind(payload);
             }
-        });
+        }
     }
 }
 
@@ -470,24 +639,15 @@ fn test_optimization() {
         .with_mut::<i16>()
         .with_mut::<u64>();
 
-    let mut systems = [sys0, sys1, sys2, sys3, sys4];
-    let parallel_runs = parallel::partition_parallel_systems(&mut systems);
+    let systems = [sys0, sys1, sys2, sys3, sys4];
+    let parallel_runs = parallel::partition_parallel_systems(&systems);
 
     assert_eq!(systems.len(), 5);
     assert_eq!(parallel_runs.len(), 3);
 
-    assert_eq!(
-        &parallel_runs[0].systems.iter().cloned().collect::<Vec<_>>(),
-        &[1, 4]
-    );
-    assert_eq!(
-        &parallel_runs[1].systems.iter().cloned().collect::<Vec<_>>(),
-        &[2]
-    );
-    assert_eq!(
-        &parallel_runs[2].systems.iter().cloned().collect::<Vec<_>>(),
-        &[3, 0]
-    );
+    assert_eq!(&parallel_runs[0].systems, &[1, 4]);
+    assert_eq!(&parallel_runs[1].systems, &[2]);
+    assert_eq!(&parallel_runs[2].systems, &[3, 0]);
 
     for run in &parallel_runs {
         let conflicting = run.systems.iter().enumerate().any(|(i, sys0_id)| {
@@ -502,8 +662,62 @@ fn test_optimization() {
             })
         });
 
-        assert_eq!(conflicting, false);
+        assert!(!conflicting);
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_archetype_declarations() {
+    #[derive(Clone, crate::Archetype)]
+    struct Arch {
+        comp: i16,
+        other: u8,
+    }
+
+    struct TestSystem {}
+
+    impl SystemHandler for TestSystem {
+        fn run(&mut self, _: SystemAccess) {}
     }
+
+    let mut test_sys0 = TestSystem {};
+    let mut test_sys1 = TestSystem {};
+    let mut test_sys2 = TestSystem {};
+
+    // Mutable declaration wins regardless of the order
+    let sys0 = System::new(&mut test_sys0)
+        .with_archetype_mut::<Arch>()
+        .with::<i16>()
+        .with_archetype::<Arch>();
+    assert_eq!(sys0.components.len(), 2);
+    assert!(sys0.components.values().all(|mutable| *mutable));
+
+    let sys1 = System::new(&mut test_sys1).with::<u8>();
+    let sys2 = System::new(&mut test_sys2).with_archetype::<Arch>();
+
+    assert!(parallel::systems_do_conflict(
+        &sys0.components,
+        &sys1.components
+    ));
+    assert!(!parallel::systems_do_conflict(
+        &sys1.components,
+        &sys2.components
+    ));
+
+    let systems = [sys0, sys1, sys2];
+    let parallel_runs = parallel::partition_parallel_systems(&systems);
+    assert_eq!(parallel_runs.len(), 2);
+
+    let run_of = |sys_id: usize| {
+        parallel_runs
+            .iter()
+            .position(|run| run.systems.contains(&sys_id))
+            .unwrap()
+    };
+    assert_ne!(run_of(0), run_of(1));
+    assert_ne!(run_of(0), run_of(2));
+    assert_eq!(run_of(1), run_of(2));
 }
 
 #[test]
@@ -540,3 +754,366 @@ fn test_system_data_access() {
 
     assert_eq!(*storage.get::<i16>(&entity).unwrap(), 321);
 }
+
+#[test]
+fn test_system_component_iter() {
+    #[derive(Clone, crate::Archetype)]
+    struct Arch0 {
+        comp: i16,
+    }
+
+    #[derive(Clone, crate::Archetype)]
+    struct Arch1 {
+        comp: i16,
+        other: u8,
+    }
+
+    let mut storage = EntityStorage::new();
+    for i in 0..5 {
+        storage.add(Arch0 { comp: i });
+    }
+    let removed = storage.add(Arch1 { comp: 5, other: 0 });
+    for i in 6..10 {
+        storage.add(Arch1 { comp: i, other: 0 });
+    }
+    storage.remove(&removed);
+
+    let mut test_sys = |data: SystemAccess| {
+        let mut comps = data.component_mut::<i16>();
+
+        let iter = comps.iter_mut();
+        assert_eq!(iter.len(), 9);
+        for comp in iter {
+            *comp *= 2;
+        }
+    };
+    storage.dispatch(&mut [System::new(&mut test_sys).with_mut::<i16>()]);
+
+    let mut test_sys = |data: SystemAccess| {
+        let comps = data.component::<i16>();
+
+        let iter = comps.iter();
+        assert_eq!(iter.size_hint(), (9, Some(9)));
+
+        let mut values: Vec<_> = iter.cloned().collect();
+        values.sort();
+        assert_eq!(values, [0, 2, 4, 6, 8, 12, 14, 16, 18]);
+    };
+    storage.dispatch(&mut [System::new(&mut test_sys).with::<i16>()]);
+}
+
+#[test]
+fn test_system_state_access() {
+    #[derive(Clone, crate::Archetype)]
+    struct Arch0 {
+        comp: i16,
+        other: u8,
+    }
+
+    #[derive(Clone, crate::Archetype)]
+    struct Arch1 {
+        comp: i16,
+    }
+
+    let mut storage = EntityStorage::new();
+    for i in 0..5 {
+        storage.add(Arch0 {
+            comp: i,
+            other: i as u8 * 2,
+        });
+    }
+    let other = storage.add(Arch1 { comp: 10 });
+    let arch_id = storage
+        .type_id_to_archetype_id(&TypeId::of::<Arch0>())
+        .unwrap();
+
+    let mut test_sys = |data: SystemAccess| {
+        let mut n = 0;
+        for entity in data.archetype_entities(arch_id) {
+            let state = data.state::<Arch0>(&entity).unwrap();
+            assert_eq!(state.other, state.comp as u8 * 2);
+            n += 1;
+        }
+        assert_eq!(n, 5);
+
+        assert!(data.state::<Arch0>(&other).is_none());
+        assert_eq!(data.archetype_entities(100).count(), 0);
+
+        // Components of the archetype are still accessible separately
+        assert_eq!(data.component::<i16>().iter().count(), 6);
+    };
+    storage.dispatch(&mut [System::new(&mut test_sys).with_archetype::<Arch0>()]);
+
+    let mut test_sys = |data: SystemAccess| {
+        let entity = data.archetype_entities(arch_id).next().unwrap();
+        data.state::<Arch0>(&entity);
+    };
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        storage.dispatch(&mut [System::new(&mut test_sys)
+            .with_archetype::<Arch0>()
+            .with_mut::<u8>()]);
+    }));
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_system_try_component() {
+    #[derive(Clone, crate::Archetype)]
+    struct Arch {
+        comp: i16,
+        other: u8,
+    }
+
+    let mut storage = EntityStorage::new();
+    storage.add(Arch { comp: 1, other: 2 });
+
+    let mut test_sys = |data: SystemAccess| {
+        {
+            let comps = data.try_component_mut::<i16>().unwrap();
+            assert_eq!(comps.iter().count(), 1);
+
+            assert!(data.try_component::<i16>().is_err());
+            assert!(data.try_component_mut::<i16>().is_err());
+            assert!(data.try_component::<u8>().is_ok());
+        }
+
+        let comps = data.try_component::<i16>().unwrap();
+        assert!(data.try_component::<i16>().is_ok());
+        assert!(data.try_component_mut::<i16>().is_err());
+        drop(comps);
+
+        assert!(data.try_component_mut::<i16>().is_ok());
+    };
+    storage.dispatch(&mut [System::new(&mut test_sys).with_mut::<i16>().with::<u8>()]);
+}
+
+#[test]
+fn test_system_run_if() {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    let mut storage = EntityStorage::new();
+    let enabled = AtomicBool::new(false);
+    let runs = AtomicUsize::new(0);
+
+    let mut sys = |_: SystemAccess| {
+        runs.fetch_add(1, Ordering::Relaxed);
+    };
+    let mut systems = [System::new(&mut sys).run_if(|| enabled.load(Ordering::Relaxed))];
+
+    storage.dispatch(&mut systems);
+    assert_eq!(runs.load(Ordering::Relaxed), 0);
+
+    enabled.store(true, Ordering::Relaxed);
+    storage.dispatch(&mut systems);
+    assert_eq!(runs.load(Ordering::Relaxed), 1);
+
+    #[cfg(feature = "rayon")]
+    {
+        storage.dispatch_par(&mut systems);
+        assert_eq!(runs.load(Ordering::Relaxed), 2);
+
+        enabled.store(false, Ordering::Relaxed);
+        storage.dispatch_par(&mut systems);
+        assert_eq!(runs.load(Ordering::Relaxed), 2);
+    }
+}
+
+#[test]
+fn test_system_result_accumulation() {
+    #[derive(Clone, crate::Archetype)]
+    struct Arch {
+        comp: i16,
+    }
+
+    struct CountSystem {
+        count: usize,
+    }
+
+    impl SystemHandler for CountSystem {
+        fn run(&mut self, data: SystemAccess) {
+            self.count += data.component::<i16>().iter().count();
+        }
+    }
+
+    let mut storage = EntityStorage::new();
+    for i in 0..10 {
+        storage.add(Arch { comp: i });
+    }
+
+    // Systems are run sequentially in slice order
+    let order = std::sync::Mutex::new(Vec::new());
+    let mut first = |_: SystemAccess| order.lock().unwrap().push(1);
+    let mut counter = CountSystem { count: 0 };
+    let mut last = |_: SystemAccess| order.lock().unwrap().push(2);
+
+    storage.dispatch(&mut [
+        System::new(&mut first),
+        System::new(&mut counter).with::<i16>(),
+        System::new(&mut last),
+    ]);
+    assert_eq!(counter.count, 10);
+    assert_eq!(*order.lock().unwrap(), [1, 2]);
+}
+
+#[test]
+fn test_iter_mut_where() {
+    #[derive(Clone, crate::Archetype)]
+    struct Arch1 {
+        comp: i32,
+    }
+
+    #[derive(Clone, crate::Archetype)]
+    struct Arch2 {
+        comp: i32,
+        other: u8,
+    }
+
+    let mut storage = EntityStorage::new();
+    for i in 0..10 {
+        storage.add(Arch1 { comp: i });
+        storage.add(Arch2 { comp: i, other: 0 });
+    }
+
+    let arch = storage.get_archetype_mut::<Arch1>().unwrap();
+    for comp in arch
+        .component_mut::<i32>()
+        .unwrap()
+        .iter_mut_where(|v| *v >= 5)
+    {
+        *comp += 100;
+    }
+
+    let access = storage.access();
+    let mut comps = access.component_mut::<i32>();
+    let n_changed = comps
+        .iter_mut_where(|v| *v > 7)
+        .map(|comp| *comp += 1000)
+        .count();
+    assert_eq!(n_changed, 5 + 2);
+
+    let mut values: Vec<_> = comps.iter().copied().collect();
+    values.sort();
+    let mut expected: Vec<_> = (0..10)
+        .flat_map(|i| {
+            let arch1 = if i >= 5 { i + 1100 } else { i };
+            let arch2 = if i > 7 { i + 1000 } else { i };
+            [arch1, arch2]
+        })
+        .collect();
+    expected.sort();
+    assert_eq!(values, expected);
+}
+
+#[test]
+fn test_exclusive_system() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Clone, crate::Archetype)]
+    struct Arch {
+        comp: i16,
+    }
+
+    struct SpawnSystem;
+
+    impl SystemHandler for SpawnSystem {
+        fn run(&mut self, _: SystemAccess) {
+            unreachable!()
+        }
+
+        fn run_exclusive(&mut self, storage: &mut EntityStorage) {
+            storage.add(Arch { comp: 1 });
+        }
+    }
+
+    let mut storage = EntityStorage::new();
+    storage.add(Arch { comp: 0 });
+
+    let before = AtomicUsize::new(0);
+    let after = AtomicUsize::new(0);
+    let mut count_before = |data: SystemAccess| {
+        before.fetch_add(data.component::<i16>().iter().count(), Ordering::Relaxed);
+    };
+    let mut spawn = SpawnSystem;
+    let mut count_after = |data: SystemAccess| {
+        after.fetch_add(data.component::<i16>().iter().count(), Ordering::Relaxed);
+    };
+    let mut increment = |data: SystemAccess| {
+        for comp in data.component_mut::<i16>().iter_mut() {
+            *comp += 1;
+        }
+    };
+    let mut systems = [
+        System::new(&mut count_before).with::<i16>(),
+        System::new(&mut spawn).with_exclusive(),
+        System::new(&mut count_after).with::<i16>(),
+        System::new(&mut increment).with_exclusive(),
+    ];
+
+    storage.dispatch(&mut systems);
+    assert_eq!(before.load(Ordering::Relaxed), 1);
+    assert_eq!(after.load(Ordering::Relaxed), 2);
+    assert_eq!(storage.count_entities(), 2);
+
+    #[cfg(feature = "rayon")]
+    {
+        storage.dispatch_par(&mut systems);
+        assert_eq!(before.load(Ordering::Relaxed), 3);
+        assert_eq!(after.load(Ordering::Relaxed), 5);
+        assert_eq!(storage.count_entities(), 3);
+    }
+
+    let sum: i16 = storage.iter_components::<i16>().map(|(_, c)| *c).sum();
+    #[cfg(not(feature = "rayon"))]
+    assert_eq!(sum, 1 + 2);
+    #[cfg(feature = "rayon")]
+    assert_eq!(sum, 2 + 3 + 2);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_dispatch_par_panic() {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let mut storage = EntityStorage::new();
+    let ran_after = AtomicBool::new(false);
+
+    let mut panicking = |_: SystemAccess| panic!("system failed");
+    let mut other = |_: SystemAccess| {};
+    let mut after = |_: SystemAccess| ran_after.store(true, Ordering::Relaxed);
+    let mut systems = [
+        System::new(&mut panicking).with_mut::<i16>(),
+        System::new(&mut other).with::<i32>(),
+        System::new(&mut after).with::<i16>(),
+    ];
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| storage.dispatch_par(&mut systems)));
+    let payload = result.unwrap_err();
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"system failed"));
+    assert!(!ran_after.load(Ordering::Relaxed));
+}
+
+#[test]
+fn test_never_spawned_component() {
+    struct NeverSpawned;
+
+    #[derive(crate::Archetype)]
+    struct Arch(i16);
+
+    let mut storage = EntityStorage::new();
+    storage.add(Arch(0));
+
+    let mut system = |data: SystemAccess| {
+        let comps = data.component::<NeverSpawned>();
+        assert!(comps.is_empty());
+        assert_eq!(comps.iter().count(), 0);
+        assert_eq!(comps.entity_ids().len(), 0);
+
+        let mut comps = data.component_mut::<i16>();
+        assert!(!comps.is_empty());
+        assert_eq!(comps.iter_mut().count(), 1);
+    };
+    storage.dispatch(&mut [System::new(&mut system)
+        .with::<NeverSpawned>()
+        .with_mut::<i16>()]);
+}
@@ -1,6 +1,11 @@
+use crate::archetype::component::{ComponentStorageRef, Iter, IterMut, IterWithIds};
+use crate::archetype::entities::EntitiesIter;
+use crate::entity::{ArchEntityId, ArchetypeId};
 use crate::{ArchetypeStorage, Component, EntityId};
 use std::cell::{Ref, RefMut};
+use std::cmp::Ordering;
 use std::marker::PhantomData;
+use std::{ptr, slice};
 
 pub(crate) type CompMutability = bool;
 
@@ -12,12 +17,89 @@ pub struct GenericComponentGlobalAccess<'a> {
 }
 
 impl GenericComponentGlobalAccess<'_> {
-    fn count_entities(&self) -> usize {
+    /// Archetypes lacking the component `C` are skipped here and by the iterators.
+    fn count_entities<C: Component>(&self) -> usize {
         self.filtered_archetype_ids
             .iter()
-            .map(|v| self.all_archetypes[*v].entities.count())
+            .map(|v| &self.all_archetypes[*v])
+            .filter(|arch| arch.component::<C>().is_some())
+            .map(|arch| arch.entities.count())
             .sum::<usize>()
     }
+
+    #[cfg(feature = "rayon")]
+    fn par_iter<C: Component>(&self) -> impl rayon::iter::ParallelIterator<Item = &C> {
+        use rayon::prelude::*;
+
+        self.filtered_archetype_ids
+            .par_iter()
+            .filter_map(|&arch_id| {
+                let comps = self.all_archetypes[arch_id].component::<C>()?;
+                Some(comps.par_iter())
+            })
+            .flatten()
+    }
+
+    /// Safety: mutable borrows must be unique.
+    #[cfg(feature = "rayon")]
+    unsafe fn par_iter_mut<C: Component>(
+        &self,
+    ) -> impl rayon::iter::ParallelIterator<Item = &mut C> {
+        use rayon::prelude::*;
+
+        self.filtered_archetype_ids
+            .par_iter()
+            .filter_map(|&arch_id| {
+                let comps = self.all_archetypes[arch_id].component::<C>()?;
+                // Safety: the caller ensures uniqueness, the archetypes are distinct.
+                Some(unsafe { comps.par_iter_mut_unsafe() })
+            })
+            .flatten()
+    }
+
+    fn iter<C: Component>(&self) -> ComponentGlobalIter<'_, C> {
+        ComponentGlobalIter {
+            filtered_archetype_ids: self.filtered_archetype_ids.iter(),
+            all_archetypes: self.all_archetypes,
+            curr_iter: None,
+            remaining_entities: self.count_entities::<C>(),
+        }
+    }
+
+    fn iter_with_ids<C: Component>(&self) -> ComponentGlobalIterWithIds<'_, C> {
+        ComponentGlobalIterWithIds {
+            filtered_archetype_ids: self.filtered_archetype_ids.iter(),
+            all_archetypes: self.all_archetypes,
+            curr_arch_id: 0,
+            curr_iter: None,
+            remaining_entities: self.count_entities::<C>(),
+        }
+    }
+
+    fn entity_ids<C: Component>(&self) -> impl ExactSizeIterator<Item = EntityId> + '_ {
+        self.iter_with_ids::<C>().map(|(entity_id, _)| entity_id)
+    }
+
+    /// Safety: mutable borrows must be unique.
+    unsafe fn iter_mut_with_ids<C: Component>(&self) -> ComponentGlobalIterMutWithIds<'_, C> {
+        ComponentGlobalIterMutWithIds {
+            filtered_archetype_ids: self.filtered_archetype_ids.iter(),
+            all_archetypes: self.all_archetypes,
+            curr_arch_id: 0,
+            curr_iter: None,
+            remaining_entities: self.count_entities::<C>(),
+        }
+    }
+
+    /// Safety: mutable borrows must be unique.
+    unsafe fn iter_mut<C: Component>(&self) -> ComponentGlobalIterMut<'_, C> {
+        ComponentGlobalIterMut {
+            filtered_archetype_ids: self.filtered_archetype_ids.iter(),
+            all_archetypes: self.all_archetypes,
+            curr_iter: None,
+            remaining_entities: self.count_entities::<C>(),
+        }
+    }
 }
 
 pub struct GlobalComponentAccess<'a, C> {
@@ -31,7 +113,7 @@ impl<'a, C: Component> GlobalComponentAccess<'a, C> {
         self.generic
             .all_archetypes
             .get(entity_id.archetype_id as usize)
-            .and_then(|v| Some(v.contains(entity_id.id)))
+            .map(|v| v.contains(entity_id.id))
             .unwrap_or(false)
     }
 
@@ -43,9 +125,152 @@ impl<'a, C: Component> GlobalComponentAccess<'a, C> {
             .get(entity_id.id)
     }
 
+    /// Returns references to the components `C` of the specified entities.
+    /// An item is `None` if the respective entity doesn't exist or doesn't have the component.
+    /// Consecutive entities of the same archetype are looked up without repeated archetype lookups,
+    /// so it is faster if the ids are grouped by archetype (e.g. sorted).
+    pub fn get_many<'b>(
+        &'b self,
+        entity_ids: &'b [EntityId],
+    ) -> impl Iterator<Item = Option<&'b C>> + 'b {
+        components_of_many(self.generic.all_archetypes, entity_ids)
+            .map(|comp| comp.and_then(|(comps, id)| comps.get(id)))
+    }
+
     /// Returns total number of entities with the component `C`.
     pub fn count_entities(&self) -> usize {
-        self.generic.count_entities()
+        self.generic.count_entities::<C>()
+    }
+
+    /// Returns `true` if there are no entities with the component `C`.
+    pub fn is_empty(&self) -> bool {
+        self.count_entities() == 0
+    }
+
+    /// Returns an iterator over all components `C`.
+    pub fn iter(&self) -> ComponentGlobalIter<'_, C> {
+        self.generic.iter()
+    }
+
+    /// Returns an iterator over all components `C` along with their entity ids.
+    pub fn iter_with_ids(&self) -> ComponentGlobalIterWithIds<'_, C> {
+        self.generic.iter_with_ids()
+    }
+
+    /// Returns an iterator over the ids of all entities with the component `C`.
+    pub fn entity_ids(&self) -> impl ExactSizeIterator<Item = EntityId> + '_ {
+        self.generic.entity_ids::<C>()
+    }
+
+    /// Returns a parallel iterator over all components `C`.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter(&self) -> impl rayon::iter::ParallelIterator<Item = &C> {
+        self.generic.par_iter()
+    }
+
+    /// Returns an iterator over the entities having both the component `C` and the component `D`
+    /// of `other`. Entities with only one of the components are skipped.
+    ///
+    /// # Panics
+    /// Panics if `other` doesn't belong to the same storage as `self`.
+    ///
+    /// # Example
+    /// ```
+    /// use entity_data::{Archetype, EntityStorage, System, SystemAccess};
+    ///
+    /// #[derive(Debug, PartialEq)]
+    /// struct Position(f32);
+    /// struct Velocity(f32);
+    ///
+    /// #[derive(Archetype)]
+    /// struct Ball(Position, Velocity);
+    /// #[derive(Archetype)]
+    /// struct Wall(Position);
+    /// #[derive(Archetype)]
+    /// struct Bird(Velocity, Position, u32);
+    ///
+    /// let mut storage = EntityStorage::new();
+    /// let ball = storage.add(Ball(Position(0.0), Velocity(1.0)));
+    /// let wall = storage.add(Wall(Position(5.0)));
+    /// let bird = storage.add(Bird(Velocity(2.0), Position(1.0), 0));
+    ///
+    /// let mut moved = vec![];
+    /// let mut sys = |data: SystemAccess| {
+    ///     let positions = data.component::<Position>();
+    ///     let mut velocities = data.component_mut::<Velocity>();
+    ///     for (entity, pos, vel) in positions.join(&mut velocities) {
+    ///         vel.0 += pos.0;
+    ///         moved.push(entity);
+    ///     }
+    /// };
+    /// storage.dispatch(&mut [System::new(&mut sys).with::<Position>().with_mut::<Velocity>()]);
+    ///
+    /// assert_eq!(moved, [ball, bird]);
+    /// assert_eq!(storage.get::<Velocity>(&bird).unwrap().0, 3.0);
+    /// assert!(storage.get::<Velocity>(&wall).is_none());
+    /// ```
+    pub fn join<'c, D: Component>(
+        &'c self,
+        other: &'c mut GlobalComponentAccessMut<'_, '_, D>,
+    ) -> impl Iterator<Item = (EntityId, &'c C, &'c mut D)> + 'c {
+        let all_archetypes = self.generic.all_archetypes;
+        let other_archetypes = other.generic.all_archetypes;
+        assert!(
+            ptr::eq(all_archetypes, other_archetypes),
+            "components of different storages can't be joined"
+        );
+
+        // Both lists of archetype ids are sorted in ascending order.
+        let ids = &self.generic.filtered_archetype_ids;
+        let other_ids = &other.generic.filtered_archetype_ids;
+        let mut common_arch_ids = Vec::with_capacity(ids.len().min(other_ids.len()));
+        let (mut i, mut j) = (0, 0);
+        while i < ids.len() && j < other_ids.len() {
+            match ids[i].cmp(&other_ids[j]) {
+                Ordering::Less => i += 1,
+                Ordering::Greater => j += 1,
+                Ordering::Equal => {
+                    common_arch_ids.push(ids[i]);
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+
+        common_arch_ids
+            .into_iter()
+            .filter_map(move |arch_id| {
+                Some((
+                    arch_id,
+                    all_archetypes[arch_id].component::<C>()?,
+                    other_archetypes[arch_id].component::<D>()?,
+                ))
+            })
+            .flat_map(|(arch_id, comps, other_comps)| {
+                comps.entity_ids().map(move |id| {
+                    let entity = EntityId::new(arch_id as ArchetypeId, id);
+                    // Safety: the entity exists. `D` is taken from `other` which is borrowed
+                    // mutably, so no other reference to `D` of this storage can exist.
+                    // `C` and `D` are distinct because they are borrowed at once
+                    // from the same storage.
+                    unsafe {
+                        (
+                            entity,
+                            comps.get_unchecked(id),
+                            other_comps.get_mut_unsafe(id),
+                        )
+                    }
+                })
+            })
+    }
+}
+
+impl<'a, 'b, C: Component> IntoIterator for &'b GlobalComponentAccess<'a, C> {
+    type Item = &'b C;
+    type IntoIter = ComponentGlobalIter<'b, C>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
     }
 }
 
@@ -60,7 +285,7 @@ impl<'a, 'b, C: Component> GlobalComponentAccessMut<'a, 'b, C> {
         self.generic
             .all_archetypes
             .get(entity_id.archetype_id as usize)
-            .and_then(|v| Some(v.contains(entity_id.id)))
+            .map(|v| v.contains(entity_id.id))
             .unwrap_or(false)
     }
 
@@ -83,8 +308,304 @@ impl<'a, 'b, C: Component> GlobalComponentAccessMut<'a, 'b, C> {
             .then(|| unsafe { comp.get_mut_unsafe(entity_id.id) })
     }
 
+    /// Returns mutable references to the components `C` of the specified entities.
+    /// An item is `None` if the respective entity doesn't exist or doesn't have the component.
+    /// Panics if the ids are not sorted in ascending order or contain duplicates.
+    pub fn get_many_mut<'c>(
+        &'c mut self,
+        entity_ids: &'c [EntityId],
+    ) -> impl Iterator<Item = Option<&'c mut C>> + 'c {
+        assert!(
+            entity_ids.windows(2).all(|w| w[0] < w[1]),
+            "Entity ids must be sorted and distinct"
+        );
+        components_of_many(self.generic.all_archetypes, entity_ids).map(|comp| {
+            let (comps, id) = comp?;
+            // Safety: `self` is borrowed mutably and the ids are distinct,
+            // so the references don't alias.
+            comps
+                .contains(id)
+                .then(|| unsafe { comps.get_mut_unsafe(id) })
+        })
+    }
+
     /// Returns total number of entities with the component `C`.
     pub fn count_entities(&self) -> usize {
-        self.generic.count_entities()
+        self.generic.count_entities::<C>()
+    }
+
+    /// Returns `true` if there are no entities with the component `C`.
+    pub fn is_empty(&self) -> bool {
+        self.count_entities() == 0
+    }
+
+    /// Returns an iterator over all components `C`.
+    pub fn iter(&self) -> ComponentGlobalIter<'_, C> {
+        self.generic.iter()
+    }
+
+    /// Returns an iterator over all components `C` along with their entity ids.
+    pub fn iter_with_ids(&self) -> ComponentGlobalIterWithIds<'_, C> {
+        self.generic.iter_with_ids()
+    }
+
+    /// Returns an iterator over the ids of all entities with the component `C`.
+    pub fn entity_ids(&self) -> impl ExactSizeIterator<Item = EntityId> + '_ {
+        self.generic.entity_ids::<C>()
+    }
+
+    /// Returns a mutable iterator over all components `C`.
+    pub fn iter_mut(&mut self) -> ComponentGlobalIterMut<'_, C> {
+        // Safety: `self` is borrowed mutably, therefore all returned references are unique.
+        unsafe { self.generic.iter_mut() }
+    }
+
+    /// Returns a mutable iterator over all components `C` along with their entity ids.
+    pub fn iter_mut_with_ids(&mut self) -> ComponentGlobalIterMutWithIds<'_, C> {
+        // Safety: `self` is borrowed mutably, therefore all returned references are unique.
+        unsafe { self.generic.iter_mut_with_ids() }
+    }
+
+    /// Returns a parallel iterator over all components `C`.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter(&self) -> impl rayon::iter::ParallelIterator<Item = &C> {
+        self.generic.par_iter()
+    }
+
+    /// Returns a parallel mutable iterator over all components `C`.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter_mut(&mut self) -> impl rayon::iter::ParallelIterator<Item = &mut C> {
+        // Safety: `self` is borrowed mutably, therefore all returned references are unique.
+        unsafe { self.generic.par_iter_mut() }
+    }
+
+    /// Returns a mutable iterator over components `C` which satisfy the predicate.
+    pub fn iter_mut_where<P>(&mut self, pred: P) -> impl Iterator<Item = &mut C>
+    where
+        P: Fn(&C) -> bool,
+    {
+        self.iter_mut().filter(move |comp| pred(comp))
     }
+
+    /// Calls a closure on every component `C` allowing to modify it.
+    pub fn for_each<F: FnMut(EntityId, &mut C)>(&mut self, mut f: F) {
+        for &arch_id in &self.generic.filtered_archetype_ids {
+            let Some(comps) = self.generic.all_archetypes[arch_id].component::<C>() else {
+                continue;
+            };
+
+            for id in comps.entities.iter() {
+                let entity = EntityId::new(arch_id as ArchetypeId, id);
+                // Safety: `self` is borrowed mutably and the entity exists.
+                f(entity, unsafe { comps.get_mut_unsafe(id) });
+            }
+        }
+    }
+
+    /// Assigns a clone of `value` to every component `C`.
+    pub fn fill(&mut self, value: C)
+    where
+        C: Clone,
+    {
+        self.for_each(|_, comp| comp.clone_from(&value));
+    }
+
+    /// Calls a closure on every component `C` in parallel allowing to modify it.
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each<F>(&mut self, f: F)
+    where
+        F: Fn(EntityId, &mut C) + Send + Sync,
+    {
+        use rayon::prelude::*;
+
+        let generic = &*self.generic;
+        generic
+            .filtered_archetype_ids
+            .par_iter()
+            .for_each(|&arch_id| {
+                let Some(comps) = generic.all_archetypes[arch_id].component::<C>() else {
+                    return;
+                };
+                // Safety: `self` is borrowed mutably, the archetypes are distinct.
+                unsafe { comps.par_iter_mut_with_ids_unsafe() }
+                    .for_each(|(id, comp)| f(EntityId::new(arch_id as ArchetypeId, id), comp));
+            });
+    }
+}
+
+impl<'a, 'b, 'c, C: Component> IntoIterator for &'c mut GlobalComponentAccessMut<'a, 'b, C> {
+    type Item = &'c mut C;
+    type IntoIter = ComponentGlobalIterMut<'c, C>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+/// Maps entities to the storages of their components `C` reusing the storage
+/// for consecutive entities of the same archetype.
+fn components_of_many<'a, C: Component>(
+    all_archetypes: &'a [ArchetypeStorage],
+    entity_ids: &'a [EntityId],
+) -> impl Iterator<Item = Option<(ComponentStorageRef<'a, C>, ArchEntityId)>> + 'a {
+    let mut cached: Option<(ArchetypeId, Option<ComponentStorageRef<'a, C>>)> = None;
+
+    entity_ids.iter().map(move |entity| {
+        let comps = match cached {
+            Some((arch_id, comps)) if arch_id == entity.archetype_id => comps,
+            _ => {
+                let comps = all_archetypes
+                    .get(entity.archetype_id as usize)
+                    .and_then(|arch| arch.component::<C>());
+                cached = Some((entity.archetype_id, comps));
+                comps
+            }
+        };
+        Some((comps?, entity.id))
+    })
 }
+
+/// An iterator over all components `C` in all archetypes.
+pub struct ComponentGlobalIter<'a, C> {
+    filtered_archetype_ids: slice::Iter<'a, usize>,
+    all_archetypes: &'a [ArchetypeStorage],
+    curr_iter: Option<Iter<'a, C, ComponentStorageRef<'a, C>>>,
+    remaining_entities: usize,
+}
+
+impl<'a, C: Component> Iterator for ComponentGlobalIter<'a, C> {
+    type Item = &'a C;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(component) = self.curr_iter.as_mut().and_then(|v| v.next()) {
+                self.remaining_entities -= 1;
+                return Some(component);
+            }
+
+            let arch_id = *self.filtered_archetype_ids.next()?;
+            let Some(storage) = self.all_archetypes[arch_id].component::<C>() else {
+                continue;
+            };
+            self.curr_iter = Some(storage.iter());
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining_entities, Some(self.remaining_entities))
+    }
+}
+
+impl<C: Component> ExactSizeIterator for ComponentGlobalIter<'_, C> {}
+
+/// A mutable iterator over all components `C` in all archetypes.
+pub struct ComponentGlobalIterMut<'a, C> {
+    filtered_archetype_ids: slice::Iter<'a, usize>,
+    all_archetypes: &'a [ArchetypeStorage],
+    curr_iter: Option<IterMut<'a, C, ComponentStorageRef<'a, C>>>,
+    remaining_entities: usize,
+}
+
+impl<'a, C: Component> Iterator for ComponentGlobalIterMut<'a, C> {
+    type Item = &'a mut C;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(component) = self.curr_iter.as_mut().and_then(|v| v.next()) {
+                self.remaining_entities -= 1;
+                return Some(component);
+            }
+
+            let arch_id = *self.filtered_archetype_ids.next()?;
+            let Some(storage) = self.all_archetypes[arch_id].component::<C>() else {
+                continue;
+            };
+            self.curr_iter = Some(IterMut {
+                entities_iter: storage.entities.iter(),
+                data: storage,
+                _ty: Default::default(),
+            });
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining_entities, Some(self.remaining_entities))
+    }
+}
+
+impl<C: Component> ExactSizeIterator for ComponentGlobalIterMut<'_, C> {}
+
+/// An iterator over all components `C` in all archetypes along with their entity ids.
+#[derive(Clone)]
+pub struct ComponentGlobalIterWithIds<'a, C> {
+    pub(crate) filtered_archetype_ids: slice::Iter<'a, usize>,
+    pub(crate) all_archetypes: &'a [ArchetypeStorage],
+    pub(crate) curr_arch_id: ArchetypeId,
+    pub(crate) curr_iter: Option<IterWithIds<'a, C>>,
+    pub(crate) remaining_entities: usize,
+}
+
+impl<'a, C: Component> Iterator for ComponentGlobalIterWithIds<'a, C> {
+    type Item = (EntityId, &'a C);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((id, component)) = self.curr_iter.as_mut().and_then(|v| v.next()) {
+                self.remaining_entities -= 1;
+                return Some((EntityId::new(self.curr_arch_id, id), component));
+            }
+
+            let arch_id = *self.filtered_archetype_ids.next()?;
+            let Some(storage) = self.all_archetypes[arch_id].component::<C>() else {
+                continue;
+            };
+            self.curr_arch_id = arch_id as ArchetypeId;
+            self.curr_iter = Some(storage.iter_with_ids());
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining_entities, Some(self.remaining_entities))
+    }
+}
+
+impl<C: Component> ExactSizeIterator for ComponentGlobalIterWithIds<'_, C> {}
+
+/// A mutable iterator over all components `C` in all archetypes along with their entity ids.
+pub struct ComponentGlobalIterMutWithIds<'a, C> {
+    filtered_archetype_ids: slice::Iter<'a, usize>,
+    all_archetypes: &'a [ArchetypeStorage],
+    curr_arch_id: ArchetypeId,
+    curr_iter: Option<(ComponentStorageRef<'a, C>, EntitiesIter<'a>)>,
+    remaining_entities: usize,
+}
+
+impl<'a, C: Component> Iterator for ComponentGlobalIterMutWithIds<'a, C> {
+    type Item = (EntityId, &'a mut C);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((comps, entities_iter)) = &mut self.curr_iter {
+                if let Some(id) = entities_iter.next() {
+                    self.remaining_entities -= 1;
+                    // Safety: the entity exists and every entity is yielded once.
+                    let component = unsafe { comps.get_mut_unsafe(id) };
+                    return Some((EntityId::new(self.curr_arch_id, id), component));
+                }
+            }
+
+            let arch_id = *self.filtered_archetype_ids.next()?;
+            let Some(storage) = self.all_archetypes[arch_id].component::<C>() else {
+                continue;
+            };
+            self.curr_arch_id = arch_id as ArchetypeId;
+            self.curr_iter = Some((storage, storage.entity_ids()));
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining_entities, Some(self.remaining_entities))
+    }
+}
+
+impl<C: Component> ExactSizeIterator for ComponentGlobalIterMutWithIds<'_, C> {}
@@ -1,6 +1,13 @@
-use crate::{Archetype, EntityStorage, StaticArchetype};
+use crate::entity_storage::MAX_CACHED_QUERIES;
+use crate::private::ArchetypeMetadata;
+use crate::{
+    Archetype, DynamicArchetypeBuilder, EntityId, EntityStorage, StaticArchetype, StorageOptions,
+};
 use rand::prelude::StdRng;
 use rand::{Rng, SeedableRng};
+use std::any::TypeId;
+use std::mem;
+use std::sync::Arc;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 struct Comp1 {
@@ -70,13 +77,13 @@ fn general() {
     let e2v = Comp2::new();
 
     let _e0 = storage.add(Archetype12 {
-        comp1: e00v.clone(),
+        comp1: e00v,
         comp2: e01v.clone(),
     });
 
     let e0 = storage.add(
         Archetype12 {
-            comp1: e00v.clone(),
+            comp1: e00v,
             comp2: e01v.clone(),
         }
         .into_any(),
@@ -85,8 +92,8 @@ fn general() {
     let temp = storage.add(Archetype3(Comp3).into_any());
     storage.remove(&temp);
 
-    let _e1 = storage.add(Archetype1 { comp1: e1v.clone() });
-    let e1 = storage.add(Archetype1 { comp1: e1v.clone() });
+    let _e1 = storage.add(Archetype1 { comp1: e1v });
+    let e1 = storage.add(Archetype1 { comp1: e1v });
     let _e2 = storage.add(Archetype2(e2v.clone()));
     let e2 = storage.add(Archetype2(e2v.clone()).into_any());
 
@@ -104,6 +111,10 @@ fn general() {
     assert_eq!(&e2v, v2);
 
     assert_eq!(storage.entry(&e1).unwrap().get::<Comp1>(), Some(&e1v));
+    assert!(storage.entry(&e0).unwrap().has::<Comp2>());
+    assert!(!storage.entry(&e1).unwrap().has::<Comp2>());
+    assert!(storage.entry_mut(&e2).unwrap().has::<Comp2>());
+    assert!(!storage.entry_mut(&e2).unwrap().has::<Comp1>());
 
     storage.remove(&_e0);
     storage.remove(&_e1);
@@ -148,3 +159,3377 @@ fn add_modify_remove_add() {
 
     assert_eq!(storage.get::<Comp1>(&e2).unwrap().a, 123);
 }
+
+#[test]
+fn len_is_empty() {
+    let mut storage = EntityStorage::new();
+    assert!(storage.is_empty());
+    assert!(storage.entities().is_empty());
+
+    let e0 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let e1 = storage.add(Archetype2(Comp2::new()));
+    let _e2 = storage.add(Archetype2(Comp2::new()).into_any());
+
+    assert_eq!(storage.len(), 3);
+    assert_eq!(storage.entities().count(), 3);
+    assert_eq!(storage.entities().iter().len(), 3);
+
+    assert!(storage.remove(&e0));
+    assert!(!storage.remove(&e0));
+    assert!(!storage.remove(&EntityId::NULL));
+    assert_eq!(storage.len(), 2);
+
+    storage.remove(&e1);
+    assert_eq!(storage.len(), 1);
+    assert!(!storage.is_empty());
+    assert_eq!(storage.entities().iter().count(), storage.len());
+}
+
+#[test]
+fn memory_report() {
+    let mut storage = EntityStorage::new();
+
+    let entities: Vec<_> = (0..10)
+        .map(|_| {
+            storage.add(Archetype1 {
+                comp1: Comp1::new(),
+            })
+        })
+        .collect();
+    storage.add(Archetype2(Comp2::new()));
+
+    storage.remove(&entities[3]);
+    storage.remove(&entities[4]);
+
+    let report = storage.memory_report();
+    assert_eq!(report.archetypes.len(), 2);
+    assert_eq!(report.total_entities, 9);
+
+    let arch1 = &report.archetypes[0];
+    assert_eq!(arch1.entity_size, std::mem::size_of::<Archetype1>());
+    assert_eq!(arch1.n_entities, 8);
+    assert_eq!(arch1.n_allocated_slots, 10);
+    assert!(arch1.data_capacity >= 10 * arch1.entity_size);
+
+    let arch2 = &report.archetypes[1];
+    assert_eq!(arch2.n_entities, 1);
+    assert_eq!(arch2.n_allocated_slots, 1);
+
+    assert_eq!(
+        report.total_data_capacity,
+        arch1.data_capacity + arch2.data_capacity
+    );
+
+    let arch1_storage = storage.get_archetype::<Archetype1>().unwrap();
+    assert_eq!(arch1_storage.data_len(), 10 * arch1.entity_size);
+    assert_eq!(arch1_storage.data_capacity(), arch1.data_capacity);
+
+    // Removing the highest entities doesn't release their slots
+    storage.remove(&entities[9]);
+    storage.remove(&entities[8]);
+    let report = storage.memory_report();
+    let arch1 = &report.archetypes[0];
+    assert_eq!(arch1.n_entities, 6);
+    assert_eq!(arch1.n_allocated_slots, 10);
+    let arch1_storage = storage.get_archetype::<Archetype1>().unwrap();
+    assert_eq!(arch1_storage.entities.allocated_slots(), 8);
+    assert_eq!(
+        arch1_storage.data_len(),
+        arch1.n_allocated_slots * arch1.entity_size
+    );
+
+    // Reused slots don't raise the mark
+    storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    assert_eq!(storage.memory_report().archetypes[0].n_allocated_slots, 10);
+}
+
+#[test]
+fn component_as_slice() {
+    let mut storage = EntityStorage::new();
+
+    let entities: Vec<_> = (0..4)
+        .map(|i| {
+            storage.add(Archetype1 {
+                comp1: Comp1 { a: i, b: [i; 4] },
+            })
+        })
+        .collect();
+    storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+
+    let arch = storage.get_archetype::<Archetype1>().unwrap();
+    let slice = arch.component::<Comp1>().unwrap().as_slice().unwrap();
+    assert_eq!(slice.iter().map(|v| v.a).collect::<Vec<_>>(), [0, 1, 2, 3]);
+
+    // Multiple components per entity
+    let arch12 = storage.get_archetype::<Archetype12>().unwrap();
+    assert!(arch12.component::<Comp1>().unwrap().as_slice().is_none());
+
+    let arch = storage.get_archetype_mut::<Archetype1>().unwrap();
+    for comp in arch
+        .component_mut::<Comp1>()
+        .unwrap()
+        .as_mut_slice()
+        .unwrap()
+    {
+        comp.a += 10;
+    }
+    assert_eq!(storage.get::<Comp1>(&entities[2]).unwrap().a, 12);
+
+    // Removal creates a hole
+    storage.remove(&entities[1]);
+    let arch = storage.get_archetype_mut::<Archetype1>().unwrap();
+    assert!(arch.component::<Comp1>().unwrap().as_slice().is_none());
+    assert!(arch
+        .component_mut::<Comp1>()
+        .unwrap()
+        .as_mut_slice()
+        .is_none());
+}
+
+#[test]
+fn dynamic_archetype() {
+    let mut storage = EntityStorage::new();
+    let e0 = storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+
+    let comp2 = Comp2::new();
+    let counter = Arc::new(());
+
+    // Packed layout with the same set of components as `Archetype12`
+    let meta12 = DynamicArchetypeBuilder::new()
+        .register::<Comp2>()
+        .register::<Comp1>()
+        .build();
+    assert_eq!(
+        meta12.size,
+        mem::size_of::<Comp1>() + mem::size_of::<Comp2>()
+    );
+
+    let mut state = vec![0_u8; meta12.size];
+    let infos = meta12.component_infos();
+    assert_eq!(infos[0].type_name(), std::any::type_name::<Comp2>());
+    assert_eq!(infos[1].size(), mem::size_of::<Comp1>());
+    unsafe {
+        let ptr = state.as_mut_ptr();
+        (ptr.add(infos[0].range.start) as *mut Comp2).write_unaligned(comp2.clone());
+        (ptr.add(infos[1].range.start) as *mut Comp1).write_unaligned(Comp1 { a: 7, b: [0; 4] });
+    }
+    let e1 = unsafe { storage.add_raw(meta12, state.as_ptr()) };
+
+    // The entity is put into the existing archetype
+    assert_eq!(e1.archetype_id, e0.archetype_id);
+    assert_eq!(storage.get::<Comp1>(&e1).unwrap().a, 7);
+    assert_eq!(storage.get::<Comp2>(&e1), Some(&comp2));
+
+    // A new aligned archetype
+    let meta = DynamicArchetypeBuilder::new()
+        .aligned()
+        .register::<u8>()
+        .register::<Arc<()>>()
+        .build();
+    assert_eq!(meta.size, 2 * mem::size_of::<usize>());
+
+    let mut state = [0_usize; 2];
+    unsafe {
+        let infos = meta.component_infos();
+        let ptr = state.as_mut_ptr() as *mut u8;
+        ptr.add(infos[0].range.start).write(5);
+        (ptr.add(infos[1].range.start) as *mut Arc<()>).write(counter.clone());
+    }
+    let e2 = unsafe { storage.add_raw(meta, state.as_ptr() as *const u8) };
+
+    assert_ne!(e2.archetype_id, e0.archetype_id);
+    assert_eq!(storage.get::<u8>(&e2), Some(&5));
+    assert_eq!(Arc::strong_count(&counter), 2);
+
+    storage.remove(&e2);
+    assert_eq!(Arc::strong_count(&counter), 1);
+    assert_eq!(storage.count_entities(), 2);
+}
+
+#[test]
+fn entries() {
+    let mut storage = EntityStorage::new();
+
+    let e0 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let e1 = storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+    let e2 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    storage.add(Archetype2(Comp2::new()));
+    storage.remove(&e0);
+
+    storage.for_each_entry_mut(|mut entry| {
+        let entity = *entry.entity();
+        if let Some(comp1) = entry.get_mut::<Comp1>() {
+            comp1.a = entity.archetype_id * 10 + entity.id;
+        }
+    });
+
+    let entries = storage.entries();
+    assert_eq!(entries.len(), 3);
+
+    let with_comp1: Vec<_> = entries
+        .filter_map(|entry| Some((*entry.entity(), entry.get::<Comp1>()?.a)))
+        .collect();
+    assert_eq!(with_comp1, [(e2, 1), (e1, 10)]);
+}
+
+#[test]
+fn archetype_stats() {
+    let mut storage = EntityStorage::new();
+
+    let entities: Vec<_> = (0..10)
+        .map(|_| {
+            storage.add(Archetype1 {
+                comp1: Comp1::new(),
+            })
+        })
+        .collect();
+    storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+
+    for entity in entities.iter().step_by(3) {
+        storage.remove(entity);
+    }
+
+    let stats = storage.archetype_stats();
+    assert_eq!(stats.len(), 2);
+
+    assert_eq!(stats[0].n_components, 1);
+    assert_eq!(stats[0].n_entities, 6);
+    // Slot 9 is removed last, so it is not a hole.
+    assert_eq!(stats[0].hole_count, 3);
+    assert!(stats[0].capacity_bytes >= 10 * mem::size_of::<Archetype1>());
+
+    assert_eq!(stats[1].n_components, 2);
+    assert_eq!(stats[1].n_entities, 1);
+    assert_eq!(stats[1].hole_count, 0);
+}
+
+#[test]
+fn for_each() {
+    let mut storage = EntityStorage::new();
+
+    let e0 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let e1 = storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+    let e2 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    storage.add(Archetype2(Comp2::new()));
+    storage.remove(&e0);
+
+    storage.for_each_mut::<Comp1, _>(|entity, comp| comp.a = entity.archetype_id + 10);
+
+    let mut visited = vec![];
+    storage.for_each::<Comp1, _>(|entity, comp| visited.push((entity, comp.a)));
+    assert_eq!(visited, [(e2, 10), (e1, 11)]);
+
+    storage.for_each::<Comp3, _>(|_, _| unreachable!());
+}
+
+#[test]
+fn entry_of_removed_entity() {
+    let mut storage = EntityStorage::new();
+
+    let e0 = storage.add(Archetype1 {
+        comp1: Comp1 { a: 1, b: [0; 4] },
+    });
+    let e1 = storage.add(Archetype1 {
+        comp1: Comp1 { a: 2, b: [0; 4] },
+    });
+    storage.remove(&e0);
+
+    assert!(storage.entry(&e0).is_none());
+    assert!(storage.entry_mut(&e0).is_none());
+    assert_eq!(storage.entry(&e1).unwrap().get::<Comp1>().unwrap().a, 2);
+
+    // Out-of-range slot
+    let invalid = EntityId::new(e1.archetype_id, 100);
+    assert!(storage.entry(&invalid).is_none());
+    assert!(storage.entry_mut(&invalid).is_none());
+
+    // The slot is reused by a new entity
+    let e2 = storage.add(Archetype1 {
+        comp1: Comp1 { a: 3, b: [0; 4] },
+    });
+    assert_eq!(e2, e0);
+    assert_eq!(storage.entry(&e2).unwrap().get::<Comp1>().unwrap().a, 3);
+    assert_eq!(storage.entry_mut(&e2).unwrap().get::<Comp1>().unwrap().a, 3);
+}
+
+#[test]
+fn iter_components_with_ids() {
+    let mut storage = EntityStorage::new();
+
+    let e0 = storage.add(Archetype1 {
+        comp1: Comp1 { a: 0, b: [0; 4] },
+    });
+    let e1 = storage.add(Archetype12 {
+        comp1: Comp1 { a: 1, b: [0; 4] },
+        comp2: Comp2::new(),
+    });
+    let e2 = storage.add(Archetype1 {
+        comp1: Comp1 { a: 2, b: [0; 4] },
+    });
+    let e3 = storage.add(Archetype1 {
+        comp1: Comp1 { a: 3, b: [0; 4] },
+    });
+    storage.add(Archetype2(Comp2::new()));
+    storage.remove(&e0);
+
+    let arch = storage.get_archetype::<Archetype1>().unwrap();
+    let local: Vec<_> = arch
+        .component::<Comp1>()
+        .unwrap()
+        .iter_with_ids()
+        .map(|(id, comp)| (id, comp.a))
+        .collect();
+    assert_eq!(local, [(e2.id, 2), (e3.id, 3)]);
+
+    let iter = storage.iter_components::<Comp1>();
+    assert_eq!(iter.len(), 3);
+
+    let global: Vec<_> = iter.map(|(entity, comp)| (entity, comp.a)).collect();
+    assert_eq!(global, [(e2, 2), (e3, 3), (e1, 1)]);
+
+    assert_eq!(storage.iter_components::<Comp3>().count(), 0);
+}
+
+#[test]
+fn set_component() {
+    let mut storage = EntityStorage::new();
+
+    let e0 = storage.add(Archetype12 {
+        comp1: Comp1 { a: 1, b: [0; 4] },
+        comp2: Comp2::new(),
+    });
+    let e1 = storage.add(Archetype2(Comp2::new()));
+
+    let prev = storage.set(&e0, Comp1 { a: 2, b: [0; 4] }).unwrap();
+    assert_eq!(prev.a, 1);
+    assert_eq!(storage.get::<Comp1>(&e0).unwrap().a, 2);
+
+    // No such component
+    let value = storage.set(&e1, Comp1 { a: 3, b: [0; 4] }).unwrap_err();
+    assert_eq!(value.a, 3);
+
+    let mut entry = storage.entry_mut(&e0).unwrap();
+    let prev = entry.set(Comp1 { a: 4, b: [0; 4] }).unwrap();
+    assert_eq!(prev.a, 2);
+    assert!(entry.set(Comp3).is_err());
+    assert_eq!(storage.get::<Comp1>(&e0).unwrap().a, 4);
+
+    // Removed entity
+    storage.remove(&e0);
+    assert!(storage.set(&e0, Comp1::new()).is_err());
+}
+
+#[test]
+fn all_entities_iter_empty_archetypes() {
+    fn check(storage: &EntityStorage, expected: &[EntityId]) {
+        let mut iter = storage.entities().iter();
+        assert_eq!(iter.len(), expected.len());
+
+        let mut visited = vec![];
+        while let Some(entity) = iter.next() {
+            visited.push(entity);
+            assert_eq!(iter.len(), expected.len() - visited.len());
+        }
+        assert_eq!(visited, expected);
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next(), None);
+    }
+
+    // No archetypes
+    let mut storage = EntityStorage::new();
+    check(&storage, &[]);
+
+    // All archetypes are empty
+    let a = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let b = storage.add(Archetype2(Comp2::new()));
+    let c = storage.add(Archetype3(Comp3));
+    storage.remove(&a);
+    storage.remove(&b);
+    storage.remove(&c);
+    check(&storage, &[]);
+
+    // Leading and trailing empty archetypes
+    let b0 = storage.add(Archetype2(Comp2::new()));
+    let b1 = storage.add(Archetype2(Comp2::new()));
+    check(&storage, &[b0, b1]);
+
+    // Interleaved empty archetypes and holes
+    let a0 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let a1 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let a2 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    storage.remove(&a1);
+    storage.remove(&b0);
+    let c0 = storage.add(Archetype3(Comp3));
+    storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+    let d = storage.entities().iter().last().unwrap();
+    storage.remove(&d);
+    check(&storage, &[a0, a2, b1, c0]);
+}
+
+#[test]
+fn zero_sized_components() {
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    #[repr(align(16))]
+    struct Aligned;
+
+    #[derive(Archetype)]
+    struct Arch {
+        comp1: Comp1,
+        tag: Comp3,
+        aligned: Aligned,
+    }
+
+    let mut storage = EntityStorage::new();
+    let entities: Vec<_> = (0..3)
+        .map(|i| {
+            storage.add(Arch {
+                comp1: Comp1 { a: i, b: [i; 4] },
+                tag: Comp3,
+                aligned: Aligned,
+            })
+        })
+        .collect();
+
+    for (i, entity) in entities.iter().enumerate() {
+        assert_eq!(storage.get::<Comp3>(entity), Some(&Comp3));
+        assert_eq!(storage.get_mut::<Comp3>(entity), Some(&mut Comp3));
+
+        let aligned = storage.get::<Aligned>(entity).unwrap();
+        assert_eq!(aligned as *const Aligned as usize % 16, 0);
+
+        assert_eq!(storage.get::<Comp1>(entity).unwrap().a, i as u32);
+    }
+
+    let arch = storage.get_archetype::<Arch>().unwrap();
+    assert_eq!(arch.component::<Comp3>().unwrap().iter().count(), 3);
+}
+
+#[test]
+fn take_replace_component() {
+    #[derive(Default)]
+    struct Inventory(Vec<Arc<()>>);
+
+    #[derive(Archetype)]
+    struct Player {
+        comp1: Comp1,
+        inventory: Inventory,
+    }
+
+    let item = Arc::new(());
+    let mut storage = EntityStorage::new();
+    let e0 = storage.add(Player {
+        comp1: Comp1::new(),
+        inventory: Inventory(vec![item.clone(), item.clone()]),
+    });
+    let e1 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+
+    let taken = storage.take_component::<Inventory>(&e0).unwrap();
+    assert_eq!(taken.0.len(), 2);
+    assert!(storage.get::<Inventory>(&e0).unwrap().0.is_empty());
+    assert_eq!(Arc::strong_count(&item), 3);
+
+    let prev = storage
+        .replace_component(&e0, Inventory(vec![item.clone()]))
+        .unwrap();
+    assert!(prev.0.is_empty());
+    assert_eq!(Arc::strong_count(&item), 4);
+
+    // No such component
+    assert!(storage.take_component::<Inventory>(&e1).is_none());
+    assert!(storage
+        .replace_component(&e1, Inventory::default())
+        .is_none());
+
+    let mut entry = storage.entry_mut(&e0).unwrap();
+    let prev = entry.replace_component(taken).unwrap();
+    assert_eq!(prev.0.len(), 1);
+    drop(prev);
+    assert_eq!(Arc::strong_count(&item), 3);
+    assert_eq!(entry.take_component::<Inventory>().unwrap().0.len(), 2);
+    assert!(entry.take_component::<u64>().is_none());
+    assert_eq!(Arc::strong_count(&item), 1);
+
+    // Removed entity
+    storage
+        .get_mut::<Inventory>(&e0)
+        .unwrap()
+        .0
+        .push(item.clone());
+    storage.remove(&e0);
+    assert_eq!(Arc::strong_count(&item), 1);
+    assert!(storage.take_component::<Inventory>(&e0).is_none());
+    assert!(storage
+        .replace_component(&e0, Inventory::default())
+        .is_none());
+}
+
+#[test]
+fn archetype_deref() {
+    #[derive(Archetype)]
+    #[archetype(deref)]
+    struct Position(Comp1);
+
+    let mut pos = Position(Comp1 { a: 1, b: [0; 4] });
+    assert_eq!(pos.a, 1);
+    pos.a = 2;
+
+    let mut storage = EntityStorage::new();
+    let entity = storage.add(pos);
+
+    let pos = storage.get_state_mut::<Position>(&entity).unwrap();
+    assert_eq!(pos.a, 2);
+    pos.b[0] = 3;
+    assert_eq!(storage.get::<Comp1>(&entity).unwrap().b[0], 3);
+}
+
+#[test]
+fn archetypes_with_component() {
+    let mut storage = EntityStorage::new();
+    storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+    storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    storage.add(Archetype2(Comp2::new()));
+
+    let arch12 = storage
+        .type_id_to_archetype_id(&TypeId::of::<Archetype12>())
+        .unwrap();
+    let arch1 = storage
+        .type_id_to_archetype_id(&TypeId::of::<Archetype1>())
+        .unwrap();
+
+    let mut with_comp1: Vec<_> = storage.archetypes_with_component::<Comp1>().collect();
+    with_comp1.sort();
+    assert_eq!(with_comp1, [arch12, arch1]);
+    assert_eq!(storage.archetypes_with_component::<Comp2>().len(), 2);
+
+    assert_eq!(storage.archetypes_with_component::<Comp3>().len(), 0);
+    assert_eq!(
+        storage
+            .archetypes_with_component_id(&TypeId::of::<u64>())
+            .count(),
+        0
+    );
+
+    for arch_id in storage.archetypes_with_component::<Comp1>() {
+        let arch = storage.get_archetype_by_id(arch_id).unwrap();
+        assert!(arch.has_component_id(&TypeId::of::<Comp1>()));
+        assert!(!arch.has_component_id(&TypeId::of::<Comp3>()));
+    }
+}
+
+#[test]
+fn archetypes_matching() {
+    let mut storage = EntityStorage::new();
+    assert_eq!(storage.archetype_generation(), 0);
+
+    let arch1 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let arch12 = storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+    let arch2 = storage.add(Archetype2(Comp2::new()));
+    assert_eq!(storage.archetype_generation(), 3);
+
+    // Adding to an existing archetype doesn't create a new one
+    storage.add(Archetype2(Comp2::new()));
+    assert_eq!(storage.archetype_generation(), 3);
+
+    let comp1 = TypeId::of::<Comp1>();
+    let comp2 = TypeId::of::<Comp2>();
+    let comp3 = TypeId::of::<Comp3>();
+
+    assert_eq!(
+        storage.archetypes_matching(&[comp1], &[]),
+        [arch1.archetype_id, arch12.archetype_id]
+    );
+    assert_eq!(
+        storage.archetypes_matching(&[comp1, comp2], &[]),
+        [arch12.archetype_id]
+    );
+    assert_eq!(
+        storage.archetypes_matching(&[comp2], &[comp1]),
+        [arch2.archetype_id]
+    );
+    assert_eq!(
+        storage.archetypes_matching(&[], &[comp2]),
+        [arch1.archetype_id]
+    );
+    assert!(storage.archetypes_matching(&[comp3], &[]).is_empty());
+
+    assert_eq!(
+        storage.archetypes_with::<(Comp2, Comp1)>(),
+        [arch12.archetype_id]
+    );
+    assert_eq!(
+        storage.archetypes_with::<(Comp2,)>(),
+        [arch12.archetype_id, arch2.archetype_id]
+    );
+
+    storage.add(Archetype3(Comp3));
+    assert_eq!(storage.archetype_generation(), 4);
+    assert_eq!(storage.archetypes_matching(&[], &[comp1, comp2]).len(), 1);
+}
+
+#[test]
+#[should_panic(
+    expected = "expected `entity_data::tests::Archetype1`, but the archetype is `entity_data::tests::Archetype12`"
+)]
+fn get_state_type_mismatch() {
+    let mut storage = EntityStorage::new();
+    let entity = storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+
+    let arch = storage.get_archetype::<Archetype12>().unwrap();
+    assert_eq!(arch.type_name(), std::any::type_name::<Archetype12>());
+
+    storage.get_state::<Archetype1>(&entity);
+}
+
+#[test]
+fn single() {
+    let mut storage = EntityStorage::new();
+    assert!(storage.single::<Archetype1>().is_none());
+
+    let e0 = storage.add(Archetype1 {
+        comp1: Comp1 { a: 1, b: [0; 4] },
+    });
+    let (entity, state) = storage.single::<Archetype1>().unwrap();
+    assert_eq!(entity, e0);
+    assert_eq!(state.comp1.a, 1);
+
+    storage.single_mut::<Archetype1>().unwrap().1.comp1.a = 2;
+    assert_eq!(storage.get::<Comp1>(&e0).unwrap().a, 2);
+
+    let e1 = storage.add(Archetype1 {
+        comp1: Comp1 { a: 3, b: [0; 4] },
+    });
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        storage.single::<Archetype1>();
+    }));
+    assert!(result.is_err());
+
+    // Removing down to one
+    storage.remove(&e0);
+    let (entity, state) = storage.single_mut::<Archetype1>().unwrap();
+    assert_eq!(entity, e1);
+    assert_eq!(state.comp1.a, 3);
+
+    storage.remove(&e1);
+    assert!(storage.single_mut::<Archetype1>().is_none());
+}
+
+#[test]
+fn with_capacity() {
+    let mut storage = EntityStorage::with_capacity(1000);
+    assert!(storage.is_empty());
+
+    let entities: Vec<_> = (0..1000)
+        .map(|i| {
+            storage.add(Archetype1 {
+                comp1: Comp1 { a: i, b: [0; 4] },
+            })
+        })
+        .collect();
+    storage.add(Archetype2(Comp2::new()));
+
+    assert_eq!(storage.len(), 1001);
+    assert_eq!(storage.n_archetypes(), 2);
+    assert_eq!(storage.get::<Comp1>(&entities[999]).unwrap().a, 999);
+}
+
+#[test]
+fn slot_generations() {
+    let mut storage = EntityStorage::new();
+    let e0 = storage.add(Archetype2(Comp2::new()));
+    let e1 = storage.add(Archetype2(Comp2::new()));
+
+    let generation = |storage: &EntityStorage, entity: EntityId| {
+        storage
+            .get_archetype_by_id(entity.archetype_id)
+            .unwrap()
+            .entities
+            .generation(entity.id)
+    };
+    assert_eq!(generation(&storage, e0), 0);
+
+    assert!(storage.remove(&e0));
+    assert!(!storage.remove(&e0));
+    assert_eq!(generation(&storage, e0), 1);
+
+    let e2 = storage.add(Archetype2(Comp2::new()));
+    assert_eq!(e2.id, e0.id);
+    storage.remove(&e2);
+    storage.remove(&e1);
+    assert_eq!(generation(&storage, e0), 2);
+    assert_eq!(generation(&storage, e1), 1);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "entity 0 (generation 1) already freed")]
+fn slot_double_free() {
+    let mut storage = EntityStorage::new();
+    let entity = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+
+    let arch = storage
+        .get_mut_archetype_by_id(entity.archetype_id)
+        .unwrap();
+    arch.entities.free(entity.id);
+    arch.entities.free(entity.id);
+}
+
+#[test]
+fn storage_into_iter() {
+    let mut storage = EntityStorage::new();
+    assert_eq!((&storage).into_iter().count(), 0);
+
+    let e0 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let e1 = storage.add(Archetype2(Comp2::new()));
+    let e2 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    storage.remove(&e0);
+
+    let iter = storage.iter();
+    assert_eq!(iter.len(), 2);
+    assert_eq!(iter.clone().count(), 2);
+
+    let mut visited = vec![];
+    for (entity, entry) in &storage {
+        assert_eq!(entry.entity(), &entity);
+        assert_eq!(entry.has::<Comp1>(), entity != e1);
+        visited.push(entity);
+    }
+    visited.sort_by_key(|v| (v.archetype_id, v.id));
+    assert_eq!(visited, [e2, e1]);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "contains multiple components of the same type")]
+fn dynamic_archetype_duplicate_components() {
+    let meta = DynamicArchetypeBuilder::new()
+        .register::<u32>()
+        .register::<u64>()
+        .register::<u32>()
+        .build();
+    let state = [0_u8; 16];
+
+    let mut storage = EntityStorage::new();
+    unsafe { storage.add_raw(meta, state.as_ptr()) };
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "multiple components of the same type")]
+fn static_archetype_aliased_duplicate_components() {
+    type Alias = Comp1;
+
+    #[derive(Archetype)]
+    struct Aliased {
+        a: Comp1,
+        b: Alias,
+    }
+
+    let mut storage = EntityStorage::new();
+    storage.add(Aliased {
+        a: Comp1::new(),
+        b: Comp1::new(),
+    });
+}
+
+#[test]
+fn component_ptr() {
+    let mut storage = EntityStorage::new();
+    let comp1 = Comp1 {
+        a: 7,
+        b: [1, 2, 3, 4],
+    };
+    let entity = storage.add(Archetype12 {
+        comp1,
+        comp2: Comp2::new(),
+    });
+    let removed = storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+    storage.remove(&removed);
+
+    let ty = TypeId::of::<Comp1>();
+    let arch = storage
+        .get_mut_archetype_by_id(entity.archetype_id)
+        .unwrap();
+    assert_eq!(arch.component_size(ty), Some(mem::size_of::<Comp1>()));
+    assert_eq!(arch.component_size(TypeId::of::<Comp3>()), None);
+
+    let ptr = arch.component_ptr(ty, entity.id).unwrap();
+    let bytes = unsafe { std::slice::from_raw_parts(ptr, mem::size_of::<Comp1>()) };
+    assert_eq!(bytes, unsafe {
+        std::slice::from_raw_parts(&comp1 as *const Comp1 as *const u8, mem::size_of::<Comp1>())
+    });
+
+    let ptr = arch.component_ptr_mut(ty, entity.id).unwrap();
+    unsafe { (*(ptr as *mut Comp1)).a = 8 };
+    assert_eq!(arch.get::<Comp1>(entity.id).unwrap().a, 8);
+
+    assert!(arch.component_ptr(ty, removed.id).is_none());
+    assert!(arch
+        .component_ptr(TypeId::of::<Comp3>(), entity.id)
+        .is_none());
+
+    unsafe {
+        let ptr = arch.get_component_ptr_by_id(entity.id, ty).unwrap();
+        assert_eq!((*(ptr as *const Comp1)).b, comp1.b);
+        let ptr = arch.get_component_mut_ptr_by_id(entity.id, ty).unwrap();
+        (*(ptr as *mut Comp1)).a = 9;
+        assert!(arch.get_component_ptr_by_id(removed.id, ty).is_none());
+        assert!(arch
+            .get_component_mut_ptr_by_id(entity.id, TypeId::of::<Comp3>())
+            .is_none());
+    }
+    assert_eq!(arch.get::<Comp1>(entity.id).unwrap().a, 9);
+}
+
+#[test]
+fn find() {
+    let mut storage = EntityStorage::new();
+    assert_eq!(storage.find::<Comp1, _>(|_| true), None);
+
+    let e0 = storage.add(Archetype1 {
+        comp1: Comp1 { a: 1, b: [0; 4] },
+    });
+    let e1 = storage.add(Archetype12 {
+        comp1: Comp1 { a: 2, b: [0; 4] },
+        comp2: Comp2::new(),
+    });
+    storage.add(Archetype2(Comp2::new()));
+
+    assert_eq!(storage.find(|c: &Comp1| c.a == 1), Some(e0));
+    assert_eq!(storage.find(|c: &Comp1| c.a == 2), Some(e1));
+    assert_eq!(storage.find(|c: &Comp1| c.a == 3), None);
+
+    let mut n_checked = 0;
+    let found = storage.find_mut(|c: &Comp1| {
+        n_checked += 1;
+        c.a == 1
+    });
+    assert_eq!(found, Some(e0));
+    assert_eq!(n_checked, 1);
+
+    storage.remove(&e0);
+    assert_eq!(storage.find(|c: &Comp1| c.a == 1), None);
+}
+
+#[test]
+fn for_each_state_mut() {
+    let mut storage = EntityStorage::new();
+    storage.for_each_state_mut(|_, _: &mut Archetype12| unreachable!());
+
+    let mut entities = vec![];
+    for i in 0..100 {
+        entities.push(storage.add(Archetype12 {
+            comp1: Comp1 { a: i, b: [0; 4] },
+            comp2: Comp2::new(),
+        }));
+    }
+    storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    for entity in entities.iter().step_by(3) {
+        storage.remove(entity);
+    }
+
+    let mut visited = vec![];
+    storage.for_each_state_mut(|entity, state: &mut Archetype12| {
+        state.comp1.b[0] = state.comp1.a * 2;
+        visited.push(entity);
+    });
+    assert_eq!(
+        visited.len(),
+        storage
+            .get_archetype::<Archetype12>()
+            .unwrap()
+            .count_entities()
+    );
+    assert!(visited.iter().all(|entity| storage.contains(entity)));
+
+    #[cfg(feature = "rayon")]
+    {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let n_visited = AtomicUsize::new(0);
+        storage.par_for_each_state_mut(|_, state: &mut Archetype12| {
+            state.comp1.b[1] += state.comp1.b[0] + 1;
+            n_visited.fetch_add(1, Ordering::Relaxed);
+        });
+        assert_eq!(n_visited.into_inner(), visited.len());
+    }
+
+    for entity in &visited {
+        let comp1 = storage.get::<Comp1>(entity).unwrap();
+        assert_eq!(comp1.b[0], comp1.a * 2);
+        #[cfg(feature = "rayon")]
+        assert_eq!(comp1.b[1], comp1.a * 2 + 1);
+    }
+}
+
+#[test]
+fn update_all() {
+    let mut storage = EntityStorage::new();
+    let mut entities = vec![];
+    for i in 0..10 {
+        entities.push(storage.add(Archetype1 {
+            comp1: Comp1 { a: i, b: [0; 4] },
+        }));
+        entities.push(storage.add(Archetype12 {
+            comp1: Comp1 { a: i, b: [0; 4] },
+            comp2: Comp2::new(),
+        }));
+    }
+    storage.add(Archetype2(Comp2::new()));
+    storage.remove(&entities[4]);
+    storage.remove(&entities[5]);
+    entities.drain(4..6);
+
+    storage.update_all(|c: &mut Comp1| c.b[0] = c.a + 1);
+
+    unsafe fn double(ptr: *mut Comp1, count: usize) {
+        for comp in std::slice::from_raw_parts_mut(ptr, count) {
+            comp.b[1] = comp.b[0] * 2;
+        }
+    }
+    unsafe { storage.update_all_unchecked(double) };
+
+    for entity in &entities {
+        let comp1 = storage.get::<Comp1>(entity).unwrap();
+        assert_eq!(comp1.b[0], comp1.a + 1);
+        assert_eq!(comp1.b[1], comp1.b[0] * 2);
+    }
+}
+
+#[test]
+fn monotonic_ids() {
+    let mut storage = EntityStorage::with_options(StorageOptions { reuse_ids: false });
+
+    let e0 = storage.add(Archetype2(Comp2::new()));
+    let e1 = storage.add(Archetype2(Comp2::new()));
+    storage.remove(&e0);
+    storage.remove(&e1);
+
+    let e2 = storage.try_add(Archetype2(Comp2::new())).unwrap();
+    assert_eq!(e2.archetype_id, e0.archetype_id);
+    assert_eq!(e2.id, 2);
+    assert!(!storage.contains(&e0));
+    assert!(!storage.contains(&e1));
+    assert!(storage.get::<Comp2>(&e1).is_none());
+    assert_eq!(storage.get::<Comp2>(&e2), Some(&Comp2::new()));
+    assert_eq!(storage.entities().iter().collect::<Vec<_>>(), [e2]);
+
+    // Ids are reused by default
+    let mut storage = EntityStorage::new();
+    let e0 = storage.add(Archetype2(Comp2::new()));
+    storage.remove(&e0);
+    assert_eq!(storage.add(Archetype2(Comp2::new())), e0);
+}
+
+#[test]
+fn get_or_insert_component() {
+    #[derive(Archetype)]
+    struct Archetype123 {
+        comp1: Comp1,
+        comp2: Comp2,
+        comp3: Comp3,
+    }
+
+    let mut storage = EntityStorage::new();
+    let e0 = storage.add(Archetype12 {
+        comp1: Comp1 { a: 1, b: [2; 4] },
+        comp2: Comp2::new(),
+    });
+    let e1 = storage.add(Archetype1 {
+        comp1: Comp1 { a: 3, b: [4; 4] },
+    });
+
+    // Already present
+    let (entity, comp1) = storage
+        .get_or_insert_component(&e0, || Comp1 { a: 100, b: [0; 4] })
+        .unwrap();
+    assert_eq!(entity, e0);
+    assert_eq!(comp1.a, 1);
+    comp1.a = 5;
+    assert_eq!(storage.get::<Comp1>(&e0).unwrap().a, 5);
+
+    // Newly inserted into an existing static archetype
+    let (new_e0, comp3) = storage.get_or_insert_component(&e0, || Comp3).unwrap();
+    assert_eq!(*comp3, Comp3);
+    assert_ne!(new_e0, e0);
+    assert!(!storage.contains(&e0));
+    assert_eq!(storage.len(), 2);
+
+    let state = storage.add(Archetype123 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+        comp3: Comp3,
+    });
+    assert_eq!(new_e0.archetype_id, state.archetype_id);
+    assert_eq!(
+        storage.get::<Comp1>(&new_e0),
+        Some(&Comp1 { a: 5, b: [2; 4] })
+    );
+    assert_eq!(storage.get::<Comp2>(&new_e0), Some(&Comp2::new()));
+
+    // Newly inserted into a new archetype
+    let item = Arc::new(());
+    let (new_e1, items) = storage
+        .get_or_insert_component(&e1, || vec![item.clone()])
+        .unwrap();
+    assert_eq!(items.len(), 1);
+    items.push(item.clone());
+    assert_eq!(
+        storage.get::<Comp1>(&new_e1),
+        Some(&Comp1 { a: 3, b: [4; 4] })
+    );
+    assert_eq!(storage.len(), 3);
+    assert_eq!(Arc::strong_count(&item), 3);
+
+    storage.remove(&new_e1);
+    assert_eq!(Arc::strong_count(&item), 1);
+
+    // Removed entity
+    assert!(storage.get_or_insert_component(&e1, || Comp3).is_none());
+}
+
+#[test]
+fn query_cache() {
+    let mut storage = EntityStorage::new();
+    let comp1 = TypeId::of::<Comp1>();
+    let comp2 = TypeId::of::<Comp2>();
+
+    assert!(storage.query(&[comp1]).is_empty());
+
+    storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+
+    let with_comp1 = storage.query(&[comp1]);
+    assert_eq!(*with_comp1, *storage.archetypes_matching(&[comp1], &[]));
+    assert_eq!(with_comp1.len(), 2);
+
+    // The same query in any order hits the cache
+    let with_both = storage.query(&[comp1, comp2]);
+    assert!(Arc::ptr_eq(&with_both, &storage.query(&[comp2, comp1])));
+    assert!(Arc::ptr_eq(&with_comp1, &storage.query(&[comp1])));
+
+    let only_comp1 = storage.query_filtered(&[comp1], &[comp2]);
+    assert_eq!(only_comp1.len(), 1);
+
+    // A new archetype invalidates the cache
+    storage.add(Archetype2(Comp2::new()));
+    assert!(storage.query_cache.read().unwrap().is_empty());
+    let with_comp2 = storage.query(&[comp2]);
+    assert_eq!(with_comp2.len(), 2);
+    assert_eq!(
+        *storage.query_filtered(&[], &[comp1]),
+        *storage.archetypes_matching(&[], &[comp1])
+    );
+    assert_eq!(*storage.query(&[comp1]), *with_comp1);
+    assert!(!Arc::ptr_eq(&with_comp1, &storage.query(&[comp1])));
+
+    // The cache doesn't grow indefinitely
+    for n in 1..1000 {
+        assert_eq!(storage.query(&vec![comp1; n]).len(), 2);
+    }
+    let n_cached = storage.query_cache.read().unwrap().len();
+    assert!(n_cached <= MAX_CACHED_QUERIES);
+}
+
+#[test]
+fn archetype_layout_set_operations() {
+    use crate::ArchetypeLayout;
+
+    let layout = |ids: &[TypeId]| ArchetypeLayout::new(ids.to_vec());
+    let (t1, t2, t3) = (
+        TypeId::of::<Comp1>(),
+        TypeId::of::<Comp2>(),
+        TypeId::of::<Comp3>(),
+    );
+
+    let l1 = layout(&[t1]);
+    let l12 = layout(&[t2, t1]);
+    let l23 = layout(&[t3, t2]);
+    let empty = layout(&[]);
+
+    assert!(l1.is_subset_of(&l12));
+    assert!(!l12.is_subset_of(&l1));
+    assert!(!l1.is_subset_of(&l23));
+    assert!(empty.is_subset_of(&l1));
+    assert!(l12.is_subset_of(&l12));
+    assert!(l12.is_superset_of(&l1));
+    assert!(!l23.is_superset_of(&l12));
+
+    assert_eq!(l12.intersection(&l23), layout(&[t2]));
+    assert_eq!(l1.intersection(&l23), empty);
+    assert_eq!(l12.union(&l23), layout(&[t1, t2, t3]));
+    assert_eq!(l1.union(&empty), l1);
+
+    let mut storage = EntityStorage::new();
+    storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+    let arch = storage.get_archetype::<Archetype12>().unwrap();
+    assert_eq!(arch.layout(), l12);
+    assert_eq!(arch.layout().type_ids().len(), 2);
+}
+
+#[test]
+fn get_state_dyn() {
+    let mut storage = EntityStorage::new();
+    let e0 = storage.add(Archetype12 {
+        comp1: Comp1 { a: 1, b: [0; 4] },
+        comp2: Comp2::new(),
+    });
+    let e1 = storage.add(Archetype3(Comp3));
+
+    let meta = DynamicArchetypeBuilder::new().register::<u32>().build();
+    let e2 = unsafe { storage.add_raw(meta, 5_u32.to_ne_bytes().as_ptr()) };
+
+    let state = storage.get_state_dyn(&e0).unwrap();
+    assert_eq!(state.downcast_ref::<Archetype12>().unwrap().comp1.a, 1);
+    assert!(state.downcast_ref::<Archetype3>().is_none());
+
+    storage
+        .get_state_dyn_mut(&e0)
+        .unwrap()
+        .downcast_mut::<Archetype12>()
+        .unwrap()
+        .comp1
+        .a = 2;
+    assert_eq!(storage.get::<Comp1>(&e0).unwrap().a, 2);
+
+    assert!(storage.get_state_dyn(&e1).unwrap().is::<Archetype3>());
+    assert!(storage.get_state_dyn(&e2).is_none());
+
+    storage.remove(&e0);
+    assert!(storage.get_state_dyn(&e0).is_none());
+}
+
+#[test]
+fn raw_component_bytes() {
+    let mut storage = EntityStorage::new();
+    let entity = storage.add(Archetype12 {
+        comp1: Comp1 {
+            a: 0x01020304,
+            b: [5, 6, 7, 8],
+        },
+        comp2: Comp2::new(),
+    });
+    let ty = TypeId::of::<Comp1>();
+
+    let bytes = storage.get_raw(&entity, ty).unwrap();
+    assert_eq!(bytes.len(), mem::size_of::<Comp1>());
+    let a_offset = mem::offset_of!(Comp1, a);
+    assert_eq!(bytes[a_offset..a_offset + 4], 0x01020304_u32.to_ne_bytes());
+
+    let bytes = storage.get_raw_mut(&entity, ty).unwrap();
+    let b_offset = mem::offset_of!(Comp1, b);
+    bytes[b_offset..b_offset + 4].copy_from_slice(&9_u32.to_ne_bytes());
+    assert_eq!(storage.get::<Comp1>(&entity).unwrap().b[0], 9);
+
+    let arch = storage.get_archetype::<Archetype12>().unwrap();
+    let info = arch
+        .iter_component_infos()
+        .find(|info| info.type_id() == ty)
+        .unwrap();
+    assert_eq!(info.size(), mem::size_of::<Comp1>());
+    assert_eq!(info.type_name(), std::any::type_name::<Comp1>());
+    assert_eq!(
+        arch.component_bytes(entity.id, ty),
+        storage.get_raw(&entity, ty)
+    );
+
+    assert!(storage.get_raw(&entity, TypeId::of::<Comp3>()).is_none());
+    storage.remove(&entity);
+    assert!(storage.get_raw(&entity, ty).is_none());
+}
+
+#[test]
+fn get_two_mut() {
+    let mut storage = EntityStorage::new();
+    let e0 = storage.add(Archetype1 {
+        comp1: Comp1 { a: 1, b: [0; 4] },
+    });
+    let e1 = storage.add(Archetype1 {
+        comp1: Comp1 { a: 2, b: [0; 4] },
+    });
+    let e2 = storage.add(Archetype12 {
+        comp1: Comp1 { a: 3, b: [0; 4] },
+        comp2: Comp2::new(),
+    });
+    let e3 = storage.add(Archetype2(Comp2::new()));
+
+    let (c0, c1) = storage.get_two_mut::<Comp1>(&e0, &e1).unwrap();
+    mem::swap(&mut c0.a, &mut c1.a);
+    assert_eq!(storage.get::<Comp1>(&e0).unwrap().a, 2);
+    assert_eq!(storage.get::<Comp1>(&e1).unwrap().a, 1);
+
+    // Different archetypes
+    let (c2, c0) = storage.get_two_mut::<Comp1>(&e2, &e0).unwrap();
+    c0.b[0] = c2.a;
+    assert_eq!(storage.get::<Comp1>(&e0).unwrap().b[0], 3);
+
+    assert!(storage.get_two_mut::<Comp1>(&e0, &e3).is_none());
+    storage.remove(&e1);
+    assert!(storage.get_two_mut::<Comp1>(&e0, &e1).is_none());
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        storage.get_two_mut::<Comp1>(&e0, &e0);
+    }));
+    assert!(result.is_err());
+}
+
+#[test]
+fn extend() {
+    let mut storage = EntityStorage::new();
+    storage.extend((0..10).map(|i| Archetype1 {
+        comp1: Comp1 { a: i, b: [0; 4] },
+    }));
+    assert_eq!(storage.len(), 10);
+
+    let arch = storage.get_archetype::<Archetype1>().unwrap();
+    assert!(arch.data_capacity() >= 10 * arch.entity_size());
+
+    // Mixed archetypes
+    let entities = storage.extend_collect(vec![Archetype2(Comp2::new()); 3]);
+    storage.extend([Archetype1 {
+        comp1: Comp1 { a: 10, b: [0; 4] },
+    }]);
+    assert_eq!(entities.len(), 3);
+    assert_eq!(storage.len(), 14);
+    assert!(entities
+        .iter()
+        .all(|entity| storage.get::<Comp2>(entity) == Some(&Comp2::new())));
+    assert_eq!(storage.iter_components::<Comp1>().count(), 11);
+
+    // Single archetype
+    let (storage, entities) = EntityStorage::from_states((0..5).map(|i| Archetype1 {
+        comp1: Comp1 { a: i, b: [0; 4] },
+    }));
+    assert_eq!(storage.len(), 5);
+    for (i, entity) in entities.iter().enumerate() {
+        assert_eq!(storage.get::<Comp1>(entity).unwrap().a, i as u32);
+    }
+}
+
+#[test]
+fn owned_entity() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let storage = Rc::new(RefCell::new(EntityStorage::new()));
+
+    let owned = EntityStorage::add_owned(&storage, Archetype2(Comp2::new()));
+    let released = EntityStorage::add_owned(
+        &storage,
+        Archetype1 {
+            comp1: Comp1::new(),
+        },
+    );
+    assert_eq!(storage.borrow().len(), 2);
+    assert!(storage.borrow().get::<Comp2>(&owned).is_some());
+
+    let entity = *owned;
+    drop(owned);
+    assert!(!storage.borrow().contains(&entity));
+
+    let released = released.into_inner();
+    assert_eq!(Rc::strong_count(&storage), 1);
+    assert!(storage.borrow().contains(&released));
+    assert_eq!(storage.borrow().len(), 1);
+}
+
+#[test]
+fn owned_entity_nested() {
+    use crate::OwnedEntity;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Child(OwnedEntity);
+    // Safety: the storage is used only on the current thread.
+    unsafe impl Send for Child {}
+    unsafe impl Sync for Child {}
+
+    #[derive(Archetype)]
+    struct Parent {
+        child: Child,
+    }
+
+    let storage = Rc::new(RefCell::new(EntityStorage::new()));
+
+    // Dropping the owner removes the owned children
+    let child = EntityStorage::add_owned(&storage, Archetype3(Comp3));
+    let child_id = *child;
+    let parent = EntityStorage::add_owned(
+        &storage,
+        Parent {
+            child: Child(child),
+        },
+    );
+    assert_eq!(storage.borrow().len(), 2);
+    assert_eq!(*storage.borrow().get::<Child>(&parent).unwrap().0, child_id);
+    drop(parent);
+    assert!(!storage.borrow().contains(&child_id));
+    assert!(storage.borrow().is_empty());
+
+    // Children dropped while the storage is borrowed are left in the storage
+    let child = EntityStorage::add_owned(&storage, Archetype3(Comp3));
+    let child_id = *child;
+    let parent = storage.borrow_mut().add(Parent {
+        child: Child(child),
+    });
+    assert!(storage.borrow_mut().remove(&parent));
+    assert!(storage.borrow().contains(&child_id));
+
+    // `take` releases the storage before the children are dropped
+    let child = EntityStorage::add_owned(&storage, Archetype3(Comp3));
+    let child_id = *child;
+    let parent = storage.borrow_mut().add(Parent {
+        child: Child(child),
+    });
+    let state = storage.borrow_mut().take::<Parent>(&parent);
+    drop(state);
+    assert!(!storage.borrow().contains(&child_id));
+
+    // The storage isn't kept alive by the entities it contains
+    let weak = Rc::downgrade(&storage);
+    let child = EntityStorage::add_owned(&storage, Archetype3(Comp3));
+    storage.borrow_mut().add(Parent {
+        child: Child(child),
+    });
+    drop(storage);
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn zero_sized_archetype() {
+    #[derive(Archetype)]
+    struct Markers(Comp3);
+
+    let mut storage = EntityStorage::new();
+
+    let entities: Vec<_> = (0..1000).map(|_| storage.add(Markers(Comp3))).collect();
+    for e in entities.iter().step_by(3) {
+        assert!(storage.remove(e));
+    }
+    let new_entities: Vec<_> = (0..100).map(|_| storage.add(Markers(Comp3))).collect();
+
+    let arch = storage.get_archetype::<Markers>().unwrap();
+    assert_eq!(arch.data_len(), 0);
+    assert_eq!(arch.count_entities(), 1000 - 334 + 100);
+
+    let alive: Vec<_> = entities
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| i % 3 != 0)
+        .map(|(_, e)| *e)
+        .chain(new_entities.iter().copied())
+        .collect();
+    for e in &alive {
+        assert!(storage.get::<Comp3>(e).is_some());
+    }
+
+    let comps = arch.component::<Comp3>().unwrap();
+    assert_eq!(comps.iter().count(), alive.len());
+    assert_eq!(storage.entities().iter().count(), alive.len());
+
+    for e in &alive {
+        assert!(storage.remove(e));
+    }
+    assert_eq!(storage.len(), 0);
+}
+
+#[test]
+fn split_off_archetype() {
+    let mut storage = EntityStorage::new();
+
+    let e1 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let e2: Vec<_> = (0..10)
+        .map(|i| {
+            let mut comp2 = Comp2::new();
+            comp2.b[0] = i;
+            storage.add(Archetype2(comp2))
+        })
+        .collect();
+    storage.remove(&e2[3]);
+
+    let chunk = storage.split_off_archetype::<Archetype2>();
+    assert_eq!(storage.len(), 1);
+    assert_eq!(chunk.len(), 9);
+    assert!(storage.contains(&e1));
+    assert!(!storage.contains(&e2[0]));
+    assert_eq!(
+        storage.type_id_to_archetype_id(&TypeId::of::<Archetype2>()),
+        Some(e2[0].archetype_id)
+    );
+
+    for (i, e) in e2.iter().enumerate() {
+        let moved = EntityId::new(0, e.id);
+        if i == 3 {
+            assert!(!chunk.contains(&moved));
+        } else {
+            assert_eq!(chunk.get::<Comp2>(&moved).unwrap().b[0], i);
+        }
+    }
+
+    // The archetype is still registered in the source storage
+    let e = storage.add(Archetype2(Comp2::new()));
+    assert_eq!(e.archetype_id, e2[0].archetype_id);
+
+    let empty = storage.split_off_archetype::<Archetype12>();
+    assert!(empty.is_empty());
+}
+
+#[test]
+fn registered_archetypes() {
+    let archetypes: [fn() -> ArchetypeMetadata; 3] = [
+        <Archetype3 as StaticArchetype>::metadata,
+        <Archetype2 as StaticArchetype>::metadata,
+        <Archetype1 as StaticArchetype>::metadata,
+    ];
+
+    let mut storage1 = EntityStorage::with_registered_archetypes(&archetypes);
+    let mut storage2 = EntityStorage::with_registered_archetypes(&archetypes);
+    assert_eq!(storage1.n_archetypes(), 3);
+
+    let a1 = storage1.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let a2 = storage1.add(Archetype2(Comp2::new()));
+    let a3 = storage1.add(Archetype3(Comp3));
+
+    let b3 = storage2.add(Archetype3(Comp3));
+    let b2 = storage2.add(Archetype2(Comp2::new()));
+    let b1 = storage2.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+
+    assert_eq!(a1.archetype_id, 2);
+    assert_eq!(a1.archetype_id, b1.archetype_id);
+    assert_eq!(a2.archetype_id, b2.archetype_id);
+    assert_eq!(a3.archetype_id, b3.archetype_id);
+
+    assert_eq!(storage1.n_archetypes(), 3);
+    assert_eq!(
+        storage1
+            .archetypes_with_component::<Comp2>()
+            .collect::<Vec<_>>(),
+        [1]
+    );
+    assert_eq!(
+        storage2
+            .archetypes_with_component::<Comp1>()
+            .collect::<Vec<_>>(),
+        [2]
+    );
+}
+
+#[test]
+fn component_type_ids() {
+    use crate::ArchetypeState;
+
+    let ids = Archetype12::component_type_ids();
+    assert_eq!(
+        ids.as_slice(),
+        [TypeId::of::<Comp1>(), TypeId::of::<Comp2>()]
+    );
+    assert_eq!(
+        ids,
+        <Archetype12 as StaticArchetype>::metadata().component_type_ids()
+    );
+
+    let state = Archetype2(Comp2::new());
+    assert_eq!(state.component_ids().as_slice(), [TypeId::of::<Comp2>()]);
+    assert_eq!(
+        state.into_any().component_ids().as_slice(),
+        [TypeId::of::<Comp2>()]
+    );
+    assert!(<() as StaticArchetype>::component_type_ids().is_empty());
+}
+
+#[test]
+fn component_alignment() {
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    #[repr(align(32))]
+    struct Aligned([f32; 3]);
+
+    #[derive(Archetype)]
+    struct Arch {
+        flag: u8,
+        aligned: Aligned,
+        id: u64,
+    }
+
+    assert_eq!(<Arch as StaticArchetype>::metadata().align, 32);
+
+    let mut storage = EntityStorage::new();
+    let entities: Vec<_> = (0..100)
+        .map(|i| {
+            storage.add(Arch {
+                flag: i as u8,
+                aligned: Aligned([i as f32; 3]),
+                id: i,
+            })
+        })
+        .collect();
+
+    for (i, e) in entities.iter().enumerate() {
+        let aligned = storage.get::<Aligned>(e).unwrap();
+        assert_eq!(aligned as *const Aligned as usize % 32, 0);
+        assert_eq!(*aligned, Aligned([i as f32; 3]));
+        assert_eq!(*storage.get::<u64>(e).unwrap(), i as u64);
+        assert_eq!(*storage.get::<u8>(e).unwrap(), i as u8);
+    }
+
+    let arch = storage.get_archetype::<Arch>().unwrap();
+    for comp in arch.component::<Aligned>().unwrap().iter() {
+        assert_eq!(comp as *const Aligned as usize % 32, 0);
+    }
+}
+
+#[test]
+fn components_of() {
+    let mut storage = EntityStorage::new();
+    let e = storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+
+    let mut types: Vec<_> = storage.components_of(&e).unwrap().collect();
+    let mut expected = Archetype12::component_type_ids().into_vec();
+    types.sort();
+    expected.sort();
+    assert_eq!(types, expected);
+
+    assert!(storage.has_component(&e, TypeId::of::<Comp1>()));
+    assert!(storage.has_component(&e, TypeId::of::<Comp2>()));
+    assert!(!storage.has_component(&e, TypeId::of::<Comp3>()));
+
+    storage.remove(&e);
+    assert!(storage.components_of(&e).is_none());
+    assert!(!storage.has_component(&e, TypeId::of::<Comp1>()));
+    assert!(storage.components_of(&EntityId::NULL).is_none());
+}
+
+#[test]
+fn component_offsets() {
+    // Declaration order differs from size order, so every component has a non-trivial offset
+    #[derive(Archetype)]
+    struct Arch {
+        small: u8,
+        big: [u64; 3],
+        medium: u16,
+    }
+
+    let mut storage = EntityStorage::new();
+    let entities: Vec<_> = (0..200_u64)
+        .map(|i| {
+            storage.add(Arch {
+                small: i as u8,
+                big: [i, i * 2, i * 3],
+                medium: i as u16 * 7,
+            })
+        })
+        .collect();
+
+    for (i, e) in entities.iter().enumerate() {
+        let i = i as u64;
+        assert_eq!(storage.get::<[u64; 3]>(e), Some(&[i, i * 2, i * 3]));
+        assert_eq!(storage.get::<u16>(e), Some(&(i as u16 * 7)));
+        *storage.get_mut::<u16>(e).unwrap() += 1;
+    }
+
+    let arch = storage.get_archetype_mut::<Arch>().unwrap();
+    for big in arch.component_mut::<[u64; 3]>().unwrap() {
+        big[2] = big[0] + 1000;
+    }
+
+    let access = storage.access();
+    let mediums = access.component::<u16>();
+    let bigs = access.component::<[u64; 3]>();
+    let smalls = access.component::<u8>();
+    for (i, e) in entities.iter().enumerate() {
+        let i = i as u64;
+        assert_eq!(smalls.get(e), Some(&(i as u8)));
+        assert_eq!(bigs.get(e), Some(&[i, i * 2, i + 1000]));
+        assert_eq!(mediums.get(e), Some(&(i as u16 * 7 + 1)));
+    }
+}
+
+#[test]
+fn sparse_components() {
+    #[derive(Debug, PartialEq)]
+    struct Tag(u32);
+
+    let counter = Arc::new(());
+
+    let mut storage = EntityStorage::new();
+    storage.register_sparse::<Tag>();
+    storage.register_sparse::<Arc<()>>();
+    assert!(storage.is_sparse::<Tag>());
+    assert!(!storage.is_sparse::<Comp1>());
+
+    let e1 = storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+    let e2 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let e3 = storage.add(Archetype2(Comp2::new()));
+
+    assert_eq!(storage.insert_sparse(&e1, Tag(1)), Ok(None));
+    assert_eq!(storage.insert_sparse(&e3, Tag(3)), Ok(None));
+    assert_eq!(storage.insert_sparse(&e3, Tag(4)), Ok(Some(Tag(3))));
+    storage.insert_sparse(&e2, counter.clone()).unwrap();
+    assert_eq!(Arc::strong_count(&counter), 2);
+
+    // Dense and sparse components of the same entity
+    assert_eq!(storage.get::<Tag>(&e1), Some(&Tag(1)));
+    assert!(storage.get::<Comp2>(&e1).is_some());
+    assert!(storage.get::<Tag>(&e2).is_none());
+    storage.get_mut::<Tag>(&e1).unwrap().0 = 10;
+    storage.get_mut::<Comp1>(&e1).unwrap().a = 5;
+
+    let mut tags: Vec<_> = storage
+        .iter_sparse::<Tag>()
+        .map(|(e, t)| (e, t.0))
+        .collect();
+    tags.sort();
+    assert_eq!(tags, [(e1, 10), (e3, 4)]);
+
+    for (_, tag) in storage.iter_sparse_mut::<Tag>() {
+        tag.0 += 1;
+    }
+    assert_eq!(storage.get::<Tag>(&e3), Some(&Tag(5)));
+    assert_eq!(storage.iter_sparse::<Comp3>().count(), 0);
+
+    // Sparse components follow the entity when it changes archetype
+    let (e2, _) = storage.get_or_insert_component(&e2, Comp2::new).unwrap();
+    assert!(storage.get::<Arc<()>>(&e2).is_some());
+
+    assert_eq!(storage.remove_sparse::<Tag>(&e3), Some(Tag(5)));
+    assert!(storage.get::<Tag>(&e3).is_none());
+
+    // Removing the entity drops its sparse components
+    storage.remove(&e2);
+    assert_eq!(Arc::strong_count(&counter), 1);
+    assert!(storage.get::<Arc<()>>(&e2).is_none());
+
+    storage.remove(&e1);
+    assert_eq!(storage.insert_sparse(&e1, Tag(0)), Err(Tag(0)));
+    assert_eq!(storage.iter_sparse::<Tag>().count(), 0);
+}
+
+#[test]
+fn entity_id_conversions() {
+    let entity = EntityId::new(3, 0xdeadbeef);
+    assert_eq!(entity.to_u64(), (3 << 32) | 0xdeadbeef);
+    assert_eq!(EntityId::from_u64(entity.to_u64()), entity);
+    assert_eq!(EntityId::NULL.to_u64(), u64::MAX);
+    assert_eq!(EntityId::from_u64(u64::MAX), EntityId::NULL);
+
+    assert_eq!(entity.to_string(), "3:3735928559");
+    assert_eq!("3:3735928559".parse(), Ok(entity));
+    assert_eq!(
+        EntityId::NULL.to_string().parse::<EntityId>(),
+        Ok(EntityId::NULL)
+    );
+    assert!("3".parse::<EntityId>().is_err());
+    assert!("3:".parse::<EntityId>().is_err());
+    assert!("a:1".parse::<EntityId>().is_err());
+    assert!("1:2:3".parse::<EntityId>().is_err());
+}
+
+#[derive(Archetype)]
+#[archetype(layout = "soa")]
+struct SoaArchetype {
+    id: u64,
+    flag: u8,
+    counter: Arc<()>,
+}
+
+#[test]
+fn soa_layout() {
+    use crate::DataLayout;
+
+    let counter = Arc::new(());
+    let mut storage = EntityStorage::new();
+
+    let entities: Vec<_> = (0..100_u64)
+        .map(|i| {
+            storage.add(SoaArchetype {
+                id: i,
+                flag: i as u8,
+                counter: counter.clone(),
+            })
+        })
+        .collect();
+    assert_eq!(Arc::strong_count(&counter), 101);
+
+    let arch = storage.get_archetype::<SoaArchetype>().unwrap();
+    assert_eq!(arch.data_layout(), DataLayout::Soa);
+    let ids = arch.component::<u64>().unwrap().as_slice().unwrap();
+    assert_eq!(ids, (0..100).collect::<Vec<_>>());
+    let flags = arch.component::<u8>().unwrap().as_slice().unwrap();
+    assert_eq!(flags[42], 42);
+
+    for (i, e) in entities.iter().enumerate() {
+        assert_eq!(storage.get::<u64>(e), Some(&(i as u64)));
+        *storage.get_mut::<u8>(e).unwrap() += 1;
+    }
+    let ty = TypeId::of::<u8>();
+    assert_eq!(storage.get_raw(&entities[5], ty), Some(&[6_u8][..]));
+
+    for e in entities.iter().step_by(2) {
+        storage.remove(e);
+    }
+    assert_eq!(Arc::strong_count(&counter), 51);
+
+    let arch = storage.get_archetype::<SoaArchetype>().unwrap();
+    assert!(arch.component::<u64>().unwrap().as_slice().is_none());
+    let sum: u64 = arch.component::<u64>().unwrap().iter().sum();
+    assert_eq!(sum, (1..100).step_by(2).sum());
+    assert!(arch.get_state_dyn(entities[1].id).is_none());
+
+    // A state with the same components, but a different layout, is scattered into the columns
+    #[derive(Archetype)]
+    struct AosArchetype {
+        counter: Arc<()>,
+        flag: u8,
+        id: u64,
+    }
+    let e = storage.add(AosArchetype {
+        counter: counter.clone(),
+        flag: 7,
+        id: 1000,
+    });
+    assert_eq!(e.archetype_id, entities[0].archetype_id);
+    assert_eq!(storage.get::<u64>(&e), Some(&1000));
+    assert_eq!(storage.get::<u8>(&e), Some(&7));
+
+    // Migration keeps the layout
+    let (e, _) = storage.get_or_insert_component(&e, || 1.5_f32).unwrap();
+    assert_eq!(storage.get::<u64>(&e), Some(&1000));
+    assert_eq!(storage.get::<f32>(&e), Some(&1.5));
+    assert_eq!(
+        storage
+            .get_archetype_by_id(e.archetype_id)
+            .unwrap()
+            .data_layout(),
+        DataLayout::Soa
+    );
+
+    drop(storage);
+    assert_eq!(Arc::strong_count(&counter), 1);
+}
+
+#[test]
+#[should_panic(expected = "stored by components")]
+fn soa_layout_state_access() {
+    let mut storage = EntityStorage::new();
+    let e = storage.add(SoaArchetype {
+        id: 0,
+        flag: 0,
+        counter: Arc::new(()),
+    });
+    storage.get_state::<SoaArchetype>(&e);
+}
+
+#[test]
+fn get_many() {
+    let mut storage = EntityStorage::new();
+    let entities: Vec<_> = (0..5)
+        .map(|i| {
+            storage.add(Archetype12 {
+                comp1: Comp1 { a: i, b: [0; 4] },
+                comp2: Comp2::new(),
+            })
+        })
+        .collect();
+    storage.remove(&entities[3]);
+
+    let arch = storage.get_archetype_mut::<Archetype12>().unwrap();
+    let ids = [entities[4].id, entities[3].id, entities[0].id];
+
+    let [a, b, c] = arch.get_many::<Comp1, 3>(ids);
+    assert_eq!(a.unwrap().a, 4);
+    assert!(b.is_none());
+    assert_eq!(c.unwrap().a, 0);
+    assert!(arch
+        .get_many::<Comp3, 2>([0, 1])
+        .iter()
+        .all(Option::is_none));
+
+    let [a, b, c] = arch.get_many_mut::<Comp1, 3>(ids);
+    mem::swap(a.unwrap(), c.unwrap());
+    assert!(b.is_none());
+
+    assert_eq!(storage.get::<Comp1>(&entities[4]).unwrap().a, 0);
+    assert_eq!(storage.get::<Comp1>(&entities[0]).unwrap().a, 4);
+}
+
+#[test]
+#[should_panic(expected = "distinct")]
+fn get_many_mut_aliasing() {
+    let mut storage = EntityStorage::new();
+    let e = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let arch = storage.get_archetype_mut::<Archetype1>().unwrap();
+    arch.get_many_mut::<Comp1, 2>([e.id, e.id]);
+}
+
+#[test]
+fn entity_id_u64_round_trip() {
+    for entity in [
+        EntityId::new(0, 0),
+        EntityId::new(1, 2),
+        EntityId::new(u32::MAX - 1, 12345),
+        EntityId::NULL,
+    ] {
+        assert_eq!(EntityId::from_u64(entity.to_u64()), entity);
+    }
+    assert_eq!(EntityId::new(1, 2).to_u64(), (1 << 32) | 2);
+    assert_eq!(EntityId::NULL.to_u64(), u64::MAX);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn entity_id_serde() {
+    use serde_test::{assert_tokens, Token};
+
+    assert_tokens(&EntityId::new(1, 2), &[Token::U64((1 << 32) | 2)]);
+    assert_tokens(&EntityId::NULL, &[Token::U64(u64::MAX)]);
+}
+
+#[test]
+fn transform_entity() {
+    let counter = Arc::new(());
+
+    #[derive(Archetype)]
+    struct Old(u32, Arc<()>);
+    #[derive(Archetype)]
+    struct New(u64, Arc<()>);
+
+    let mut storage = EntityStorage::new();
+    let e = storage.add(Old(5, counter.clone()));
+    let other = storage.add(Old(6, counter.clone()));
+
+    let e = storage
+        .transform_entity(&e, |Old(v, c): Old| New(v as u64 * 2, c))
+        .unwrap();
+    assert_eq!(storage.get::<u64>(&e), Some(&10));
+    assert!(storage.get::<u32>(&e).is_none());
+    assert_eq!(storage.len(), 2);
+    assert_eq!(Arc::strong_count(&counter), 3);
+
+    // Same type transforms in place
+    let same = storage
+        .transform_entity(&other, |Old(v, c): Old| Old(v + 1, c))
+        .unwrap();
+    assert_eq!(same, other);
+    assert_eq!(storage.get::<u32>(&other), Some(&7));
+    assert_eq!(storage.len(), 2);
+
+    // A panic in the closure removes the entity
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        storage.transform_entity(&other, |_: Old| -> Old { panic!() });
+    }));
+    assert!(result.is_err());
+    assert!(!storage.contains(&other));
+    assert_eq!(storage.len(), 1);
+    assert_eq!(Arc::strong_count(&counter), 2);
+
+    assert!(storage
+        .transform_entity(&other, |Old(v, c): Old| New(v as u64, c))
+        .is_none());
+    drop(storage);
+    assert_eq!(Arc::strong_count(&counter), 1);
+}
+
+#[test]
+fn archetype_as_slice() {
+    #[derive(Archetype)]
+    struct Particle(f32);
+
+    let mut storage = EntityStorage::new();
+    let particles: Vec<_> = (0..10).map(|i| storage.add(Particle(i as f32))).collect();
+    storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+
+    let arch = storage.get_archetype_mut::<Particle>().unwrap();
+    assert_eq!(arch.as_slice::<f32>().unwrap().len(), 10);
+    for v in arch.as_mut_slice::<f32>().unwrap() {
+        *v *= 2.0;
+    }
+    assert_eq!(arch.as_slice::<f32>().unwrap()[3], 6.0);
+    assert!(arch.as_slice::<u32>().is_none());
+
+    // Interleaved components
+    let arch = storage.get_archetype::<Archetype12>().unwrap();
+    assert!(arch.as_slice::<Comp1>().is_none());
+
+    // A hole below the highest occupied slot
+    storage.remove(&particles[4]);
+    let arch = storage.get_archetype_mut::<Particle>().unwrap();
+    assert!(arch.as_slice::<f32>().is_none());
+    assert!(arch.as_mut_slice::<f32>().is_none());
+
+    // The hole is filled by a new entity
+    let e = storage.add(Particle(100.0));
+    assert_eq!(e.id, particles[4].id);
+    let arch = storage.get_archetype::<Particle>().unwrap();
+    assert_eq!(arch.as_slice::<f32>().unwrap()[4], 100.0);
+
+    // Removing the last entity doesn't leave a hole
+    storage.remove(&particles[9]);
+    let arch = storage.get_archetype::<Particle>().unwrap();
+    assert_eq!(arch.as_slice::<f32>().unwrap().len(), 9);
+}
+
+#[test]
+fn iter_states() {
+    let mut storage = EntityStorage::new();
+    let entities: Vec<_> = (0..10)
+        .map(|i| {
+            storage.add(Archetype12 {
+                comp1: Comp1 { a: i, b: [0; 4] },
+                comp2: Comp2::new(),
+            })
+        })
+        .collect();
+    storage.remove(&entities[2]);
+    storage.remove(&entities[7]);
+
+    let arch = storage.get_archetype::<Archetype12>().unwrap();
+    let iter = arch.iter_states::<Archetype12>();
+    assert_eq!(iter.len(), 8);
+
+    let states: Vec<_> = iter.map(|(id, state)| (id, state.comp1.a)).collect();
+    let expected: Vec<_> = entities
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 2 && *i != 7)
+        .map(|(i, e)| (e.id, i as u32))
+        .collect();
+    assert_eq!(states, expected);
+}
+
+#[test]
+#[should_panic(expected = "invalid state type")]
+fn iter_states_invalid_type() {
+    let mut storage = EntityStorage::new();
+    storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let arch = storage.get_archetype::<Archetype1>().unwrap();
+    arch.iter_states::<Archetype2>();
+}
+
+#[test]
+fn archetype_alignment_metadata() {
+    #[derive(Archetype)]
+    struct Arch(u8, u64);
+
+    assert_eq!(
+        <Arch as StaticArchetype>::metadata().align,
+        mem::align_of::<Arch>()
+    );
+    assert_eq!(<() as StaticArchetype>::metadata().align, 1);
+
+    let packed = DynamicArchetypeBuilder::new()
+        .register::<u8>()
+        .register::<u64>()
+        .build();
+    assert_eq!(packed.align, 1);
+
+    let aligned = DynamicArchetypeBuilder::new()
+        .aligned()
+        .register::<u8>()
+        .register::<u64>()
+        .build();
+    assert_eq!(aligned.align, mem::align_of::<u64>());
+
+    let mut storage = EntityStorage::new();
+    let e = storage.add(Arch(1, 2));
+    let ptr = storage.get_state::<Arch>(&e).unwrap() as *const Arch;
+    assert_eq!(ptr as usize % mem::align_of::<Arch>(), 0);
+}
+
+#[test]
+#[should_panic(expected = "invalid alignment")]
+fn archetype_invalid_alignment() {
+    let mut meta = DynamicArchetypeBuilder::new().register::<u8>().build();
+    meta.align = 4;
+    let mut storage = EntityStorage::new();
+    unsafe { storage.add_raw(meta, [0_u8].as_ptr()) };
+}
+
+#[test]
+fn take_state() {
+    #[derive(Archetype)]
+    struct Named {
+        name: String,
+        counter: Arc<()>,
+    }
+
+    let counter = Arc::new(());
+    let mut storage = EntityStorage::new();
+    let e1 = storage.add(Named {
+        name: "first".to_string(),
+        counter: counter.clone(),
+    });
+    let e2 = storage.add(Named {
+        name: "second".to_string(),
+        counter: counter.clone(),
+    });
+    let e3 = storage.add(SoaArchetype {
+        id: 3,
+        flag: 1,
+        counter: counter.clone(),
+    });
+    assert_eq!(Arc::strong_count(&counter), 4);
+
+    let first = storage.take::<Named>(&e1).unwrap();
+    assert_eq!(first.name, "first");
+    assert!(!storage.contains(&e1));
+    assert!(storage.take::<Named>(&e1).is_none());
+    assert_eq!(storage.count_entities(), 2);
+    assert_eq!(Arc::strong_count(&counter), 4);
+    drop(first);
+    assert_eq!(Arc::strong_count(&counter), 3);
+
+    let second = storage.take_any(&e2).unwrap();
+    assert_eq!(second.downcast_ref::<Named>().unwrap().name, "second");
+    assert!(storage.take_any(&e2).is_none());
+    drop(second);
+    assert_eq!(Arc::strong_count(&counter), 2);
+
+    let soa = storage.take::<SoaArchetype>(&e3).unwrap();
+    assert_eq!((soa.id, soa.flag), (3, 1));
+    assert!(storage.is_empty());
+    drop(soa);
+    drop(storage);
+    assert_eq!(Arc::strong_count(&counter), 1);
+}
+
+#[test]
+#[should_panic(expected = "invalid state type")]
+fn take_state_invalid_type() {
+    let mut storage = EntityStorage::new();
+    let e = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    storage.take::<Archetype12>(&e);
+}
+
+#[test]
+fn storage_content_eq() {
+    #[derive(Archetype, PartialEq)]
+    #[archetype(eq)]
+    struct Named {
+        name: String,
+        value: u32,
+    }
+
+    #[derive(Archetype, PartialEq)]
+    #[archetype(eq, layout = "soa")]
+    struct Point(i32, i64);
+
+    fn fill() -> (EntityStorage, Vec<EntityId>) {
+        let mut storage = EntityStorage::new();
+        let mut entities = vec![];
+        for i in 0..10 {
+            entities.push(storage.add(Named {
+                name: i.to_string(),
+                value: i,
+            }));
+            entities.push(storage.add(Point(i as i32, -(i as i64))));
+        }
+        storage.remove(&entities[4]);
+        (storage, entities)
+    }
+
+    let (mut storage, entities) = fill();
+    let (other, _) = fill();
+    assert!(storage.content_eq(&other));
+    assert!(other.content_eq(&storage));
+
+    storage.get_mut::<String>(&entities[0]).unwrap().push('!');
+    assert!(!storage.content_eq(&other));
+    storage.get_mut::<String>(&entities[0]).unwrap().pop();
+    assert!(storage.content_eq(&other));
+
+    *storage.get_mut::<i64>(&entities[1]).unwrap() += 1;
+    assert!(!storage.content_eq(&other));
+    *storage.get_mut::<i64>(&entities[1]).unwrap() -= 1;
+
+    // The same states in a different order
+    let mut reversed = EntityStorage::new();
+    for i in (0..2).rev() {
+        reversed.add(Named {
+            name: i.to_string(),
+            value: i,
+        });
+    }
+    let mut ordered = EntityStorage::new();
+    for i in 0..2 {
+        ordered.add(Named {
+            name: i.to_string(),
+            value: i,
+        });
+    }
+    assert!(!reversed.content_eq(&ordered));
+
+    assert!(EntityStorage::new().content_eq(&EntityStorage::new()));
+}
+
+#[test]
+#[should_panic(expected = "doesn't implement `EqArchetype`")]
+fn storage_content_eq_unsupported() {
+    let mut storage = EntityStorage::new();
+    storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let mut other = EntityStorage::new();
+    other.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    storage.content_eq(&other);
+}
+
+#[test]
+fn component_storage_len() {
+    let mut storage = EntityStorage::new();
+    let entities: Vec<_> = (0..10)
+        .map(|_| {
+            storage.add(Archetype12 {
+                comp1: Comp1::new(),
+                comp2: Comp2::new(),
+            })
+        })
+        .collect();
+    for e in entities.iter().step_by(3) {
+        storage.remove(e);
+    }
+
+    let arch = storage.get_archetype::<Archetype12>().unwrap();
+    let comps = arch.component::<Comp1>().unwrap();
+    assert_eq!(comps.len(), 6);
+    assert!(!comps.is_empty());
+
+    let mut iter = comps.iter();
+    assert_eq!(iter.len(), 6);
+    iter.next();
+    assert_eq!(iter.size_hint(), (5, Some(5)));
+    assert_eq!(comps.iter_with_ids().len(), 6);
+    assert_eq!(arch.entities.iter().len(), 6);
+
+    let arch = storage.get_archetype_mut::<Archetype12>().unwrap();
+    let mut iter = arch.component_mut::<Comp2>().unwrap().into_iter();
+    assert_eq!(iter.len(), 6);
+    assert_eq!(iter.by_ref().skip(2).count(), 4);
+    assert_eq!(iter.len(), 0);
+
+    let access = storage.access();
+    let comps = access.component::<Comp1>();
+    assert_eq!(comps.count_entities(), 6);
+    assert_eq!(comps.iter().len(), 6);
+    assert_eq!(comps.iter().collect::<Vec<_>>().capacity(), 6);
+
+    let mut empty = EntityStorage::new();
+    let e = empty.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    empty.remove(&e);
+    let comps = empty
+        .get_archetype::<Archetype1>()
+        .unwrap()
+        .component::<Comp1>()
+        .unwrap();
+    assert_eq!(comps.len(), 0);
+    assert!(comps.is_empty());
+    assert_eq!(comps.iter().len(), 0);
+}
+
+#[test]
+fn component_storage_mut_reborrow() {
+    let mut storage = EntityStorage::new();
+    let e = storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+    let arch = storage.get_archetype_mut::<Archetype12>().unwrap();
+    let mut comps = arch.component_mut::<Comp1>().unwrap();
+
+    for comp in comps.iter_mut() {
+        comp.a = 1;
+    }
+    for comp in comps.iter_mut() {
+        comp.a += 1;
+    }
+    comps.get_mut(e.id).unwrap().a += 1;
+    assert_eq!(comps.get(e.id).unwrap().a, 3);
+
+    let comp = comps.into_mut(e.id).unwrap();
+    comp.a += 1;
+    assert_eq!(arch.get::<Comp1>(e.id).unwrap().a, 4);
+}
+
+#[test]
+fn derive_forwards_outer_attributes() {
+    /// A documented archetype.
+    #[derive(Archetype)]
+    #[cfg(test)]
+    #[allow(dead_code)]
+    struct Documented {
+        unused: u8,
+    }
+
+    let mut storage = EntityStorage::new();
+    let e = storage.add(Documented { unused: 1 });
+    assert!(storage.contains(&e));
+}
+
+#[test]
+fn iter_states_mut() {
+    let mut storage = EntityStorage::new();
+    let entities: Vec<_> = (0..10)
+        .map(|i| {
+            storage.add(Archetype12 {
+                comp1: Comp1 { a: i, b: [0; 4] },
+                comp2: Comp2::new(),
+            })
+        })
+        .collect();
+    storage.remove(&entities[3]);
+
+    let arch = storage.get_archetype_mut::<Archetype12>().unwrap();
+    let iter = arch.iter_states_mut::<Archetype12>();
+    assert_eq!(iter.len(), 9);
+    for (id, state) in iter {
+        state.comp1.a *= 2;
+        state.comp1.b = [id; 4];
+    }
+
+    for (i, e) in entities.iter().enumerate() {
+        let Some(state) = storage.get_state::<Archetype12>(e) else {
+            assert_eq!(i, 3);
+            continue;
+        };
+        assert_eq!(state.comp1.a, i as u32 * 2);
+        assert_eq!(state.comp1.b, [e.id; 4]);
+    }
+}
+
+#[test]
+fn component_get_many() {
+    let mut storage = EntityStorage::new();
+    let mut entities: Vec<_> = (0..10)
+        .map(|i| {
+            storage.add(Archetype1 {
+                comp1: Comp1 { a: i, b: [0; 4] },
+            })
+        })
+        .collect();
+    entities.extend((10..20).map(|i| {
+        storage.add(Archetype12 {
+            comp1: Comp1 { a: i, b: [0; 4] },
+            comp2: Comp2::new(),
+        })
+    }));
+    storage.remove(&entities[5]);
+
+    let arch = storage.get_archetype::<Archetype1>().unwrap();
+    let comps = arch.component::<Comp1>().unwrap();
+    let values: Vec<_> = comps
+        .get_many(&[7, 5, 0, 100])
+        .map(|c| c.map(|c| c.a))
+        .collect();
+    assert_eq!(values, [Some(7), None, Some(0), None]);
+
+    let arch = storage.get_archetype_mut::<Archetype1>().unwrap();
+    let mut comps = arch.component_mut::<Comp1>().unwrap();
+    for comp in comps.get_many_mut(&[1, 2, 5]).flatten() {
+        comp.a += 100;
+    }
+    assert_eq!(comps.get(1).unwrap().a, 101);
+    assert_eq!(comps.get(2).unwrap().a, 102);
+    assert_eq!(comps.get(3).unwrap().a, 3);
+
+    let ids = [
+        entities[15],
+        entities[0],
+        entities[5],
+        entities[12],
+        EntityId::NULL,
+    ];
+    let access = storage.access();
+    let comps = access.component::<Comp1>();
+    let values: Vec<_> = comps.get_many(&ids).map(|c| c.map(|c| c.a)).collect();
+    assert_eq!(values, [Some(15), Some(0), None, Some(12), None]);
+    drop(comps);
+
+    let mut sorted = ids;
+    sorted.sort();
+    let mut comps = access.component_mut::<Comp1>();
+    let n_found = comps
+        .get_many_mut(&sorted)
+        .flatten()
+        .map(|comp| comp.a = 0)
+        .count();
+    assert_eq!(n_found, 3);
+    assert_eq!(comps.get(&entities[12]).unwrap().a, 0);
+    assert_eq!(comps.get(&entities[13]).unwrap().a, 13);
+}
+
+#[test]
+#[should_panic(expected = "sorted and distinct")]
+fn component_get_many_mut_unsorted() {
+    let mut storage = EntityStorage::new();
+    for _ in 0..2 {
+        storage.add(Archetype1 {
+            comp1: Comp1::new(),
+        });
+    }
+    let arch = storage.get_archetype_mut::<Archetype1>().unwrap();
+    let mut comps = arch.component_mut::<Comp1>().unwrap();
+    let _ = comps.get_many_mut(&[1, 0]);
+}
+
+#[test]
+fn iter_all_components() {
+    #[derive(Archetype)]
+    struct Pair(u32, u64);
+
+    let mut storage = EntityStorage::new();
+    let entities: Vec<_> = (0..5)
+        .map(|i| storage.add(Pair(i, i as u64 * 10)))
+        .collect();
+    storage.remove(&entities[1]);
+
+    let arch = storage.get_archetype::<Pair>().unwrap();
+    let states: Vec<_> = arch
+        .iter_all_components()
+        .map(|(id, bytes)| {
+            assert_eq!(bytes.len(), mem::size_of::<Pair>());
+            let state = unsafe { &*(bytes.as_ptr() as *const Pair) };
+            (id, state.0, state.1)
+        })
+        .collect();
+    assert_eq!(states, [(0, 0, 0), (2, 2, 20), (3, 3, 30), (4, 4, 40)]);
+}
+
+#[test]
+#[should_panic(expected = "stored by components")]
+fn iter_all_components_soa() {
+    let mut storage = EntityStorage::new();
+    storage.add(SoaArchetype {
+        id: 0,
+        flag: 0,
+        counter: Arc::new(()),
+    });
+    let arch = storage.get_archetype::<SoaArchetype>().unwrap();
+    let _ = arch.iter_all_components();
+}
+
+#[test]
+fn large_archetype() {
+    #[derive(Archetype)]
+    #[archetype(large)]
+    #[rustfmt::skip]
+    struct Large(
+        [u8; 1], [u8; 2], [u8; 3], [u8; 4], [u8; 5], [u8; 6], [u8; 7], [u8; 8], [u8; 9], [u8; 10],
+        [u8; 11], [u8; 12], [u8; 13], [u8; 14], [u8; 15], [u8; 16], [u8; 17], [u8; 18], [u8; 19],
+        [u8; 20], [u8; 21], [u8; 22], [u8; 23], [u8; 24], [u8; 25], [u8; 26], [u8; 27], [u8; 28],
+        [u8; 29], [u8; 30], [u8; 31], [u8; 32], [u8; 33],
+    );
+
+    assert_eq!(Large::N_COMPONENTS, 33);
+    assert_eq!(Large::metadata().component_infos().len(), 33);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_iter_components() {
+    use rayon::prelude::*;
+
+    #[derive(Archetype)]
+    struct Value(u64);
+
+    #[derive(Archetype)]
+    struct Pair(u64, u8);
+
+    let mut storage = EntityStorage::new();
+    let entities: Vec<_> = (0..1_000_000_u64)
+        .map(|i| {
+            if i % 3 == 0 {
+                storage.add(Pair(i, 0))
+            } else {
+                storage.add(Value(i))
+            }
+        })
+        .collect();
+    for e in entities.iter().step_by(7) {
+        storage.remove(e);
+    }
+
+    let arch = storage.get_archetype::<Value>().unwrap();
+    let comps = arch.component::<u64>().unwrap();
+    let sum: u64 = comps.par_iter().sum();
+    assert_eq!(sum, comps.iter().sum());
+
+    let arch = storage.get_archetype_mut::<Pair>().unwrap();
+    let mut comps = arch.component_mut::<u64>().unwrap();
+    comps.par_iter_mut().for_each(|v| *v += 1);
+    assert_eq!(comps.get(entities[3].id), Some(&4));
+
+    let expected: u64 = storage.iter_components::<u64>().map(|(_, v)| *v).sum();
+    let access = storage.access();
+    assert_eq!(access.component::<u64>().par_iter().sum::<u64>(), expected);
+
+    let mut comps = access.component_mut::<u64>();
+    comps.par_iter_mut().for_each(|v| *v *= 2);
+    assert_eq!(comps.par_iter().count(), comps.count_entities());
+    assert_eq!(comps.iter().sum::<u64>(), expected * 2);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_for_each() {
+    let mut storage = EntityStorage::new();
+    let mut entities = vec![];
+    for i in 0..1000 {
+        entities.push(storage.add(Archetype1 {
+            comp1: Comp1 { a: i, b: [0; 4] },
+        }));
+        entities.push(storage.add(Archetype12 {
+            comp1: Comp1 { a: i, b: [0; 4] },
+            comp2: Comp2::new(),
+        }));
+    }
+    for e in entities.iter().step_by(5) {
+        storage.remove(e);
+    }
+
+    storage.par_for_each::<Comp1, _>(|entity, comp| {
+        comp.b[0] = comp.a * 2;
+        comp.b[1] = entity.id;
+        comp.b[2] = entity.archetype_id;
+    });
+    // Archetypes without the component are skipped
+    storage.par_for_each::<u8, _>(|_, _| unreachable!());
+
+    let mut n_visited = 0;
+    storage.for_each_mut::<Comp1, _>(|entity, comp| {
+        assert_eq!(comp.b, [comp.a * 2, entity.id, entity.archetype_id, 0]);
+        n_visited += 1;
+    });
+    assert_eq!(n_visited, storage.count_entities());
+}
+
+#[test]
+fn null_entity_lookups() {
+    use crate::ArchetypeState;
+
+    let mut storage = EntityStorage::new();
+    storage.register_sparse::<u8>();
+    let e = storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+    let invalid_id = EntityId::new(e.archetype_id, u32::MAX);
+    let ty = TypeId::of::<Comp1>();
+
+    for null in [EntityId::NULL, invalid_id] {
+        assert!(!storage.contains(&null));
+        assert!(storage.components_of(&null).is_none());
+        assert!(!storage.has_component(&null, ty));
+        assert!(storage.get::<Comp1>(&null).is_none());
+        assert!(storage.get_mut::<Comp1>(&null).is_none());
+        assert!(storage.get::<u8>(&null).is_none());
+        assert!(storage.get_two_mut::<Comp1>(&null, &e).is_none());
+        assert!(storage.get_two_mut::<Comp1>(&e, &null).is_none());
+        assert!(storage.set(&null, Comp1::new()).is_err());
+        assert!(storage.take_component::<u32>(&null).is_none());
+        assert!(storage.replace_component(&null, Comp1::new()).is_none());
+        assert!(storage.get_or_insert_component(&null, || 0_u64).is_none());
+        assert!(storage.insert_sparse(&null, 0_u8).is_err());
+        assert!(storage.remove_sparse::<u8>(&null).is_none());
+        assert!(storage.get_state::<Archetype12>(&null).is_none());
+        assert!(storage.get_state_mut::<Archetype12>(&null).is_none());
+        assert!(storage
+            .transform_entity(&null, |state: Archetype12| state)
+            .is_none());
+        assert!(storage.get_raw(&null, ty).is_none());
+        assert!(storage.get_raw_mut(&null, ty).is_none());
+        assert!(storage.get_state_dyn(&null).is_none());
+        assert!(storage.get_state_dyn_mut(&null).is_none());
+        assert!(storage.entry(&null).is_none());
+        assert!(storage.entry_mut(&null).is_none());
+        assert!(!storage.remove(&null));
+        assert!(storage.take::<Archetype12>(&null).is_none());
+        assert!(storage.take_any(&null).is_none());
+        assert!(!storage.entities().contains(&null));
+
+        let arch = storage.get_archetype_mut::<Archetype12>().unwrap();
+        assert!(!arch.contains(null.id));
+        assert!(arch.get::<Comp1>(null.id).is_none());
+        assert!(arch.get_mut::<Comp1>(null.id).is_none());
+        assert!(arch.get_state::<Archetype12>(null.id).is_none());
+        assert!(arch.get_state_mut::<Archetype12>(null.id).is_none());
+        assert!(arch.component_ptr(ty, null.id).is_none());
+        assert!(arch.component_bytes(null.id, ty).is_none());
+        assert!(arch.get_state_dyn(null.id).is_none());
+
+        let access = storage.access();
+        let comps = access.component::<Comp1>();
+        assert!(!comps.contains(&null));
+        assert!(comps.get(&null).is_none());
+        assert_eq!(comps.get_many(&[null]).next(), Some(None));
+        drop(comps);
+        let mut comps = access.component_mut::<Comp2>();
+        assert!(!comps.contains(&null));
+        assert!(comps.get(&null).is_none());
+        assert!(comps.get_mut(&null).is_none());
+    }
+    let mut sys = |data: crate::system::SystemAccess| {
+        assert!(data.state::<Archetype12>(&EntityId::NULL).is_none());
+    };
+    storage.dispatch(&mut [crate::System::new(&mut sys).with_archetype::<Archetype12>()]);
+
+    assert_eq!(storage.count_entities(), 1);
+    assert_eq!(
+        storage.get_state::<Archetype12>(&e).unwrap().ty(),
+        TypeId::of::<Archetype12>()
+    );
+}
+
+#[test]
+fn archetype_iter_zip() {
+    let mut storage = EntityStorage::new();
+    let entities: Vec<_> = (0..10)
+        .map(|i| {
+            storage.add(SoaArchetype {
+                id: i,
+                flag: i as u8,
+                counter: Arc::new(()),
+            })
+        })
+        .collect();
+    storage.remove(&entities[4]);
+    let e = storage.add(Archetype12 {
+        comp1: Comp1 { a: 3, b: [0; 4] },
+        comp2: Comp2::new(),
+    });
+
+    let arch = storage.get_archetype::<SoaArchetype>().unwrap();
+    let zipped: Vec<_> = arch
+        .iter_zip::<u64, u8>()
+        .unwrap()
+        .map(|(id, a, b)| (id, *a, *b))
+        .collect();
+    assert_eq!(zipped.len(), 9);
+    assert!(zipped
+        .iter()
+        .all(|(id, a, b)| *a == *id as u64 && *b == *id as u8));
+    assert!(arch.iter_zip::<u64, Comp1>().is_none());
+
+    let arch = storage.get_archetype_mut::<SoaArchetype>().unwrap();
+    for (_, id, flag) in arch.iter_zip_mut::<u64, u8>().unwrap() {
+        *flag += *id as u8;
+    }
+    assert_eq!(storage.get::<u8>(&entities[5]), Some(&10));
+
+    let arch = storage.get_archetype_mut::<Archetype12>().unwrap();
+    for (_, comp1, comp2) in arch.iter_zip_mut::<Comp1, Comp2>().unwrap() {
+        comp2.c[0] = comp1.a + 1;
+    }
+    assert_eq!(storage.get::<Comp2>(&e).unwrap().c[0], 4);
+}
+
+#[test]
+#[should_panic(expected = "Components must be distinct")]
+fn archetype_iter_zip_mut_same_type() {
+    let mut storage = EntityStorage::new();
+    storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let arch = storage.get_archetype_mut::<Archetype1>().unwrap();
+    let _ = arch.iter_zip_mut::<Comp1, Comp1>();
+}
+
+#[test]
+fn map_component() {
+    let mut storage = EntityStorage::new();
+    let e0 = storage.add(SoaArchetype {
+        id: 1,
+        flag: 0,
+        counter: Arc::new(()),
+    });
+    let counter = Arc::new(());
+    let e1 = storage.add(SoaArchetype {
+        id: 2,
+        flag: 0,
+        counter: counter.clone(),
+    });
+    let e2 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+
+    storage.map_component::<u64, _>(|id| id * 10);
+    assert_eq!(storage.get::<u64>(&e0), Some(&10));
+    assert_eq!(storage.get::<u64>(&e1), Some(&20));
+
+    // Old values are dropped exactly once.
+    storage.map_component::<Arc<()>, _>(|c| c.clone());
+    assert_eq!(Arc::strong_count(&counter), 2);
+
+    storage.map_component::<Comp1, _>(|c| Comp1 { a: c.a + 1, b: c.b });
+    assert_eq!(storage.get::<Comp1>(&e2).unwrap().a, Comp1::new().a + 1);
+}
+
+#[test]
+fn component_for_each_fill() {
+    let mut storage = EntityStorage::new();
+    let entities: Vec<_> = (0..6)
+        .map(|i| {
+            storage.add(SoaArchetype {
+                id: i,
+                flag: 0,
+                counter: Arc::new(()),
+            })
+        })
+        .collect();
+    storage.remove(&entities[2]);
+    let e = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+
+    let arch = storage.get_archetype_mut::<SoaArchetype>().unwrap();
+    let mut comps = arch.component_mut::<u64>().unwrap();
+    comps.for_each(|id, comp| *comp += id as u64);
+    assert_eq!(comps.get(5), Some(&10));
+    comps.fill(7);
+    assert!(comps.iter_mut().all(|c| *c == 7));
+
+    #[cfg(feature = "rayon")]
+    {
+        comps.par_for_each(|id, comp| *comp = id as u64 * 2);
+        assert_eq!(comps.get(4), Some(&8));
+    }
+
+    let mut ids = vec![];
+    let access = storage.access();
+    access.component_mut::<Comp1>().for_each(|id, comp| {
+        ids.push(id);
+        comp.a = 42;
+    });
+    assert_eq!(ids, [e]);
+    access.component_mut::<u8>().fill(3);
+    assert!(access.component::<u8>().iter().all(|c| *c == 3));
+
+    #[cfg(feature = "rayon")]
+    {
+        access
+            .component_mut::<u8>()
+            .par_for_each(|id, comp| *comp = id.id as u8);
+        assert_eq!(access.component::<u8>().get(&entities[3]), Some(&3));
+        assert_eq!(access.component::<u8>().get(&entities[5]), Some(&5));
+    }
+    drop(access);
+    assert_eq!(storage.get::<Comp1>(&e).unwrap().a, 42);
+}
+
+#[test]
+fn component_storage_entities() {
+    let mut storage = EntityStorage::new();
+    let entities: Vec<_> = (0..4)
+        .map(|_| {
+            storage.add(Archetype1 {
+                comp1: Comp1::new(),
+            })
+        })
+        .collect();
+    storage.remove(&entities[1]);
+
+    let arch = storage.get_archetype::<Archetype1>().unwrap();
+    let comps: crate::ComponentStorageRef<Comp1> = arch.component().unwrap();
+    assert_eq!(comps.entity_ids().collect::<Vec<_>>(), [0, 2, 3]);
+    assert_eq!(comps.entity_ids().len(), comps.len());
+}
+
+#[test]
+fn count_entities_in_archetype() {
+    let mut storage = EntityStorage::new();
+    assert_eq!(storage.count_entities_in_archetype::<Archetype1>(), 0);
+    assert_eq!(storage.count_entities_in_archetype_by_id(0), 0);
+
+    let e = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    storage.add(Archetype2(Comp2::new()));
+
+    assert_eq!(storage.count_entities_in_archetype::<Archetype1>(), 2);
+    assert_eq!(storage.count_entities_in_archetype::<Archetype2>(), 1);
+    assert_eq!(storage.count_entities_in_archetype::<Archetype12>(), 0);
+    assert_eq!(storage.count_entities_in_archetype_by_id(e.archetype_id), 2);
+    assert_eq!(storage.count_entities_in_archetype_by_id(100), 0);
+}
+
+#[test]
+fn for_each_pair_mut() {
+    let mut storage = EntityStorage::new();
+    let mut entities = vec![];
+    for i in 0..5 {
+        entities.push(storage.add(SoaArchetype {
+            id: i,
+            flag: 0,
+            counter: Arc::new(()),
+        }));
+    }
+    storage.remove(&entities[1]);
+    #[derive(Archetype)]
+    struct Other(u64);
+    entities.push(storage.add(Other(7)));
+
+    let n = storage.iter_components::<u64>().count();
+    let mut n_pairs = 0;
+    storage.for_each_pair_mut::<u64, _>(|a, b| {
+        assert!(!std::ptr::eq(a, b));
+        n_pairs += 1;
+        *a += 1;
+        *b += 1;
+    });
+    assert_eq!(n_pairs, n * (n - 1) / 2);
+
+    // Every component is paired with each of the other `n - 1` ones.
+    assert_eq!(storage.get::<u64>(&entities[0]), Some(&(n as u64 - 1)));
+    assert_eq!(storage.get::<u64>(&entities[5]), Some(&(7 + n as u64 - 1)));
+
+    storage.for_each_pair_mut::<Comp1, _>(|_, _| unreachable!());
+}
+
+#[test]
+fn register_archetype() {
+    let mut storage = EntityStorage::new();
+    let arch_id = storage.register_archetype::<Archetype12>();
+
+    assert_eq!(storage.n_archetypes(), 1);
+    assert_eq!(storage.count_entities(), 0);
+    assert_eq!(
+        storage
+            .archetypes_with_component::<Comp2>()
+            .collect::<Vec<_>>(),
+        [arch_id]
+    );
+    assert_eq!(storage.register_archetype::<Archetype12>(), arch_id);
+    assert_ne!(storage.register_archetype::<Archetype1>(), arch_id);
+
+    let e = storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+    assert_eq!(e.archetype_id, arch_id);
+}
+
+#[test]
+fn global_component_entity_ids() {
+    let mut storage = EntityStorage::new();
+    let e0 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let removed = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let e1 = storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+    storage.add(Archetype2(Comp2::new()));
+    storage.remove(&removed);
+
+    let access = storage.access();
+    let comps = access.component::<Comp1>();
+    let ids = comps.entity_ids();
+    assert_eq!(ids.len(), 2);
+    let mut ids: Vec<_> = ids.collect();
+    ids.sort();
+    assert_eq!(ids, [e0, e1]);
+    drop(comps);
+    assert_eq!(access.component_mut::<Comp1>().entity_ids().count(), 2);
+}
+
+#[test]
+fn storage_send_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<crate::ArchetypeStorage>();
+    assert_send_sync::<EntityStorage>();
+
+    let mut storage = EntityStorage::new();
+    let entity = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+
+    std::thread::scope(|s| {
+        s.spawn(|| assert!(storage.contains(&entity)));
+    });
+    let storage = std::thread::spawn(move || storage).join().unwrap();
+    assert_eq!(storage.count_entities(), 1);
+}
+
+#[test]
+fn static_and_any_state_share_archetype() {
+    let mut storage = EntityStorage::new();
+    let arch_id = storage.register_archetype::<Archetype1>();
+
+    let e0 = storage.add(
+        Archetype1 {
+            comp1: Comp1::new(),
+        }
+        .into_any(),
+    );
+    let e1 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    assert_eq!(e0.archetype_id, arch_id);
+    assert_eq!(e1.archetype_id, arch_id);
+
+    // The state-based path creates the archetype first.
+    let e2 = storage.add(Archetype2(Comp2::new()).into_any());
+    assert_eq!(storage.register_archetype::<Archetype2>(), e2.archetype_id);
+    storage.reserve::<Archetype2>(10);
+    assert_eq!(storage.n_archetypes(), 2);
+}
+
+#[test]
+fn global_iter_skips_archetypes_without_component() {
+    use crate::system::component::GenericComponentGlobalAccess;
+    use crate::GlobalComponentAccess;
+    use std::cell::RefCell;
+
+    let mut storage = EntityStorage::new();
+    let e0 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let other = storage.add(Archetype2(Comp2::new()));
+    let e1 = storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+
+    // The archetype of `other` doesn't contain `Comp1`.
+    let generic = RefCell::new(GenericComponentGlobalAccess {
+        filtered_archetype_ids: vec![
+            e0.archetype_id as usize,
+            other.archetype_id as usize,
+            e1.archetype_id as usize,
+        ],
+        all_archetypes: &storage.archetypes,
+        mutable: false,
+    });
+    let access = GlobalComponentAccess::<Comp1> {
+        generic: generic.borrow(),
+        _ty: Default::default(),
+    };
+
+    let iter = access.iter();
+    assert_eq!(iter.count(), 2);
+    let mut ids: Vec<_> = access.entity_ids().collect();
+    ids.sort();
+    assert_eq!(ids, [e0, e1]);
+
+    assert_eq!(access.count_entities(), 2);
+    let mut iter = access.iter();
+    iter.next();
+    assert_eq!(iter.len(), 1);
+    iter.next();
+    assert_eq!(iter.len(), 0);
+    assert!(iter.next().is_none());
+
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+        assert_eq!(access.par_iter().count(), 2);
+    }
+}
+
+#[test]
+fn derive_with_cfg_fields() {
+    #[derive(Archetype)]
+    struct Conditional {
+        comp1: Comp1,
+        #[cfg(not(test))]
+        excluded: Comp2,
+        #[cfg(test)]
+        #[cfg_attr(test, allow(dead_code))]
+        included: u64,
+    }
+
+    assert_eq!(Conditional::N_COMPONENTS, 2);
+    assert_eq!(
+        Conditional::component_type_ids().as_slice(),
+        [TypeId::of::<Comp1>(), TypeId::of::<u64>()]
+    );
+
+    let mut storage = EntityStorage::new();
+    let e = storage.add(Conditional {
+        comp1: Comp1::new(),
+        included: 5,
+    });
+    assert_eq!(storage.get::<u64>(&e), Some(&5));
+    assert!(storage.get::<Comp2>(&e).is_none());
+}
+
+#[test]
+fn any_state_get_component() {
+    let mut state = Archetype12 {
+        comp1: Comp1 { a: 3, b: [1; 4] },
+        comp2: Comp2::new(),
+    }
+    .into_any();
+
+    assert_eq!(state.get::<Comp1>().unwrap().a, 3);
+    assert_eq!(state.get::<Comp1>().unwrap().b, [1; 4]);
+    assert_eq!(state.get::<Comp2>().unwrap().c, Comp2::new().c);
+    assert!(state.get::<Comp3>().is_none());
+
+    state.get_mut::<Comp1>().unwrap().a = 8;
+    state.get_mut::<Comp2>().unwrap().c[1] = 9;
+
+    let mut storage = EntityStorage::new();
+    let e = storage.add(state);
+    assert_eq!(storage.get::<Comp1>(&e).unwrap().a, 8);
+    assert_eq!(storage.get::<Comp2>(&e).unwrap().c[1], 9);
+
+    let state = SoaArchetype {
+        id: 11,
+        flag: 2,
+        counter: Arc::new(()),
+    }
+    .into_any();
+    assert_eq!(state.get::<u64>(), Some(&11));
+    assert_eq!(state.get::<u8>(), Some(&2));
+}
+
+#[test]
+fn global_component_iter_with_ids() {
+    let mut storage = EntityStorage::new();
+    let e0 = storage.add(Archetype1 {
+        comp1: Comp1 { a: 1, b: [0; 4] },
+    });
+    let removed = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let e1 = storage.add(Archetype12 {
+        comp1: Comp1 { a: 2, b: [0; 4] },
+        comp2: Comp2::new(),
+    });
+    storage.remove(&removed);
+
+    let access = storage.access();
+    let mut comps = access.component_mut::<Comp1>();
+    for (entity, comp) in comps.iter_mut_with_ids() {
+        comp.b[0] = entity.archetype_id;
+    }
+    let mut items: Vec<_> = comps
+        .iter_mut_with_ids()
+        .map(|(entity, comp)| (entity, comp.a))
+        .collect();
+    items.sort();
+    assert_eq!(items, [(e0, 1), (e1, 2)]);
+    drop(comps);
+
+    let comps = access.component::<Comp1>();
+    let iter = comps.iter_with_ids();
+    assert_eq!(iter.len(), 2);
+    for (entity, comp) in iter {
+        assert_eq!(comp.b[0], entity.archetype_id);
+    }
+}
+
+#[test]
+fn all_entities_into_iter() {
+    let mut storage = EntityStorage::new();
+    let e0 = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    let e1 = storage.add(Archetype2(Comp2::new()));
+
+    let mut visited = vec![];
+    for entity in storage.entities() {
+        visited.push(entity);
+    }
+    assert_eq!(visited, [e0, e1]);
+
+    for (entity, entry) in &storage {
+        assert_eq!(*entry.entity(), entity);
+    }
+}
+
+#[test]
+fn clone_entity() {
+    #[derive(Clone, Archetype)]
+    #[archetype(clone)]
+    struct Prefab {
+        items: Vec<u32>,
+        counter: Arc<()>,
+    }
+
+    #[derive(Clone, Archetype)]
+    #[archetype(clone, layout = "soa")]
+    struct SoaPrefab(Vec<u64>, u8);
+
+    let counter = Arc::new(());
+    let mut storage = EntityStorage::new();
+    let src = storage.add(Prefab {
+        items: vec![1, 2],
+        counter: counter.clone(),
+    });
+    let copy = storage.clone_entity(&src).unwrap();
+    assert_ne!(copy, src);
+    assert_eq!(copy.archetype_id, src.archetype_id);
+    assert_eq!(Arc::strong_count(&counter), 3);
+
+    storage.get_mut::<Vec<u32>>(&copy).unwrap().push(3);
+    assert_eq!(storage.get::<Vec<u32>>(&src).unwrap(), &[1, 2]);
+    assert_eq!(storage.get::<Vec<u32>>(&copy).unwrap(), &[1, 2, 3]);
+
+    let soa_src = storage.add(SoaPrefab(vec![7], 1));
+    let soa_copy = storage.clone_entity(&soa_src).unwrap();
+    storage.get_mut::<Vec<u64>>(&soa_copy).unwrap()[0] = 8;
+    assert_eq!(storage.get::<Vec<u64>>(&soa_src).unwrap(), &[7]);
+    assert_eq!(storage.get::<u8>(&soa_copy), Some(&1));
+
+    // Not cloneable
+    let other = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    assert!(storage.clone_entity(&other).is_none());
+
+    storage.remove(&src);
+    assert!(storage.clone_entity(&src).is_none());
+    assert_eq!(storage.count_entities(), 4);
+
+    drop(storage);
+    assert_eq!(Arc::strong_count(&counter), 1);
+}
+
+#[test]
+fn global_component_entries_snapshot() {
+    use std::collections::HashMap;
+
+    let mut storage = EntityStorage::new();
+    let mut expected = HashMap::new();
+    for i in 0..3 {
+        let e = storage.add(Archetype1 {
+            comp1: Comp1 { a: i, b: [0; 4] },
+        });
+        expected.insert(e, i);
+        let e = storage.add(Archetype12 {
+            comp1: Comp1 {
+                a: i + 10,
+                b: [0; 4],
+            },
+            comp2: Comp2::new(),
+        });
+        expected.insert(e, i + 10);
+    }
+    storage.add(Archetype2(Comp2::new()));
+
+    let access = storage.access();
+    let comps = access.component::<Comp1>();
+    let iter = comps.iter_with_ids();
+    assert_eq!(iter.len(), 6);
+
+    // Cloning allows several passes
+    let snapshot: HashMap<EntityId, u32> = iter.clone().map(|(e, c)| (e, c.a)).collect();
+    assert_eq!(snapshot, expected);
+    assert_eq!(iter.count(), 6);
+    drop(comps);
+
+    let mut comps = access.component_mut::<Comp1>();
+    let mut iter = comps.iter_mut_with_ids();
+    assert_eq!(iter.len(), 6);
+    iter.next();
+    assert_eq!(iter.len(), 5);
+    for (entity, comp) in comps.iter_mut_with_ids() {
+        comp.a += expected[&entity];
+    }
+    drop(comps);
+
+    for (entity, a) in &expected {
+        assert_eq!(access.component::<Comp1>().get(entity).unwrap().a, a * 2);
+    }
+}
+
+#[test]
+fn iter_of_archetype() {
+    let mut storage = EntityStorage::new();
+    assert_eq!(storage.iter_of::<Archetype1>().count(), 0);
+    assert_eq!(storage.iter_of_mut::<Archetype1>().count(), 0);
+
+    let dogs: Vec<_> = (0..3)
+        .map(|i| {
+            storage.add(Archetype1 {
+                comp1: Comp1 { a: i, b: [0; 4] },
+            })
+        })
+        .collect();
+    storage.add(Archetype12 {
+        comp1: Comp1::new(),
+        comp2: Comp2::new(),
+    });
+    storage.add(Archetype2(Comp2::new()));
+    storage.remove(&dogs[1]);
+
+    let visited: Vec<_> = storage
+        .iter_of::<Archetype1>()
+        .map(|(e, state)| (e, state.comp1.a))
+        .collect();
+    assert_eq!(visited, [(dogs[0], 0), (dogs[2], 2)]);
+
+    for (entity, state) in storage.iter_of_mut::<Archetype1>() {
+        state.comp1.b[0] = entity.id;
+    }
+    assert_eq!(storage.get::<Comp1>(&dogs[2]).unwrap().b[0], dogs[2].id);
+    assert_eq!(storage.iter_of::<Archetype2>().count(), 1);
+
+    // Shares the storage with `Archetype1`
+    #[derive(Archetype)]
+    struct Cat {
+        comp1: Comp1,
+    }
+    let cat = storage.add(Cat {
+        comp1: Comp1 { a: 7, b: [0; 4] },
+    });
+    assert_eq!(cat.archetype_id, dogs[0].archetype_id);
+    assert_eq!(cat.id, dogs[1].id);
+
+    // An entity with the same components added at runtime
+    let meta = DynamicArchetypeBuilder::new().register::<Comp1>().build();
+    let comp1 = Comp1 { a: 8, b: [0; 4] };
+    let raw = unsafe { storage.add_raw(meta, &comp1 as *const Comp1 as *const u8) };
+    assert_eq!(raw.archetype_id, dogs[0].archetype_id);
+
+    let cats: Vec<_> = storage
+        .iter_of::<Cat>()
+        .map(|(e, state)| (e, state.comp1.a))
+        .collect();
+    assert_eq!(cats, [(cat, 7)]);
+    let visited: Vec<_> = storage.iter_of::<Archetype1>().map(|(e, _)| e).collect();
+    assert_eq!(visited, [dogs[0], dogs[2]]);
+    assert_eq!(storage.iter_of_mut::<Cat>().count(), 1);
+    assert_eq!(storage.iter_of_mut::<Archetype1>().count(), 2);
+    assert_eq!(storage.single::<Cat>().unwrap().0, cat);
+    assert_eq!(storage.single_mut::<Cat>().unwrap().1.comp1.a, 7);
+    assert_eq!(storage.iter_components::<Comp1>().count(), 5);
+
+    assert_eq!(storage.get_state::<Cat>(&cat).unwrap().comp1.a, 7);
+    assert!(storage.get_state::<Archetype1>(&cat).is_none());
+    assert!(storage.get_state::<Archetype1>(&raw).is_none());
+    assert!(storage.get_state::<Cat>(&dogs[0]).is_none());
+
+    let mut n_visited = 0;
+    storage.for_each_state_mut::<Cat, _>(|e, state| {
+        assert_eq!(e, cat);
+        state.comp1.a += 1;
+        n_visited += 1;
+    });
+    assert_eq!(n_visited, 1);
+    assert_eq!(storage.get::<Comp1>(&cat).unwrap().a, 8);
+
+    // A reused slot takes the type of the new state
+    storage.remove(&cat);
+    let dog = storage.add(Archetype1 {
+        comp1: Comp1 { a: 1, b: [0; 4] },
+    });
+    assert_eq!(dog, cat);
+    assert_eq!(storage.iter_of::<Cat>().count(), 0);
+    assert_eq!(storage.iter_of::<Archetype1>().count(), 3);
+}
+
+#[test]
+fn packed_dynamic_archetype_is_stored_aligned() {
+    let meta = DynamicArchetypeBuilder::new()
+        .register::<u8>()
+        .register::<String>()
+        .build();
+    let infos = meta.component_infos();
+    assert_eq!(infos[1].range.start, 1);
+
+    let mut state = vec![0_u8; meta.size];
+    unsafe {
+        let ptr = state.as_mut_ptr();
+        ptr.add(infos[0].range.start).write(7);
+        (ptr.add(infos[1].range.start) as *mut String).write_unaligned("packed".to_string());
+    }
+
+    let mut storage = EntityStorage::new();
+    let entity = unsafe { storage.add_raw(meta, state.as_ptr()) };
+
+    let s = storage.get::<String>(&entity).unwrap();
+    assert_eq!(s as *const String as usize % mem::align_of::<String>(), 0);
+    assert_eq!(s, "packed");
+    assert_eq!(storage.get::<u8>(&entity), Some(&7));
+
+    storage.get_mut::<String>(&entity).unwrap().push('!');
+    assert_eq!(storage.get::<String>(&entity).unwrap(), "packed!");
+
+    // Drops the string through the storage's own (aligned) layout
+    assert!(storage.remove(&entity));
+}
+
+#[test]
+fn transform_entity_panic_drops_sparse_components() {
+    #[derive(Debug, PartialEq)]
+    struct Tag(u32);
+
+    let mut storage = EntityStorage::new();
+    storage.register_sparse::<Tag>();
+    let e = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    storage.insert_sparse(&e, Tag(1)).unwrap();
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        storage.transform_entity(&e, |_: Archetype1| -> Archetype12 { panic!() });
+    }));
+    assert!(result.is_err());
+    assert!(!storage.contains(&e));
+
+    // The slot is reused with the same id
+    let reused = storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    assert_eq!(reused, e);
+    assert_eq!(storage.get::<Tag>(&reused), None);
+    assert_eq!(storage.iter_sparse::<Tag>().count(), 0);
+}
+
+#[test]
+fn join_components() {
+    #[derive(Clone, Archetype)]
+    struct Archetype23(Comp2, Comp3);
+    #[derive(Clone, Archetype)]
+    struct Archetype13 {
+        comp1: Comp1,
+        comp3: Comp3,
+    }
+
+    let mut storage = EntityStorage::new();
+    let e0 = storage.add(Archetype13 {
+        comp1: Comp1::new(),
+        comp3: Comp3,
+    });
+    storage.add(Archetype1 {
+        comp1: Comp1::new(),
+    });
+    storage.add(Archetype3(Comp3));
+    storage.add(Archetype23(Comp2::new(), Comp3));
+    let e1 = storage.add(Archetype13 {
+        comp1: Comp1::new(),
+        comp3: Comp3,
+    });
+
+    let access = storage.access();
+    let comps1 = access.component::<Comp1>();
+    let mut comps3 = access.component_mut::<Comp3>();
+    let joined: Vec<_> = comps1.join(&mut comps3).map(|(e, ..)| e).collect();
+    assert_eq!(joined, [e0, e1]);
+}
+
+#[test]
+#[should_panic(expected = "components of different storages can't be joined")]
+fn join_components_of_different_storages() {
+    let mut storage1 = EntityStorage::new();
+    let mut storage2 = EntityStorage::new();
+    for storage in [&mut storage1, &mut storage2] {
+        storage.add(Archetype12 {
+            comp1: Comp1::new(),
+            comp2: Comp2::new(),
+        });
+    }
+
+    let access1 = storage1.access();
+    let access2 = storage2.access();
+    let comps1 = access1.component::<Comp1>();
+    let mut comps2 = access2.component_mut::<Comp2>();
+    let _ = comps1.join(&mut comps2);
+}